@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS mails_mailbox_id_maildir_id;
+DROP INDEX IF EXISTS mails_mailbox_id_uid;
+ALTER TABLE mails DROP COLUMN uid;
+ALTER TABLE mails DROP COLUMN mailbox_id;
+DROP TABLE mailboxes;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS mailboxes (
+    id BIGSERIAL NOT NULL PRIMARY KEY UNIQUE,
+    path TEXT NOT NULL UNIQUE,
+    uid_validity BIGINT NOT NULL,
+    uid_next BIGINT NOT NULL DEFAULT 1
+);
+
+-- The old uids were global. Mails get a new uid in their mailbox the next time it gets listed.
+DELETE FROM mails;
+ALTER TABLE mails ADD COLUMN mailbox_id BIGINT NOT NULL REFERENCES mailboxes(id) ON DELETE CASCADE;
+ALTER TABLE mails ADD COLUMN uid BIGINT NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS mails_mailbox_id_uid ON mails (mailbox_id, uid);
+CREATE UNIQUE INDEX IF NOT EXISTS mails_mailbox_id_maildir_id ON mails (mailbox_id, maildir_id);
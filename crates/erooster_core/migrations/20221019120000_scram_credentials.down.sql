@@ -0,0 +1 @@
+ALTER TABLE users DROP COLUMN scram_sha256;
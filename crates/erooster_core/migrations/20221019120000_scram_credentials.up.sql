@@ -0,0 +1,2 @@
+-- Filled in when the password of a user gets changed or the user logs in with it
+ALTER TABLE users ADD COLUMN scram_sha256 TEXT;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS expunged_mails_mailbox_id_uid;
+DROP TABLE expunged_mails;
+ALTER TABLE mails DROP COLUMN flags;
+ALTER TABLE mails DROP COLUMN modseq;
+ALTER TABLE mailboxes DROP COLUMN highest_modseq;
@@ -0,0 +1,10 @@
+ALTER TABLE mailboxes ADD COLUMN highest_modseq BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE mails ADD COLUMN modseq BIGINT NOT NULL DEFAULT 1;
+-- The maildir flags the modseq belongs to. They get filled in the next time the mailbox gets listed.
+ALTER TABLE mails ADD COLUMN flags TEXT;
+CREATE TABLE IF NOT EXISTS expunged_mails (
+    mailbox_id BIGINT NOT NULL REFERENCES mailboxes(id) ON DELETE CASCADE,
+    uid BIGINT NOT NULL,
+    modseq BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS expunged_mails_mailbox_id_uid ON expunged_mails (mailbox_id, uid);
@@ -0,0 +1,2 @@
+ALTER TABLE users DROP COLUMN quota_messages;
+ALTER TABLE users DROP COLUMN quota_storage;
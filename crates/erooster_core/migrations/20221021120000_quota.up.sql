@@ -0,0 +1,3 @@
+-- NULL means the resource is not limited. The storage limit is in units of 1024 octets.
+ALTER TABLE users ADD COLUMN quota_storage BIGINT;
+ALTER TABLE users ADD COLUMN quota_messages BIGINT;
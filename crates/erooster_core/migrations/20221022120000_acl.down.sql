@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS acl_identifier;
+DROP TABLE acl;
@@ -0,0 +1,9 @@
+-- The mailbox is the on-disk folder name of the mailbox below the directory of the owner
+CREATE TABLE IF NOT EXISTS acl (
+    owner TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    identifier TEXT NOT NULL,
+    rights TEXT NOT NULL,
+    PRIMARY KEY (owner, mailbox, identifier)
+);
+CREATE INDEX IF NOT EXISTS acl_identifier ON acl (identifier);
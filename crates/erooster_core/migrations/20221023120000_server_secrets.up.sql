@@ -0,0 +1,5 @@
+-- Random secrets of the installation which are created on startup
+CREATE TABLE IF NOT EXISTS server_secrets (
+    name TEXT PRIMARY KEY,
+    secret BYTEA NOT NULL
+);
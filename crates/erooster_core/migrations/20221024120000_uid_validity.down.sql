@@ -0,0 +1,2 @@
+ALTER TABLE mailboxes ALTER COLUMN uid_validity DROP DEFAULT;
+DROP SEQUENCE mailbox_uid_validity;
@@ -0,0 +1,11 @@
+-- UIDVALIDITY has to grow even if a mailbox gets deleted and created again within one second.
+-- The sequence starts at the current time so it stays above the values of existing mailboxes.
+CREATE SEQUENCE IF NOT EXISTS mailbox_uid_validity MAXVALUE 4294967295;
+SELECT setval(
+    'mailbox_uid_validity',
+    GREATEST(
+        EXTRACT(EPOCH FROM now())::BIGINT,
+        (SELECT COALESCE(MAX(uid_validity), 0) + 1 FROM mailboxes)
+    )
+);
+ALTER TABLE mailboxes ALTER COLUMN uid_validity SET DEFAULT nextval('mailbox_uid_validity');
@@ -0,0 +1,2 @@
+-- Set once the default folders of the user got created on their first login
+ALTER TABLE users ADD COLUMN provisioned BOOLEAN NOT NULL DEFAULT FALSE;
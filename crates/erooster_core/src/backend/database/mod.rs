@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::{backend::storage::QuotaUsage, config::Config, scram::ScramCredentials};
 use color_eyre::Result;
 use secrecy::SecretString;
 use sqlx::Pool;
@@ -25,6 +25,16 @@ pub trait Database<S: sqlx::Database> {
     /// Checks if the user and password are correct
     async fn verify_user(&self, username: &str, password: SecretString) -> bool;
 
+    /// Returns the SCRAM-SHA-256 credentials of the user if they were stored already
+    ///
+    /// They get stored when the password changes or the user logs in with it.
+    async fn get_scram_credentials(&self, username: &str) -> Option<ScramCredentials>;
+
+    /// Returns credentials nobody can log in with for users without SCRAM-SHA-256 credentials
+    ///
+    /// They are the same on every attempt so they don't give away which users exist.
+    fn fake_scram_credentials(&self, username: &str) -> ScramCredentials;
+
     /// Checks if the user exists
     async fn user_exists(&self, username: &str) -> bool;
 
@@ -37,6 +47,80 @@ pub trait Database<S: sqlx::Database> {
 
     /// Adds a new user without password
     async fn add_user(&self, username: &str) -> color_eyre::eyre::Result<()>;
+
+    /// Returns the quota of the user. Users without a quota are not limited.
+    async fn get_quota(&self, username: &str) -> color_eyre::eyre::Result<Quota>;
+
+    /// Replaces the quota of the user
+    async fn set_quota(&self, username: &str, quota: Quota) -> color_eyre::eyre::Result<()>;
+
+    /// Checks if the default folders of the user were created already
+    async fn is_provisioned(&self, username: &str) -> color_eyre::eyre::Result<bool>;
+
+    /// Remembers that the default folders of the user were created
+    async fn set_provisioned(&self, username: &str) -> color_eyre::eyre::Result<()>;
+
+    /// Returns the identifiers and their rights on the mailbox of the owner
+    async fn get_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+    ) -> color_eyre::eyre::Result<Vec<(String, String)>>;
+
+    /// Replaces the rights of the identifier on the mailbox of the owner
+    async fn set_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+        identifier: &str,
+        rights: &str,
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Removes the rights of the identifier or of everyone if no identifier is given
+    async fn delete_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+        identifier: Option<&str>,
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Moves the rights along with a renamed mailbox and its children
+    async fn rename_acl(&self, owner: &str, from: &str, to: &str) -> color_eyre::eyre::Result<()>;
+
+    /// Returns the owners and names of the mailboxes the user may see through an ACL
+    async fn get_shared_mailboxes(
+        &self,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<Vec<(String, String)>>;
+}
+
+/// Brings a username into the form it is stored with
+///
+/// Usernames are case insensitive so the database and the maildir folders use the lowercase form.
+#[must_use]
+pub fn normalize_username(username: &str) -> String {
+    username.to_lowercase()
+}
+
+/// The storage and message limits of a user. Resources which are `None` are not limited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    /// The limit of the size of all messages in units of 1024 octets
+    pub storage: Option<u64>,
+    /// The limit of the number of messages
+    pub messages: Option<u64>,
+}
+
+impl Quota {
+    /// Checks if messages with the given total size in octets still fit next to the current usage
+    #[must_use]
+    pub fn allows(&self, usage: &QuotaUsage, size: u64, messages: u64) -> bool {
+        self.storage.map_or(true, |limit| {
+            usage.storage + size <= limit.saturating_mul(1024)
+        }) && self
+            .messages
+            .map_or(true, |limit| usage.messages + messages <= limit)
+    }
 }
 
 /// Get a postgres database connection pool and the higher level wrapper
@@ -1,7 +1,11 @@
-use crate::{backend::database::Database, config::Config};
+use crate::{
+    backend::database::{Database, Quota},
+    config::Config,
+    scram::ScramCredentials,
+};
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use color_eyre::Result;
-use rand_core::OsRng;
+use rand_core::{OsRng, RngCore};
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::PgPool;
 use std::sync::Arc;
@@ -11,6 +15,30 @@ use tracing::{debug, debug_span, error, instrument};
 /// Holds data to connect to the database
 pub struct Postgres {
     pool: PgPool,
+    /// Used to derive the SCRAM-SHA-256 credentials of users without any
+    scram_secret: Vec<u8>,
+}
+
+/// Returns the secret with the given name and creates it on first use
+///
+/// It has to stay the same across restarts to not give away which users exist.
+#[instrument(skip(pool))]
+async fn server_secret(pool: &PgPool, name: &str) -> Result<Vec<u8>> {
+    let mut secret = vec![0; 32];
+    OsRng.fill_bytes(&mut secret);
+    // Another instance might have created it at the same time
+    sqlx::query(
+        "INSERT INTO server_secrets (name, secret) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING",
+    )
+    .bind(name)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+    let (secret,): (Vec<u8>,) = sqlx::query_as("SELECT secret FROM server_secrets WHERE name = $1")
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
+    Ok(secret)
 }
 
 #[async_trait::async_trait]
@@ -21,7 +49,8 @@ impl Database<sqlx::Postgres> for Postgres {
             .expect("Failed to connect to postgres");
 
         sqlx::migrate!().run(&pool).await?;
-        Ok(Self { pool })
+        let scram_secret = server_secret(&pool, "scram").await?;
+        Ok(Self { pool, scram_secret })
     }
 
     #[instrument(skip(self))]
@@ -31,17 +60,16 @@ impl Database<sqlx::Postgres> for Postgres {
 
     #[instrument(skip(self, username, password))]
     async fn verify_user(&self, username: &str, password: SecretString) -> bool {
-        let hash: std::result::Result<(String,), sqlx::Error> =
-            sqlx::query_as("SELECT hash FROM users WHERE username = $1")
+        let hash: std::result::Result<(String, Option<String>), sqlx::Error> =
+            sqlx::query_as("SELECT hash, scram_sha256 FROM users WHERE username = $1")
                 .bind(username)
                 .fetch_one(self.get_pool())
                 .await;
 
         match hash {
-            Ok(hash) => {
-                let hash = hash.0;
+            Ok((hash, scram_credentials)) => {
                 let span = debug_span!("verify_user::aron2::PasswordHash");
-                span.in_scope(|| match PasswordHash::new(&hash) {
+                let valid = span.in_scope(|| match PasswordHash::new(&hash) {
                     Ok(parsed_hash) => {
                         let span = debug_span!("verify_user::aron2::verify_password");
                         span.in_scope(|| {
@@ -54,7 +82,22 @@ impl Database<sqlx::Postgres> for Postgres {
                         error!("[DB] Error verifying user: {}", e);
                         false
                     }
-                })
+                });
+                // Passwords which were set before SCRAM-SHA-256 got added are only known at this point
+                if valid && scram_credentials.is_none() {
+                    let scram_credentials = ScramCredentials::new(password.expose_secret());
+                    if let Err(e) = sqlx::query(
+                        "UPDATE users SET scram_sha256 = $1 WHERE username = $2 AND scram_sha256 IS NULL",
+                    )
+                    .bind(scram_credentials.to_string())
+                    .bind(username)
+                    .execute(self.get_pool())
+                    .await
+                    {
+                        error!("[DB] Error saving SCRAM credentials: {}", e);
+                    }
+                }
+                valid
             }
             Err(e) => {
                 error!("[DB] Error verifying user: {}", e);
@@ -74,8 +117,10 @@ impl Database<sqlx::Postgres> for Postgres {
         let password_hash = argon2
             .hash_password(password.expose_secret().as_bytes(), &salt)?
             .to_string();
-        sqlx::query("UPDATE users SET hash = $1 WHERE username = $2")
+        let scram_credentials = ScramCredentials::new(password.expose_secret());
+        sqlx::query("UPDATE users SET hash = $1, scram_sha256 = $2 WHERE username = $3")
             .bind(password_hash)
+            .bind(scram_credentials.to_string())
             .bind(username)
             .execute(self.get_pool())
             .await?;
@@ -91,6 +136,177 @@ impl Database<sqlx::Postgres> for Postgres {
         Ok(())
     }
 
+    #[instrument(skip(self, username))]
+    async fn get_quota(&self, username: &str) -> color_eyre::eyre::Result<Quota> {
+        let quota: std::result::Result<(Option<i64>, Option<i64>), sqlx::Error> =
+            sqlx::query_as("SELECT quota_storage, quota_messages FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_one(self.get_pool())
+                .await;
+        match quota {
+            Ok((storage, messages)) => Ok(Quota {
+                storage: storage.map(u64::try_from).transpose()?,
+                messages: messages.map(u64::try_from).transpose()?,
+            }),
+            // Users which only exist through an identity provider have no quota
+            Err(sqlx::Error::RowNotFound) => Ok(Quota::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[instrument(skip(self, username))]
+    async fn set_quota(&self, username: &str, quota: Quota) -> color_eyre::eyre::Result<()> {
+        let result = sqlx::query(
+            "UPDATE users SET quota_storage = $1, quota_messages = $2 WHERE username = $3",
+        )
+        .bind(quota.storage.map(i64::try_from).transpose()?)
+        .bind(quota.messages.map(i64::try_from).transpose()?)
+        .bind(username)
+        .execute(self.get_pool())
+        .await?;
+        if result.rows_affected() == 0 {
+            color_eyre::eyre::bail!("No such user");
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn is_provisioned(&self, username: &str) -> color_eyre::eyre::Result<bool> {
+        let provisioned: Option<(bool,)> =
+            sqlx::query_as("SELECT provisioned FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_optional(self.get_pool())
+                .await?;
+        Ok(provisioned.map_or(false, |(provisioned,)| provisioned))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_provisioned(&self, username: &str) -> color_eyre::eyre::Result<()> {
+        sqlx::query("UPDATE users SET provisioned = TRUE WHERE username = $1")
+            .bind(username)
+            .execute(self.get_pool())
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+    ) -> color_eyre::eyre::Result<Vec<(String, String)>> {
+        let acl = sqlx::query_as(
+            "SELECT identifier, rights FROM acl WHERE owner = $1 AND mailbox = $2 ORDER BY identifier",
+        )
+        .bind(owner)
+        .bind(mailbox)
+        .fetch_all(self.get_pool())
+        .await?;
+        Ok(acl)
+    }
+
+    #[instrument(skip(self))]
+    async fn set_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+        identifier: &str,
+        rights: &str,
+    ) -> color_eyre::eyre::Result<()> {
+        if rights.is_empty() {
+            return self.delete_acl(owner, mailbox, Some(identifier)).await;
+        }
+        sqlx::query(
+            "INSERT INTO acl (owner, mailbox, identifier, rights) VALUES ($1, $2, $3, $4) ON CONFLICT (owner, mailbox, identifier) DO UPDATE SET rights = $4",
+        )
+        .bind(owner)
+        .bind(mailbox)
+        .bind(identifier)
+        .bind(rights)
+        .execute(self.get_pool())
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_acl(
+        &self,
+        owner: &str,
+        mailbox: &str,
+        identifier: Option<&str>,
+    ) -> color_eyre::eyre::Result<()> {
+        sqlx::query(
+            "DELETE FROM acl WHERE owner = $1 AND mailbox = $2 AND ($3::TEXT IS NULL OR identifier = $3)",
+        )
+        .bind(owner)
+        .bind(mailbox)
+        .bind(identifier)
+        .execute(self.get_pool())
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn rename_acl(&self, owner: &str, from: &str, to: &str) -> color_eyre::eyre::Result<()> {
+        // Children are stored as `<mailbox>.<child>` so their rights move as well
+        sqlx::query(
+            "UPDATE acl SET mailbox = $3 || substr(mailbox, length($2) + 1) WHERE owner = $1 AND (mailbox = $2 OR left(mailbox, length($2) + 1) = $2 || '.')",
+        )
+        .bind(owner)
+        .bind(from)
+        .bind(to)
+        .execute(self.get_pool())
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_shared_mailboxes(
+        &self,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<Vec<(String, String)>> {
+        // Mailboxes are only visible with the lookup right
+        let mailboxes = sqlx::query_as(
+            "SELECT DISTINCT owner, mailbox FROM acl WHERE identifier IN ($1, 'anyone') AND owner <> $1 AND rights LIKE '%l%' ORDER BY owner, mailbox",
+        )
+        .bind(identifier)
+        .fetch_all(self.get_pool())
+        .await?;
+        Ok(mailboxes)
+    }
+
+    #[instrument(skip(self, username))]
+    async fn get_scram_credentials(&self, username: &str) -> Option<ScramCredentials> {
+        let credentials: std::result::Result<(Option<String>,), sqlx::Error> =
+            sqlx::query_as("SELECT scram_sha256 FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_one(self.get_pool())
+                .await;
+
+        match credentials {
+            Ok((Some(credentials),)) => match credentials.parse() {
+                Ok(credentials) => Some(credentials),
+                Err(e) => {
+                    error!("[DB] Error parsing SCRAM credentials: {}", e);
+                    None
+                }
+            },
+            // The password wasn't changed since SCRAM got added
+            Ok((None,)) => None,
+            Err(e) => {
+                if !matches!(e, sqlx::Error::RowNotFound) {
+                    error!("[DB] Error getting SCRAM credentials: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    #[instrument(skip(self, username))]
+    fn fake_scram_credentials(&self, username: &str) -> ScramCredentials {
+        ScramCredentials::fake(&self.scram_secret, username)
+    }
+
     #[instrument(skip(self, username))]
     async fn user_exists(&self, username: &str) -> bool {
         let exists = sqlx::query("SELECT 1 FROM users WHERE username = $1")
@@ -1,7 +1,7 @@
 use crate::{
     backend::{
         database::{Database, DB},
-        storage::{MailEntry, MailStorage},
+        storage::{shared_mailbox, MailEntry, MailStorage, QuotaUsage},
     },
     config::Config,
 };
@@ -9,6 +9,7 @@ use futures::{StreamExt, TryStreamExt};
 use maildir::Maildir;
 use mailparse::ParsedMail;
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -16,7 +17,7 @@ use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 use tokio_stream::wrappers::LinesStream;
-use tracing::{debug, instrument};
+use tracing::{debug, error, instrument};
 
 /// The Storage handler for the maildir format
 pub struct MaildirStorage {
@@ -31,32 +32,230 @@ impl MaildirStorage {
     pub fn new(db: DB, config: Arc<Config>) -> Self {
         MaildirStorage { db, config }
     }
+
+    /// Gets the database row of the mailbox and creates it if it doesn't exist yet
+    #[instrument(skip(self, path))]
+    async fn mailbox(&self, path: &Path) -> color_eyre::eyre::Result<DbMailbox> {
+        let path = path.to_string_lossy().to_string();
+        let select =
+            "SELECT id, uid_validity, uid_next, highest_modseq FROM mailboxes WHERE path = $1";
+        if let Some(mailbox) = sqlx::query_as::<_, DbMailbox>(select)
+            .bind(&path)
+            .fetch_optional(self.db.get_pool())
+            .await?
+        {
+            return Ok(mailbox);
+        }
+        // The uid validity comes from a sequence so a recreated mailbox never gets the old one
+        sqlx::query("INSERT INTO mailboxes (path) VALUES ($1) ON CONFLICT (path) DO NOTHING")
+            .bind(&path)
+            .execute(self.db.get_pool())
+            .await?;
+        let mailbox = sqlx::query_as::<_, DbMailbox>(select)
+            .bind(&path)
+            .fetch_one(self.db.get_pool())
+            .await?;
+        Ok(mailbox)
+    }
+
+    /// Assigns the next uid and modseq of the mailbox to the mail.
+    ///
+    /// Without flags they get recorded the next time the mailbox gets listed.
+    /// If the mail got a uid in the meantime (e.g. by a concurrent listing) that one is returned.
+    #[instrument(skip(self))]
+    async fn assign_uid(
+        &self,
+        mailbox_id: i64,
+        maildir_id: &str,
+        flags: Option<&str>,
+    ) -> color_eyre::eyre::Result<(i64, i64)> {
+        let uid_and_modseq = sqlx::query_as(
+            "WITH next AS (
+                UPDATE mailboxes SET uid_next = uid_next + 1, highest_modseq = highest_modseq + 1
+                WHERE id = $1 AND NOT EXISTS (
+                    SELECT 1 FROM mails WHERE mailbox_id = $1 AND maildir_id = $2
+                )
+                RETURNING uid_next - 1 AS uid, highest_modseq
+            )
+            INSERT INTO mails (mailbox_id, maildir_id, uid, modseq, flags)
+            SELECT $1, $2, uid, highest_modseq, $3 FROM next
+            ON CONFLICT (mailbox_id, maildir_id) DO NOTHING
+            RETURNING uid, modseq",
+        )
+        .bind(mailbox_id)
+        .bind(maildir_id)
+        .bind(flags)
+        .fetch_optional(self.db.get_pool())
+        .await?;
+        if let Some(uid_and_modseq) = uid_and_modseq {
+            return Ok(uid_and_modseq);
+        }
+        let uid_and_modseq = sqlx::query_as(
+            "SELECT uid, modseq FROM mails WHERE mailbox_id = $1 AND maildir_id = $2",
+        )
+        .bind(mailbox_id)
+        .bind(maildir_id)
+        .fetch_one(self.db.get_pool())
+        .await?;
+        Ok(uid_and_modseq)
+    }
+
+    /// Records the current flags of the mail and returns its modseq.
+    ///
+    /// Flags which differ from the recorded ones give the mail the next modseq of the mailbox.
+    /// This also catches changes which were made to the maildir by other programs.
+    #[instrument(skip(self, known))]
+    async fn record_flags(
+        &self,
+        mailbox_id: i64,
+        known: &DbMails,
+        flags: &str,
+    ) -> color_eyre::eyre::Result<i64> {
+        let query = if known.flags.is_some() {
+            "WITH next AS (
+                UPDATE mailboxes SET highest_modseq = highest_modseq + 1 WHERE id = $1
+                RETURNING highest_modseq
+            )
+            UPDATE mails SET flags = $3, modseq = next.highest_modseq FROM next
+            WHERE mailbox_id = $1 AND maildir_id = $2 RETURNING mails.modseq"
+        } else {
+            // Mails from before modseqs existed keep their initial one
+            "UPDATE mails SET flags = $3 WHERE mailbox_id = $1 AND maildir_id = $2 RETURNING modseq"
+        };
+        let (modseq,): (i64,) = sqlx::query_as(query)
+            .bind(mailbox_id)
+            .bind(&known.maildir_id)
+            .bind(flags)
+            .fetch_one(self.db.get_pool())
+            .await?;
+        Ok(modseq)
+    }
+
+    /// Removes the uid of the mail and remembers it for the VANISHED responses of QRESYNC
+    #[instrument(skip(self))]
+    async fn expunge_uid(&self, mailbox_id: i64, maildir_id: &str) -> color_eyre::eyre::Result<()> {
+        sqlx::query(
+            "WITH next AS (
+                UPDATE mailboxes SET highest_modseq = highest_modseq + 1 WHERE id = $1
+                RETURNING highest_modseq
+            ), removed AS (
+                DELETE FROM mails WHERE mailbox_id = $1 AND maildir_id = $2 RETURNING uid
+            )
+            INSERT INTO expunged_mails (mailbox_id, uid, modseq)
+            SELECT $1, removed.uid, next.highest_modseq FROM removed, next
+            ON CONFLICT (mailbox_id, uid) DO UPDATE SET modseq = EXCLUDED.modseq",
+        )
+        .bind(mailbox_id)
+        .bind(maildir_id)
+        .execute(self.db.get_pool())
+        .await?;
+        Ok(())
+    }
+
+    /// Looks up the uids of the mails and assigns new ones to mails we haven't seen yet
+    #[instrument(skip(self, path, entries))]
+    async fn with_uids(
+        &self,
+        path: &Path,
+        entries: Vec<maildir::MailEntry>,
+    ) -> Vec<MaildirMailEntry> {
+        let mailbox = match self.mailbox(path).await {
+            Ok(mailbox) => mailbox,
+            Err(e) => {
+                error!("Failed to load the mailbox {:?}: {}", path, e);
+                return vec![];
+            }
+        };
+        let ids: Vec<String> = entries.iter().map(|entry| entry.id().to_string()).collect();
+        let known_mails: HashMap<String, DbMails> = sqlx::query_as::<_, DbMails>(
+            "SELECT maildir_id, uid, modseq, flags FROM mails
+            WHERE mailbox_id = $1 AND maildir_id = ANY($2)",
+        )
+        .bind(mailbox.id)
+        .bind(ids)
+        .fetch(self.db.get_pool())
+        .filter_map(|x| async move { x.ok() })
+        .map(|x| (x.maildir_id.clone(), x))
+        .collect()
+        .await;
+
+        let mut mails = Vec::with_capacity(entries.len());
+        let mut unknown_entries = Vec::new();
+        for entry in entries {
+            if let Some(known) = known_mails.get(entry.id()) {
+                let modseq = if known.flags.as_deref() == Some(entry.flags()) {
+                    known.modseq
+                } else {
+                    match self.record_flags(mailbox.id, known, entry.flags()).await {
+                        Ok(modseq) => modseq,
+                        Err(e) => {
+                            error!("Failed to record the flags of {}: {}", entry.id(), e);
+                            known.modseq
+                        }
+                    }
+                };
+                mails.push(MaildirMailEntry {
+                    uid: known.uid,
+                    modseq,
+                    entry,
+                });
+            } else {
+                unknown_entries.push(entry);
+            }
+        }
+        // Maildir ids start with the time of delivery so the uids follow the delivery order
+        unknown_entries.sort_by(|a, b| a.id().cmp(b.id()));
+        for entry in unknown_entries {
+            match self
+                .assign_uid(mailbox.id, entry.id(), Some(entry.flags()))
+                .await
+            {
+                Ok((uid, modseq)) => mails.push(MaildirMailEntry { uid, modseq, entry }),
+                Err(e) => error!("Failed to assign a uid to {}: {}", entry.id(), e),
+            }
+        }
+        mails
+    }
 }
 
 #[async_trait::async_trait]
 impl MailStorage<MaildirMailEntry> for MaildirStorage {
-    #[instrument(skip(self, mailbox_path))]
-    fn get_uid_for_folder(&self, mailbox_path: &Path) -> color_eyre::eyre::Result<u32> {
-        let maildir = Maildir::from(mailbox_path.to_path_buf());
-        let current_last_id: u32 = maildir.count_cur().try_into()?;
-        let new_last_id: u32 = maildir.count_new().try_into()?;
-        Ok(current_last_id + new_last_id)
+    #[instrument(skip(self, path))]
+    async fn get_uid_next(&self, path: &Path) -> color_eyre::eyre::Result<u32> {
+        let mailbox = self.mailbox(path).await?;
+        Ok(u32::try_from(mailbox.uid_next)?)
+    }
+
+    #[instrument(skip(self, path))]
+    async fn get_highest_modseq(&self, path: &Path) -> color_eyre::eyre::Result<i64> {
+        let mailbox = self.mailbox(path).await?;
+        Ok(mailbox.highest_modseq)
+    }
+
+    #[instrument(skip(self, path))]
+    async fn get_vanished(&self, path: &Path, modseq: i64) -> color_eyre::eyre::Result<Vec<i64>> {
+        let mailbox = self.mailbox(path).await?;
+        let uids = sqlx::query_as::<_, (i64,)>(
+            "SELECT uid FROM expunged_mails WHERE mailbox_id = $1 AND modseq > $2 ORDER BY uid",
+        )
+        .bind(mailbox.id)
+        .bind(modseq)
+        .fetch_all(self.db.get_pool())
+        .await?;
+        Ok(uids.into_iter().map(|(uid,)| uid).collect())
     }
 
+    #[instrument(skip(self, path))]
     async fn find(&self, path: &Path, id: &str) -> Option<MaildirMailEntry> {
         let maildir = Maildir::from(path.to_path_buf());
-        let mail_rows: Vec<DbMails> = sqlx::query_as::<_, DbMails>("SELECT * FROM mails")
-            .fetch(self.db.get_pool())
-            .filter_map(|x| async move { x.ok() })
-            .collect()
-            .await;
-        maildir.find(id).map(|entry| MaildirMailEntry {
-            entry,
-            uid: mail_rows
-                .iter()
-                .find(|x| x.maildir_id == id)
-                .map_or(0, |x| x.id),
-        })
+        let entry = maildir.find(id)?;
+        self.with_uids(path, vec![entry]).await.pop()
+    }
+
+    #[instrument(skip(self, path))]
+    async fn get_uid_validity(&self, path: &Path) -> color_eyre::eyre::Result<u32> {
+        let mailbox = self.mailbox(path).await?;
+        Ok(u32::try_from(mailbox.uid_validity)?)
     }
 
     #[instrument(skip(self, path))]
@@ -79,7 +278,7 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
             .create(true)
             .open(flags_file)
             .await?;
-        file.write_all(flag.as_bytes()).await?;
+        file.write_all(format!("{}\n", flag).as_bytes()).await?;
         Ok(())
     }
 
@@ -93,6 +292,7 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
         let mut file = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(flags_file)
             .await?;
 
@@ -139,10 +339,8 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
             .collect::<Vec<_>>()
             .join("");
         let maildir_id = maildir.store_cur_with_flags(data, &maildir_flags)?;
-        sqlx::query("INSERT INTO mails (maildir_id) VALUES ($1)")
-            .bind(maildir_id.clone())
-            .execute(self.db.get_pool())
-            .await?;
+        let mailbox = self.mailbox(path).await?;
+        self.assign_uid(mailbox.id, &maildir_id, None).await?;
         Ok(maildir_id)
     }
 
@@ -150,13 +348,122 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
     async fn store_new(&self, path: &Path, data: &[u8]) -> color_eyre::eyre::Result<String> {
         let maildir = Maildir::from(path.to_path_buf());
         let maildir_id = maildir.store_new(data)?;
-        sqlx::query("INSERT INTO mails (maildir_id) VALUES ($1)")
-            .bind(maildir_id.clone())
-            .execute(self.db.get_pool())
+        let mailbox = self.mailbox(path).await?;
+        self.assign_uid(mailbox.id, &maildir_id, None).await?;
+        Ok(maildir_id)
+    }
+
+    #[instrument(skip(self, path, target_path))]
+    async fn copy_to(
+        &self,
+        path: &Path,
+        id: &str,
+        target_path: &Path,
+    ) -> color_eyre::eyre::Result<String> {
+        let maildir = Maildir::from(path.to_path_buf());
+        let entry = maildir
+            .find(id)
+            .ok_or_else(|| color_eyre::eyre::eyre!("Unable to find mail {}", id))?;
+        let data = tokio::fs::read(entry.path()).await?;
+        // A copy gets a new maildir id so it also gets a new uid
+        let target_maildir = Maildir::from(target_path.to_path_buf());
+        let maildir_id = target_maildir.store_cur_with_flags(&data, entry.flags())?;
+        let target_mailbox = self.mailbox(target_path).await?;
+        self.assign_uid(target_mailbox.id, &maildir_id, None)
             .await?;
         Ok(maildir_id)
     }
 
+    #[instrument(skip(self, path, target_path))]
+    async fn move_to(
+        &self,
+        path: &Path,
+        id: &str,
+        target_path: &Path,
+    ) -> color_eyre::eyre::Result<String> {
+        let maildir = Maildir::from(path.to_path_buf());
+        let entry = maildir
+            .find(id)
+            .ok_or_else(|| color_eyre::eyre::eyre!("Unable to find mail {}", id))?;
+        let file_name = entry
+            .path()
+            .file_name()
+            .ok_or_else(|| color_eyre::eyre::eyre!("Mail {} has no file name", id))?;
+        let subfolder = if entry.path().parent().map_or(false, |x| x.ends_with("new")) {
+            "new"
+        } else {
+            "cur"
+        };
+        // A rename keeps the file and its flags intact and can't leave a copy behind
+        tokio::fs::rename(entry.path(), target_path.join(subfolder).join(file_name)).await?;
+        let mailbox = self.mailbox(path).await?;
+        self.expunge_uid(mailbox.id, id).await?;
+        let target_mailbox = self.mailbox(target_path).await?;
+        self.assign_uid(target_mailbox.id, id, None).await?;
+        Ok(id.to_string())
+    }
+
+    #[instrument(skip(self, path))]
+    async fn delete(&self, path: &Path, id: &str) -> color_eyre::eyre::Result<()> {
+        let maildir = Maildir::from(path.to_path_buf());
+        maildir.delete(id)?;
+        let mailbox = self.mailbox(path).await?;
+        self.expunge_uid(mailbox.id, id).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, path, new_path))]
+    async fn rename_mailbox(&self, path: &Path, new_path: &Path) -> color_eyre::eyre::Result<()> {
+        // Children are folders next to the mailbox whose name starts with it and move along
+        let mut renames = vec![(path.to_path_buf(), new_path.to_path_buf())];
+        if let (Some(parent), Some(new_parent), Some(name), Some(new_name)) = (
+            path.parent(),
+            new_path.parent(),
+            path.file_name(),
+            new_path.file_name(),
+        ) {
+            let prefix = format!("{}.", name.to_string_lossy());
+            let mut entries = tokio::fs::read_dir(parent).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let child = entry.file_name().to_string_lossy().to_string();
+                if let Some(rest) = child.strip_prefix(&prefix) {
+                    let new_child = format!("{}.{}", new_name.to_string_lossy(), rest);
+                    renames.push((entry.path(), new_parent.join(new_child)));
+                }
+            }
+        }
+        for (path, new_path) in &renames {
+            tokio::fs::rename(path, new_path).await?;
+        }
+
+        // The uids stay valid so the mailboxes keep their rows
+        let mut tx = self.db.get_pool().begin().await?;
+        for (path, new_path) in renames {
+            sqlx::query("DELETE FROM mailboxes WHERE path = $1")
+                .bind(new_path.to_string_lossy().to_string())
+                .execute(&mut tx)
+                .await?;
+            sqlx::query("UPDATE mailboxes SET path = $2 WHERE path = $1")
+                .bind(path.to_string_lossy().to_string())
+                .bind(new_path.to_string_lossy().to_string())
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, path))]
+    async fn delete_mailbox(&self, path: &Path) -> color_eyre::eyre::Result<()> {
+        tokio::fs::remove_dir_all(path).await?;
+        // A new mailbox with the same name must not reuse the old uids
+        sqlx::query("DELETE FROM mailboxes WHERE path = $1")
+            .bind(path.to_string_lossy().to_string())
+            .execute(self.db.get_pool())
+            .await?;
+        Ok(())
+    }
+
     #[instrument(skip(self, path))]
     fn list_subdirs(&self, path: &Path) -> color_eyre::eyre::Result<Vec<PathBuf>> {
         let maildir = Maildir::from(path.to_path_buf());
@@ -181,77 +488,36 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
         maildir.count_new()
     }
 
+    #[instrument(skip(self))]
+    async fn quota_usage(&self, username: &str) -> color_eyre::eyre::Result<QuotaUsage> {
+        let user_path = Path::new(&self.config.mail.maildir_folders).join(username);
+        // Walking all folders of the user would block the other connections meanwhile
+        tokio::task::spawn_blocking(move || user_quota_usage(&user_path)).await?
+    }
+
     #[instrument(skip(self, path))]
     async fn list_cur(&self, path: &Path) -> Vec<MaildirMailEntry> {
         let maildir = Maildir::from(path.to_path_buf());
-        let mail_rows: Vec<DbMails> = sqlx::query_as::<_, DbMails>("SELECT * FROM mails")
-            .fetch(self.db.get_pool())
-            .filter_map(|x| async move { x.ok() })
-            .collect()
-            .await;
-        maildir
-            .list_cur()
-            .filter_map(|x| match x {
-                Ok(x) => {
-                    let maildir_id = x.id();
-                    let uid = mail_rows
-                        .iter()
-                        .find(|y| y.maildir_id == maildir_id)
-                        .map_or(0, |y| y.id);
-                    Some(MaildirMailEntry { uid, entry: x })
-                }
-                Err(_) => None,
-            })
-            .collect()
+        let entries = maildir.list_cur().filter_map(Result::ok).collect();
+        self.with_uids(path, entries).await
     }
 
     #[instrument(skip(self, path))]
     async fn list_new(&self, path: &Path) -> Vec<MaildirMailEntry> {
         let maildir = Maildir::from(path.to_path_buf());
-        let mail_rows: Vec<DbMails> = sqlx::query_as::<_, DbMails>("SELECT * FROM mails")
-            .fetch(self.db.get_pool())
-            .filter_map(|x| async move { x.ok() })
-            .collect()
-            .await;
-        maildir
-            .list_new()
-            .filter_map(|x| match x {
-                Ok(x) => {
-                    let maildir_id = x.id();
-                    let uid = mail_rows
-                        .iter()
-                        .find(|y| y.maildir_id == maildir_id)
-                        .map_or(0, |y| y.id);
-                    Some(MaildirMailEntry { uid, entry: x })
-                }
-                Err(_) => None,
-            })
-            .collect()
+        let entries = maildir.list_new().filter_map(Result::ok).collect();
+        self.with_uids(path, entries).await
     }
 
     #[instrument(skip(self, path))]
     async fn list_all(&self, path: &Path) -> Vec<MaildirMailEntry> {
         let maildir = Maildir::from(path.to_path_buf());
-        let mail_rows: Vec<DbMails> = sqlx::query_as::<_, DbMails>("SELECT * FROM mails")
-            .fetch(self.db.get_pool())
-            .filter_map(|x| async move { x.ok() })
-            .collect()
-            .await;
-        maildir
+        let entries = maildir
             .list_new()
             .chain(maildir.list_cur())
-            .filter_map(|x| match x {
-                Ok(x) => {
-                    let maildir_id = x.id();
-                    let uid = mail_rows
-                        .iter()
-                        .find(|y| y.maildir_id == maildir_id)
-                        .map_or(0, |y| y.id);
-                    Some(MaildirMailEntry { uid, entry: x })
-                }
-                Err(_) => None,
-            })
-            .collect()
+            .filter_map(Result::ok)
+            .collect();
+        self.with_uids(path, entries).await
     }
 
     #[instrument(skip(self, path))]
@@ -379,11 +645,15 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
             })
             .collect::<Vec<_>>()
             .join("");
-        maildir.set_flags(id, &maildir_flags)?;
+        maildir.remove_flags(id, &maildir_flags)?;
         Ok(())
     }
 
     fn to_ondisk_path(&self, path: String, username: String) -> color_eyre::eyre::Result<PathBuf> {
+        let (path, username) = match shared_mailbox(&path) {
+            Some((owner, mailbox)) => (mailbox.to_string(), owner.to_string()),
+            None => (path, username),
+        };
         let folder = self.to_ondisk_path_name(path)?;
         let mailbox_path = Path::new(&self.config.mail.maildir_folders)
             .join(username)
@@ -402,15 +672,25 @@ impl MailStorage<MaildirMailEntry> for MaildirStorage {
 
 #[derive(sqlx::FromRow)]
 struct DbMails {
-    id: i64,
-    #[allow(dead_code)]
     maildir_id: String,
+    uid: i64,
+    modseq: i64,
+    flags: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DbMailbox {
+    id: i64,
+    uid_validity: i64,
+    uid_next: i64,
+    highest_modseq: i64,
 }
 
 /// Wrapper for the mailentries from the Maildir crate
 pub struct MaildirMailEntry {
     entry: maildir::MailEntry,
     uid: i64,
+    modseq: i64,
 }
 
 #[async_trait::async_trait]
@@ -420,6 +700,11 @@ impl MailEntry for MaildirMailEntry {
         self.uid
     }
 
+    #[instrument(skip(self))]
+    fn modseq(&self) -> i64 {
+        self.modseq
+    }
+
     #[instrument(skip(self))]
     fn id(&self) -> &str {
         self.entry.id()
@@ -492,3 +777,40 @@ async fn lines_from_file(filename: impl AsRef<Path>) -> std::io::Result<Vec<Stri
         .try_collect::<Vec<String>>()
         .await
 }
+
+/// Sums up the messages in all folders below the directory of a user
+fn user_quota_usage(user_path: &Path) -> color_eyre::eyre::Result<QuotaUsage> {
+    let mut usage = QuotaUsage::default();
+    if !user_path.exists() {
+        return Ok(usage);
+    }
+    // Every folder of the user is a maildir directly below the user folder
+    for folder in std::fs::read_dir(user_path)? {
+        let folder = folder?.path();
+        for subdir in ["cur", "new"] {
+            let subdir = folder.join(subdir);
+            if !subdir.is_dir() {
+                continue;
+            }
+            let mails = match std::fs::read_dir(subdir) {
+                Ok(mails) => mails,
+                // The folder got deleted or renamed meanwhile
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for mail in mails {
+                let metadata = match mail.and_then(|mail| mail.metadata()) {
+                    Ok(metadata) => metadata,
+                    // Mails get moved from new to cur or expunged while we look at them
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if !metadata.is_dir() {
+                    usage.storage += metadata.len();
+                    usage.messages += 1;
+                }
+            }
+        }
+    }
+    Ok(usage)
+}
@@ -27,6 +27,8 @@ pub type MailEntryType = MaildirMailEntry;
 pub trait MailEntry {
     /// The uid of the mail entry
     fn uid(&self) -> i64;
+    /// The modification sequence of the last flag change
+    fn modseq(&self) -> i64;
     /// The id of the email
     fn id(&self) -> &str;
     /// The parsed form of the email
@@ -55,14 +57,47 @@ pub trait MailEntry {
     fn path(&self) -> &PathBuf;
 }
 
+/// The prefix of the namespace which contains the mailboxes other users shared
+pub const SHARED_NAMESPACE: &str = "Shared/";
+
+/// Splits a mailbox name like `Shared/alice@example.com/Projects` into the owner and the name of
+/// the mailbox below the directory of the owner.
+///
+/// Returns `None` for mailboxes outside of the shared namespace.
+#[must_use]
+pub fn shared_mailbox(path: &str) -> Option<(&str, &str)> {
+    let (owner, mailbox) = path
+        .trim_matches('"')
+        .strip_prefix(SHARED_NAMESPACE)?
+        .split_once('/')?;
+    // The owner is a directory name so it must not be able to escape the maildir folders
+    (!owner.is_empty() && !owner.starts_with('.') && !mailbox.is_empty())
+        .then_some((owner, mailbox))
+}
+
+/// The resources a user currently uses which are limited by their quota
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// The size of all messages in octets
+    pub storage: u64,
+    /// The number of messages
+    pub messages: u64,
+}
+
 /// Abstract Storage definition
 //
 // Note for future readers:
 // These are methods as other storage types may need to store some state in the struct
 #[async_trait::async_trait]
 pub trait MailStorage<M: MailEntry> {
-    /// Get the current UID for the folder
-    fn get_uid_for_folder(&self, path: &Path) -> color_eyre::eyre::Result<u32>;
+    /// Get the UID the next message in the folder will get
+    async fn get_uid_next(&self, path: &Path) -> color_eyre::eyre::Result<u32>;
+    /// Get the UIDVALIDITY of the folder
+    async fn get_uid_validity(&self, path: &Path) -> color_eyre::eyre::Result<u32>;
+    /// Get the highest modification sequence of the folder
+    async fn get_highest_modseq(&self, path: &Path) -> color_eyre::eyre::Result<i64>;
+    /// Get the uids which were expunged after the modification sequence in ascending order
+    async fn get_vanished(&self, path: &Path, modseq: i64) -> color_eyre::eyre::Result<Vec<i64>>;
     /// Get the current flags for the folder
     async fn get_flags(&self, path: &Path) -> std::io::Result<Vec<String>>;
     /// Set a new flag for the folder
@@ -80,12 +115,36 @@ pub trait MailStorage<M: MailEntry> {
         data: &[u8],
         flags: Vec<String>,
     ) -> color_eyre::eyre::Result<String>;
+    /// Copy a message to another folder keeping its flags and return the id of the copy
+    async fn copy_to(
+        &self,
+        path: &Path,
+        id: &str,
+        target_path: &Path,
+    ) -> color_eyre::eyre::Result<String>;
+    /// Move a message to another folder keeping its flags and return its id.
+    ///
+    /// The message gets a new uid as uids have to be ascending in the target folder.
+    async fn move_to(
+        &self,
+        path: &Path,
+        id: &str,
+        target_path: &Path,
+    ) -> color_eyre::eyre::Result<String>;
+    /// Permanently remove a message
+    async fn delete(&self, path: &Path, id: &str) -> color_eyre::eyre::Result<()>;
+    /// Rename a folder and its children keeping the uids of their messages
+    async fn rename_mailbox(&self, path: &Path, new_path: &Path) -> color_eyre::eyre::Result<()>;
+    /// Permanently remove a folder and all of its messages
+    async fn delete_mailbox(&self, path: &Path) -> color_eyre::eyre::Result<()>;
     /// List the subfolders
     fn list_subdirs(&self, path: &Path) -> color_eyre::eyre::Result<Vec<PathBuf>>;
     /// Count of current messages
     fn count_cur(&self, path: &Path) -> usize;
     /// Count of new messages
     fn count_new(&self, path: &Path) -> usize;
+    /// The size and count of the messages in all folders of the user
+    async fn quota_usage(&self, username: &str) -> color_eyre::eyre::Result<QuotaUsage>;
     /// Get the current messages
     async fn list_cur(&self, path: &Path) -> Vec<M>;
     /// Get the new messages
@@ -114,7 +173,9 @@ pub trait MailStorage<M: MailEntry> {
         id: &str,
         imap_flags: &[&str],
     ) -> color_eyre::eyre::Result<()>;
-    /// Converts the imap path to a local path
+    /// Converts the imap path to a local path.
+    ///
+    /// Mailboxes in the shared namespace resolve to the directory of their owner.
     fn to_ondisk_path(&self, path: String, username: String) -> color_eyre::eyre::Result<PathBuf>;
     /// Converts the imap path to a local path name
     fn to_ondisk_path_name(&self, path: String) -> color_eyre::eyre::Result<String>;
@@ -1,9 +1,26 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 const fn default_webserver_port() -> u16 {
     8080
 }
 
+fn default_folders() -> Vec<DefaultFolder> {
+    [
+        ("Sent", "\\Sent"),
+        ("Drafts", "\\Drafts"),
+        ("Trash", "\\Trash"),
+        ("Junk", "\\Junk"),
+        ("Archive", "\\Archive"),
+    ]
+    .into_iter()
+    .map(|(name, special_use)| DefaultFolder {
+        name: name.to_string(),
+        special_use: special_use.to_string(),
+    })
+    .collect()
+}
+
 /// The config for the mailserver
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -20,6 +37,36 @@ pub struct Config {
     pub sentry: bool,
     /// The config of the webserver
     pub webserver: Webserver,
+    /// Allows logging in with bearer tokens of these identity providers
+    pub oauth: Option<OAuth>,
+    /// Users which may change the quotas of all users
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+/// Configurations for the OAUTHBEARER and XOAUTH2 authentication
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuth {
+    /// The identity providers whose tokens are accepted
+    pub issuers: Vec<OAuthIssuer>,
+}
+
+/// An identity provider which issues JWT bearer tokens
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthIssuer {
+    /// The `iss` claim of the tokens
+    pub issuer: String,
+    /// The `aud` claim the tokens need to have
+    pub audience: String,
+    /// URL or path of the JWKS containing the keys of the issuer
+    pub jwks: String,
+    /// Maps the `sub` claim to the erooster user
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+    /// Uses subjects which are missing in `users` as the username.
+    /// Only enable this if the issuer is trusted to name the local users.
+    #[serde(default)]
+    pub subject_as_username: bool,
 }
 
 /// The config for the webserver
@@ -58,6 +105,18 @@ pub struct Mail {
     pub hostname: String,
     /// The Displayname to be used in software like thunderbird
     pub displayname: String,
+    /// Folders which are created for every user on their first login
+    #[serde(default = "default_folders")]
+    pub default_folders: Vec<DefaultFolder>,
+}
+
+/// A folder which gets created automatically with a special-use attribute
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DefaultFolder {
+    /// The name of the folder
+    pub name: String,
+    /// The special-use attribute of the folder as defined in RFC 6154
+    pub special_use: String,
 }
 
 impl Config {
@@ -73,4 +132,12 @@ impl Config {
         let config: Self = serde_yaml::from_str(&contents)?;
         Ok(config)
     }
+
+    /// Whether bearer tokens of any identity provider are accepted
+    #[must_use]
+    pub fn oauth_enabled(&self) -> bool {
+        self.oauth
+            .as_ref()
+            .map_or(false, |oauth| !oauth.issuers.is_empty())
+    }
 }
@@ -47,6 +47,12 @@ pub mod backend;
 /// The configuration file for the server
 pub mod config;
 
+/// The verification of OAuth 2.0 bearer tokens
+pub mod oauth;
+
+/// The SCRAM-SHA-256 credentials and proofs
+pub mod scram;
+
 /// Returns the config struct from the provided location or defaults
 #[instrument(skip(config_path))]
 pub async fn get_config(config_path: String) -> Result<Arc<config::Config>> {
@@ -0,0 +1,229 @@
+use crate::config::{Config, OAuthIssuer};
+use color_eyre::eyre::{bail, eyre, Result};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::instrument;
+
+/// How long the keys of an issuer are used before they get loaded again
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the keys of an issuer are kept at least even if a token uses an unknown key
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(5 * 60);
+
+/// How long an identity provider may take to hand out its keys
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The keys of an issuer together with the time they got loaded
+type CachedJwks = (Instant, Arc<JwkSet>);
+
+static JWKS_CACHE: Lazy<Mutex<HashMap<String, CachedJwks>>> = Lazy::new(Mutex::default);
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(JWKS_TIMEOUT)
+        .build()
+        .expect("HTTP client to be valid")
+});
+
+/// The error a client gets before the authentication fails as described in RFC 7628
+pub const OAUTH_ERROR: &str = r#"{"status":"invalid_token","schemes":"bearer"}"#;
+
+/// The user and bearer token a client sent with OAUTHBEARER or XOAUTH2
+#[derive(Debug, PartialEq, Eq)]
+pub struct OAuthResponse<'a> {
+    /// The user the client wants to log in as if it told us
+    pub user: Option<&'a str>,
+    /// The bearer token
+    pub token: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Issuer {
+    iss: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+/// Parses the client response of OAUTHBEARER as defined in RFC 7628
+#[must_use]
+pub fn oauthbearer_response(message: &str) -> Option<OAuthResponse<'_>> {
+    let (gs2_header, kvpairs) = message.split_once('\x01')?;
+    // OAUTHBEARER has no channel binding
+    let authzid = gs2_header
+        .strip_prefix("n,")
+        .or_else(|| gs2_header.strip_prefix("y,"))?
+        .strip_suffix(',')?;
+    let user = if authzid.is_empty() {
+        None
+    } else {
+        Some(authzid.strip_prefix("a=")?)
+    };
+    Some(OAuthResponse {
+        user,
+        token: bearer_token(kvpairs)?,
+    })
+}
+
+/// Parses the client response of XOAUTH2
+#[must_use]
+pub fn xoauth2_response(message: &str) -> Option<OAuthResponse<'_>> {
+    let user = message
+        .split('\x01')
+        .find_map(|pair| pair.strip_prefix("user="))?;
+    Some(OAuthResponse {
+        user: Some(user),
+        token: bearer_token(message)?,
+    })
+}
+
+fn bearer_token(kvpairs: &str) -> Option<&str> {
+    kvpairs
+        .split('\x01')
+        .find_map(|pair| pair.strip_prefix("auth="))
+        .and_then(|auth| {
+            auth.strip_prefix("Bearer ")
+                .or_else(|| auth.strip_prefix("bearer "))
+        })
+        .filter(|token| !token.is_empty())
+}
+
+/// Loads the JWKS of an issuer from a URL or a file
+#[instrument(skip(source))]
+async fn load_jwks(source: &str) -> Result<JwkSet> {
+    let contents = if source.starts_with("https://") || source.starts_with("http://") {
+        HTTP_CLIENT
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+    } else {
+        tokio::fs::read_to_string(source).await?
+    };
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Returns the JWKS of an issuer and only loads it again once it is older than [`JWKS_TTL`]
+///
+/// An unknown `kid` means the issuer may have rotated its keys. In that case the keys get
+/// loaded early, but at most once per [`JWKS_MIN_REFRESH`].
+#[instrument(skip(issuer))]
+async fn issuer_jwks(issuer: &OAuthIssuer, kid: Option<&str>) -> Result<Arc<JwkSet>> {
+    if let Some((loaded, jwks)) = JWKS_CACHE
+        .lock()
+        .expect("JWKS cache to not be poisoned")
+        .get(&issuer.issuer)
+    {
+        let known_kid = kid.map_or(true, |kid| jwks.find(kid).is_some());
+        if loaded.elapsed() < JWKS_TTL && (known_kid || loaded.elapsed() < JWKS_MIN_REFRESH) {
+            return Ok(Arc::clone(jwks));
+        }
+    }
+
+    let jwks = Arc::new(load_jwks(&issuer.jwks).await?);
+    JWKS_CACHE
+        .lock()
+        .expect("JWKS cache to not be poisoned")
+        .insert(issuer.issuer.clone(), (Instant::now(), Arc::clone(&jwks)));
+    Ok(jwks)
+}
+
+/// The algorithms a token signed with the key may use
+///
+/// An `alg` of the key is binding, otherwise the type of the key decides.
+/// Keys with a shared secret can't be used at all.
+fn key_algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(algorithm) = jwk.common.algorithm {
+        return vec![algorithm];
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(parameters) => match parameters.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => vec![],
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => vec![],
+    }
+}
+
+/// Verifies the bearer token against the configured issuers.
+///
+/// Returns the erooster user the subject of the token maps to.
+#[instrument(skip(config, token))]
+pub async fn verify_token(config: &Config, token: &str) -> Result<String> {
+    let header = decode_header(token)?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        bail!("Tokens signed with a shared secret are not supported");
+    }
+
+    // The issuer decides about the keys so we need to look at it before checking the signature
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| eyre!("The token has no payload"))?;
+    let unverified: Issuer =
+        serde_json::from_slice(&base64::decode_config(payload, base64::URL_SAFE_NO_PAD)?)?;
+    let issuer = config
+        .oauth
+        .iter()
+        .flat_map(|oauth| &oauth.issuers)
+        .find(|issuer| issuer.issuer == unverified.iss)
+        .ok_or_else(|| eyre!("Unknown token issuer {}", unverified.iss))?;
+
+    let jwks = issuer_jwks(issuer, header.kid.as_deref()).await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| eyre!("No matching key for the token of {}", issuer.issuer))?;
+    // The algorithm in the header is chosen by whoever made the token
+    if !key_algorithms(jwk).contains(&header.alg) {
+        bail!(
+            "The token of {} uses {:?} which doesn't fit its key",
+            issuer.issuer,
+            header.alg
+        );
+    }
+    let key = DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&issuer.issuer]);
+    validation.set_audience(&[&issuer.audience]);
+    let claims = decode::<Claims>(token, &key, &validation)?.claims;
+    match issuer.users.get(&claims.sub) {
+        Some(username) => Ok(username.clone()),
+        // Otherwise every issuer could log in as any local user
+        None if issuer.subject_as_username => Ok(claims.sub),
+        None => bail!(
+            "The subject {} of {} has no user",
+            claims.sub,
+            issuer.issuer
+        ),
+    }
+}
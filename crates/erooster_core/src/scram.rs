@@ -0,0 +1,158 @@
+use color_eyre::eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+use subtle::ConstantTimeEq;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The iteration count used for new credentials as recommended by RFC 7677
+const ITERATIONS: u32 = 4096;
+
+/// The SCRAM-SHA-256 credentials of a user as they are stored in the database.
+///
+/// They are saved in the format of RFC 5803: `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScramCredentials {
+    /// The iteration count of the salted password
+    pub iterations: u32,
+    /// The salt of the salted password
+    pub salt: Vec<u8>,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl ScramCredentials {
+    /// Derives the credentials from the password using a new random salt
+    #[must_use]
+    pub fn new(password: &str) -> Self {
+        let mut salt = vec![0; 16];
+        OsRng.fill_bytes(&mut salt);
+        ScramCredentials::from_password(password, salt, ITERATIONS)
+    }
+
+    /// Derives the credentials from the password with the given salt and iteration count
+    #[must_use]
+    pub fn from_password(password: &str, salt: Vec<u8>, iterations: u32) -> Self {
+        let salted_password = hi(password.as_bytes(), &salt, iterations);
+        let client_key = hmac(&salted_password, b"Client Key");
+        ScramCredentials {
+            iterations,
+            salt,
+            stored_key: Sha256::digest(&client_key).to_vec(),
+            server_key: hmac(&salted_password, b"Server Key"),
+        }
+    }
+
+    /// Derives credentials nobody can log in with from a secret of the server
+    ///
+    /// The salt only depends on the secret and the username like it would for an existing user.
+    #[must_use]
+    pub fn fake(secret: &[u8], username: &str) -> Self {
+        let key = hmac(secret, username.as_bytes());
+        ScramCredentials {
+            iterations: ITERATIONS,
+            salt: hmac(&key, b"Salt")[..16].to_vec(),
+            stored_key: hmac(&key, b"Stored Key"),
+            server_key: hmac(&key, b"Server Key"),
+        }
+    }
+
+    /// Checks the `ClientProof` of a client-final-message against the `AuthMessage` of the exchange
+    #[must_use]
+    pub fn verify_proof(&self, auth_message: &str, client_proof: &[u8]) -> bool {
+        let client_signature = hmac(&self.stored_key, auth_message.as_bytes());
+        if client_signature.len() != client_proof.len() {
+            return false;
+        }
+        let client_key: Vec<u8> = client_proof
+            .iter()
+            .zip(client_signature)
+            .map(|(proof, signature)| proof ^ signature)
+            .collect();
+        Sha256::digest(&client_key)
+            .as_slice()
+            .ct_eq(&self.stored_key)
+            .into()
+    }
+
+    /// The `ServerSignature` the client uses to authenticate us
+    #[must_use]
+    pub fn server_signature(&self, auth_message: &str) -> Vec<u8> {
+        hmac(&self.server_key, auth_message.as_bytes())
+    }
+}
+
+// The keys are enough to impersonate the server so they shouldn't end up in logs
+impl fmt::Debug for ScramCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScramCredentials")
+            .field("iterations", &self.iterations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ScramCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SCRAM-SHA-256${}:{}${}:{}",
+            self.iterations,
+            base64::encode(&self.salt),
+            base64::encode(&self.stored_key),
+            base64::encode(&self.server_key)
+        )
+    }
+}
+
+impl FromStr for ScramCredentials {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (iterations, salt, stored_key, server_key) = s
+            .strip_prefix("SCRAM-SHA-256$")
+            .and_then(|rest| rest.split_once('$'))
+            .and_then(|(iteration_and_salt, keys)| {
+                let (iterations, salt) = iteration_and_salt.split_once(':')?;
+                let (stored_key, server_key) = keys.split_once(':')?;
+                Some((iterations, salt, stored_key, server_key))
+            })
+            .ok_or_else(|| eyre!("Invalid SCRAM-SHA-256 credentials"))?;
+        Ok(ScramCredentials {
+            iterations: iterations.parse()?,
+            salt: base64::decode(salt)?,
+            stored_key: base64::decode(stored_key)?,
+            server_key: base64::decode(server_key)?,
+        })
+    }
+}
+
+/// Generates the random part of the nonce the server adds to the exchange
+#[must_use]
+pub fn server_nonce() -> String {
+    let mut nonce = [0; 18];
+    OsRng.fill_bytes(&mut nonce);
+    base64::encode(nonce)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The `Hi` function of RFC 5802 which is PBKDF2 with HMAC-SHA-256 producing a single block
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut salt_block = salt.to_vec();
+    salt_block.extend_from_slice(&1u32.to_be_bytes());
+    let mut previous = hmac(password, &salt_block);
+    let mut result = previous.clone();
+    for _ in 1..iterations {
+        previous = hmac(password, &previous);
+        for (result_byte, byte) in result.iter_mut().zip(&previous) {
+            *result_byte ^= byte;
+        }
+    }
+    result
+}
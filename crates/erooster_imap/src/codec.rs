@@ -0,0 +1,191 @@
+use bytes::{Buf, BufMut, BytesMut};
+use erooster_core::line_codec::{LinesCodec, LinesCodecError};
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+/// A piece of input from the client
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// A line without its line break
+    Line(String),
+    /// The octets of a literal
+    Literal(Vec<u8>),
+}
+
+/// The literal which the client sends next
+#[derive(Debug, Clone, Copy)]
+struct PendingLiteral {
+    remaining: usize,
+    discard: bool,
+}
+
+/// A [`Decoder`] and [`Encoder`] for the IMAP protocol.
+///
+/// Commands are read line by line like with the [`LinesCodec`] but literals are read as raw octets.
+/// They may contain any data and their length doesn't depend on line breaks.
+/// Only the session knows if a literal got accepted so it has to announce it with [`ImapCodec::read_literal`].
+///
+/// Responses are written as they are followed by a line break as literals in them may contain any octet.
+#[derive(Debug, Clone)]
+pub struct ImapCodec {
+    lines: LinesCodec,
+    literal: Option<PendingLiteral>,
+}
+
+impl ImapCodec {
+    /// Returns an `ImapCodec` with a maximum line length limit.
+    ///
+    /// The limit doesn't apply to literals.
+    #[must_use]
+    pub const fn new_with_max_length(max_length: usize) -> Self {
+        ImapCodec {
+            lines: LinesCodec::new_with_max_length(max_length),
+            literal: None,
+        }
+    }
+
+    /// Reads the next `length` octets as a literal.
+    ///
+    /// A discarded literal isn't buffered and results in an empty [`Frame::Literal`].
+    pub fn read_literal(&mut self, length: usize, discard: bool) {
+        self.literal = Some(PendingLiteral {
+            remaining: length,
+            discard,
+        });
+    }
+}
+
+impl Decoder for ImapCodec {
+    type Item = Frame;
+    type Error = LinesCodecError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, LinesCodecError> {
+        match &mut self.literal {
+            Some(literal) if literal.discard => {
+                let skipped = literal.remaining.min(buf.len());
+                buf.advance(skipped);
+                literal.remaining -= skipped;
+                if literal.remaining > 0 {
+                    return Ok(None);
+                }
+                self.literal = None;
+                Ok(Some(Frame::Literal(Vec::new())))
+            }
+            Some(literal) => {
+                if buf.len() < literal.remaining {
+                    buf.reserve(literal.remaining - buf.len());
+                    return Ok(None);
+                }
+                let data = buf.split_to(literal.remaining).to_vec();
+                self.literal = None;
+                Ok(Some(Frame::Literal(data)))
+            }
+            None => Ok(self.lines.decode(buf)?.map(Frame::Line)),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, LinesCodecError> {
+        if self.literal.is_some() {
+            // A literal which got cut off is of no use
+            let frame = self.decode(buf)?;
+            buf.clear();
+            return Ok(frame);
+        }
+        Ok(self.lines.decode_eof(buf)?.map(Frame::Line))
+    }
+}
+
+impl Encoder<String> for ImapCodec {
+    type Error = LinesCodecError;
+
+    fn encode(&mut self, line: String, buf: &mut BytesMut) -> Result<(), LinesCodecError> {
+        self.encode(line.into_bytes(), buf)
+    }
+}
+
+impl Encoder<Vec<u8>> for ImapCodec {
+    type Error = LinesCodecError;
+
+    fn encode(&mut self, line: Vec<u8>, buf: &mut BytesMut) -> Result<(), LinesCodecError> {
+        buf.reserve(line.len() + 2);
+        buf.put(line.as_slice());
+        buf.put_u8(b'\r');
+        buf.put_u8(b'\n');
+        debug!("sending line: {:?}", buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal() {
+        let mut codec = ImapCodec::new_with_max_length(1024);
+        let mut buf = BytesMut::from(&b"a APPEND INBOX {4}\r\n\xff\r\n\x80 (\\Seen)\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Line(String::from("a APPEND INBOX {4}")))
+        );
+        codec.read_literal(4, false);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Literal(b"\xff\r\n\x80".to_vec()))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Line(String::from(" (\\Seen)")))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_partial_literal() {
+        let mut codec = ImapCodec::new_with_max_length(1024);
+        let mut buf = BytesMut::from(&b"Hel"[..]);
+        codec.read_literal(5, false);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"lo\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Literal(b"Hello".to_vec()))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Line(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_discarded_literal() {
+        let mut codec = ImapCodec::new_with_max_length(1024);
+        let mut buf = BytesMut::from(&b"\0\0\0"[..]);
+        codec.read_literal(5, true);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+        buf.extend_from_slice(b"\0\0\r\nb NOOP\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Literal(Vec::new()))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Line(String::new()))
+        );
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Line(String::from("b NOOP")))
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut codec = ImapCodec::new_with_max_length(1024);
+        let mut buf = BytesMut::new();
+        codec
+            .encode(b"* 1 FETCH (BINARY[1] ~{1}\r\n\xff)".to_vec(), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], b"* 1 FETCH (BINARY[1] ~{1}\r\n\xff)\r\n");
+    }
+}
@@ -0,0 +1,454 @@
+use crate::commands::{parsers::Argument, CommandData, Data};
+use erooster_core::backend::{
+    database::{Database, DB},
+    storage::{shared_mailbox, MailStorage, Storage},
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use std::sync::Arc;
+use tracing::instrument;
+
+/// All rights of RFC 4314 in the order they are reported in
+pub const ALL_RIGHTS: &str = "lrswipkxtea";
+
+/// The identifier whose rights apply to every user
+const ANYONE: &str = "anyone";
+
+/// Resolves the mailbox to its owner and the folder name below the directory of the owner
+pub fn mailbox_owner<'a>(
+    storage: &Storage,
+    username: &'a str,
+    mailbox: &'a str,
+) -> color_eyre::eyre::Result<(&'a str, String)> {
+    let (owner, name) = shared_mailbox(mailbox).unwrap_or((username, mailbox));
+    Ok((owner, storage.to_ondisk_path_name(name.to_string())?))
+}
+
+/// Returns the rights the user has on the mailbox.
+///
+/// Users always have all rights on their own mailboxes.
+#[instrument(skip(database, storage))]
+pub async fn mailbox_rights(
+    database: &DB,
+    storage: &Storage,
+    username: &str,
+    mailbox: &str,
+) -> color_eyre::eyre::Result<String> {
+    let (owner, name) = mailbox_owner(storage, username, mailbox)?;
+    if owner == username {
+        return Ok(ALL_RIGHTS.to_string());
+    }
+    let granted = database
+        .get_acl(owner, &name)
+        .await?
+        .into_iter()
+        .filter(|(identifier, _)| identifier == username || identifier == ANYONE)
+        .map(|(_, rights)| rights)
+        .collect::<String>();
+    Ok(ALL_RIGHTS
+        .chars()
+        .filter(|right| granted.contains(*right))
+        .collect())
+}
+
+/// Returns the error response if one of the required rights is missing.
+///
+/// Mailboxes the user has no rights on at all are reported as nonexistent to not reveal them.
+pub fn missing_rights(tag: &str, rights: &str, required: &str) -> Option<String> {
+    if rights.is_empty() {
+        Some(format!("{} NO [NONEXISTENT] Mailbox does not exist", tag))
+    } else if required.chars().all(|right| rights.contains(right)) {
+        None
+    } else {
+        Some(format!("{} NO [NOPERM] Permission denied", tag))
+    }
+}
+
+/// Returns the right needed to set or clear the flag
+pub fn flag_right(flag: &str) -> char {
+    match flag.to_lowercase().as_str() {
+        "\\seen" => 's',
+        "\\deleted" => 't',
+        _ => 'w',
+    }
+}
+
+/// Resolves the mailbox argument of the ACL commands and checks that the user may see it.
+///
+/// Sends the error response and returns `None` if the command can't continue.
+#[instrument(skip(data, lines, database, storage, command_data))]
+async fn acl_mailbox<'a, S>(
+    data: &Data,
+    lines: &mut S,
+    database: &DB,
+    storage: &Storage,
+    command_data: &'a CommandData<'_>,
+    arguments: usize,
+    required: &str,
+) -> color_eyre::eyre::Result<Option<(String, &'a str, String)>>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    let username = if let Some(username) = data.authenticated_user().await {
+        username
+    } else {
+        lines
+            .send(format!("{} NO invalid state", command_data.tag))
+            .await?;
+        return Ok(None);
+    };
+    let mailbox = match command_data.arguments.first().and_then(Argument::as_str) {
+        Some(mailbox) if command_data.arguments.len() == arguments => mailbox,
+        _ => {
+            lines
+                .send(format!("{} BAD Missing arguments", command_data.tag))
+                .await?;
+            return Ok(None);
+        }
+    };
+
+    let mailbox_path = storage.to_ondisk_path(mailbox.to_string(), username.clone())?;
+    let rights = if mailbox_path.exists() {
+        mailbox_rights(database, storage, &username, mailbox).await?
+    } else {
+        String::new()
+    };
+    if let Some(response) = missing_rights(command_data.tag, &rights, required) {
+        lines.send(response).await?;
+        return Ok(None);
+    }
+    Ok(Some((username, mailbox, rights)))
+}
+
+pub struct SetAcl<'a> {
+    pub data: &'a Data,
+}
+
+impl SetAcl<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (username, mailbox, _) = if let Some(mailbox) =
+            acl_mailbox(self.data, lines, &database, &storage, command_data, 3, "a").await?
+        {
+            mailbox
+        } else {
+            return Ok(());
+        };
+        let (identifier, modification) = if let (Some(identifier), Some(modification)) = (
+            command_data.arguments[1].as_str(),
+            command_data.arguments[2].as_str(),
+        ) {
+            (identifier, modification)
+        } else {
+            lines
+                .send(format!("{} BAD Invalid arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        let (owner, name) = mailbox_owner(&storage, &username, mailbox)?;
+        if identifier == owner {
+            lines
+                .send(format!(
+                    "{} NO [CANNOT] The owner always has all rights",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        let current = database
+            .get_acl(owner, &name)
+            .await?
+            .into_iter()
+            .find_map(|(x, rights)| (x == identifier).then_some(rights))
+            .unwrap_or_default();
+        let rights = if let Some(rights) = apply_rights(&current, modification) {
+            rights
+        } else {
+            lines
+                .send(format!("{} BAD Unsupported rights", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+        database.set_acl(owner, &name, identifier, &rights).await?;
+        lines
+            .send(format!("{} OK SETACL completed", command_data.tag))
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct DeleteAcl<'a> {
+    pub data: &'a Data,
+}
+
+impl DeleteAcl<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (username, mailbox, _) = if let Some(mailbox) =
+            acl_mailbox(self.data, lines, &database, &storage, command_data, 2, "a").await?
+        {
+            mailbox
+        } else {
+            return Ok(());
+        };
+        let identifier = command_data.arguments[1].as_str().unwrap_or_default();
+
+        let (owner, name) = mailbox_owner(&storage, &username, mailbox)?;
+        if identifier == owner {
+            lines
+                .send(format!(
+                    "{} NO [CANNOT] The owner always has all rights",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        database.delete_acl(owner, &name, Some(identifier)).await?;
+        lines
+            .send(format!("{} OK DELETEACL completed", command_data.tag))
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct GetAcl<'a> {
+    pub data: &'a Data,
+}
+
+impl GetAcl<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (username, mailbox, _) = if let Some(mailbox) =
+            acl_mailbox(self.data, lines, &database, &storage, command_data, 1, "a").await?
+        {
+            mailbox
+        } else {
+            return Ok(());
+        };
+
+        let (owner, name) = mailbox_owner(&storage, &username, mailbox)?;
+        let entries = database
+            .get_acl(owner, &name)
+            .await?
+            .into_iter()
+            .map(|(identifier, rights)| format!(" \"{}\" {}", identifier, rights))
+            .collect::<String>();
+        lines
+            .feed(format!(
+                "* ACL \"{}\" \"{}\" {}{}",
+                mailbox, owner, ALL_RIGHTS, entries
+            ))
+            .await?;
+        lines
+            .feed(format!("{} OK GETACL completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct ListRights<'a> {
+    pub data: &'a Data,
+}
+
+impl ListRights<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (username, mailbox, _) = if let Some(mailbox) =
+            acl_mailbox(self.data, lines, &database, &storage, command_data, 2, "a").await?
+        {
+            mailbox
+        } else {
+            return Ok(());
+        };
+        let identifier = command_data.arguments[1].as_str().unwrap_or_default();
+
+        let (owner, _) = mailbox_owner(&storage, &username, mailbox)?;
+        lines
+            .feed(list_rights_response(mailbox, identifier, owner))
+            .await?;
+        lines
+            .feed(format!("{} OK LISTRIGHTS completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct MyRights<'a> {
+    pub data: &'a Data,
+}
+
+impl MyRights<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (_, mailbox, rights) = if let Some(mailbox) =
+            acl_mailbox(self.data, lines, &database, &storage, command_data, 1, "").await?
+        {
+            mailbox
+        } else {
+            return Ok(());
+        };
+
+        lines
+            .feed(format!("* MYRIGHTS \"{}\" {}", mailbox, rights))
+            .await?;
+        lines
+            .feed(format!("{} OK MYRIGHTS completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+/// Applies the rights of SETACL to the current rights.
+///
+/// Rights prefixed with `+` are added, rights prefixed with `-` are removed and all other rights
+/// replace the current ones. Returns `None` if an unknown right is given.
+fn apply_rights(current: &str, modification: &str) -> Option<String> {
+    let (rights, add, remove) = if let Some(rights) = modification.strip_prefix('+') {
+        (rights, true, false)
+    } else if let Some(rights) = modification.strip_prefix('-') {
+        (rights, false, true)
+    } else {
+        (modification, false, false)
+    };
+    let mut changed = String::new();
+    for right in rights.chars() {
+        match right {
+            // The obsolete rights of RFC 2086 map to the rights which replaced them
+            'c' => changed.push('k'),
+            'd' => changed.push_str("xte"),
+            right if ALL_RIGHTS.contains(right) => changed.push(right),
+            _ => return None,
+        }
+    }
+    Some(
+        ALL_RIGHTS
+            .chars()
+            .filter(|right| {
+                let is_current = current.contains(*right);
+                let is_changed = changed.contains(*right);
+                if add {
+                    is_current || is_changed
+                } else if remove {
+                    is_current && !is_changed
+                } else {
+                    is_changed
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Formats the LISTRIGHTS response. Every right can be granted on its own except for the owner
+/// who always has all of them.
+fn list_rights_response(mailbox: &str, identifier: &str, owner: &str) -> String {
+    if identifier == owner {
+        format!(
+            "* LISTRIGHTS \"{}\" \"{}\" {}",
+            mailbox, identifier, ALL_RIGHTS
+        )
+    } else {
+        let optional = ALL_RIGHTS
+            .chars()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "* LISTRIGHTS \"{}\" \"{}\" \"\" {}",
+            mailbox, identifier, optional
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_rights() {
+        assert_eq!(apply_rights("", "lr"), Some(String::from("lr")));
+        assert_eq!(apply_rights("lr", "rl"), Some(String::from("lr")));
+        assert_eq!(apply_rights("lr", "+is"), Some(String::from("lrsi")));
+        assert_eq!(apply_rights("lrsi", "-ri"), Some(String::from("ls")));
+        assert_eq!(apply_rights("lr", ""), Some(String::new()));
+        assert_eq!(apply_rights("l", "+cd"), Some(String::from("lkxte")));
+        assert_eq!(apply_rights("l", "+z"), None);
+    }
+
+    #[test]
+    fn test_missing_rights() {
+        assert_eq!(missing_rights("1", ALL_RIGHTS, "te"), None);
+        assert_eq!(missing_rights("1", "lr", ""), None);
+        assert_eq!(
+            missing_rights("1", "", ""),
+            Some(String::from("1 NO [NONEXISTENT] Mailbox does not exist"))
+        );
+        assert_eq!(
+            missing_rights("1", "lr", "i"),
+            Some(String::from("1 NO [NOPERM] Permission denied"))
+        );
+        assert_eq!(
+            missing_rights("1", "", "r"),
+            Some(String::from("1 NO [NONEXISTENT] Mailbox does not exist"))
+        );
+    }
+
+    #[test]
+    fn test_list_rights_response() {
+        assert_eq!(
+            list_rights_response("Shared/alice/Projects", "bob", "alice"),
+            "* LISTRIGHTS \"Shared/alice/Projects\" \"bob\" \"\" l r s w i p k x t e a"
+        );
+        assert_eq!(
+            list_rights_response("Projects", "alice", "alice"),
+            "* LISTRIGHTS \"Projects\" \"alice\" lrswipkxtea"
+        );
+    }
+}
@@ -1,49 +1,92 @@
 use crate::{
-    commands::{parsers::append_arguments, CommandData, Data},
-    state::{AppendingState, State},
+    commands::{
+        acl::{flag_right, mailbox_owner, mailbox_rights, missing_rights},
+        create::{create_mailbox, special_use_for_name},
+        parsers::{date_time, Argument, DateTime},
+        quota::fits_quota,
+        search::to_sequence_set,
+        CommandData, Data,
+    },
+    state::State,
 };
-use erooster_core::{
-    backend::storage::{MailStorage, Storage},
-    config::Config,
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailStorage, Storage},
 };
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
-use nom::{error::convert_error, Finish};
-use std::{path::Path, sync::Arc};
+use nom::Finish;
+use std::{iter::Peekable, path::Path, slice::Iter, sync::Arc};
 use tracing::{debug, error, instrument};
 
 pub struct Append<'a> {
     pub data: &'a Data,
 }
 impl Append<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let mut write_lock = self.data.con_state.write().await;
+        let (state, username) = {
+            let read_lock = self.data.con_state.read().await;
+            (read_lock.state.clone(), read_lock.username.clone())
+        };
         debug!("Append command start");
-        if matches!(write_lock.state, State::Authenticated)
-            || matches!(write_lock.state, State::Selected(_, _))
-        {
+        if matches!(state, State::Authenticated | State::Selected(_, _)) {
             debug!("[Append] User is authenticated");
             debug!(
                 "[Append] User added {} arguments",
                 command_data.arguments.len()
             );
-            assert!(command_data.arguments.len() >= 3);
-            let folder = command_data.arguments[0].replace('"', "");
+            let parsed = command_data
+                .arguments
+                .split_first()
+                .and_then(|(folder, append_args)| {
+                    Some((folder.as_str()?.to_string(), append_arguments(append_args)?))
+                });
+            let (folder, mut messages) = if let Some(parsed) = parsed {
+                parsed
+            } else {
+                error!("[Append] Error parsing arguments");
+                lines
+                    .send(format!(
+                        "{} BAD failed to parse arguments",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            };
             debug!("[Append] User wants to append to folder: {}", folder);
-            let mailbox_path =
-                storage.to_ondisk_path(folder.clone(), write_lock.username.clone().unwrap())?;
-            let folder = storage.to_ondisk_path_name(folder)?;
+            let username = username.unwrap();
+            let mailbox_path = storage.to_ondisk_path(folder.clone(), username.clone())?;
+            let (owner, ondisk_name) = mailbox_owner(&storage, &username, &folder)?;
+            let owner = owner.to_string();
             debug!("Appending to folder: {:?}", mailbox_path);
-            // Spec violation but thunderbird would prompt a user error otherwise :/
-            if !mailbox_path.exists() {
+            if owner != username {
+                // Mailboxes of other users are never created implicitly
+                let rights = if mailbox_path.exists() {
+                    mailbox_rights(&database, &storage, &username, &folder).await?
+                } else {
+                    String::new()
+                };
+                if let Some(response) = missing_rights(command_data.tag, &rights, "i") {
+                    lines.send(response).await?;
+                    return Ok(());
+                }
+                // Flags the user may not set are dropped silently
+                for message in &mut messages {
+                    message
+                        .flags
+                        .retain(|flag| rights.contains(flag_right(flag)));
+                }
+            } else if !mailbox_path.exists() {
+                // Spec violation but thunderbird would prompt a user error otherwise :/
                 /*lines
                     .send(format!(
                         "{} NO [TRYCREATE] folder is not yet created",
@@ -51,56 +94,64 @@ impl Append<'_> {
                     ))
                     .await?;
                 return Ok(());*/
-                storage.create_dirs(&mailbox_path)?;
-                if folder.to_lowercase() == ".sent" {
-                    storage.add_flag(&mailbox_path, "\\Sent").await?;
-                    storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                } else if folder.to_lowercase() == ".junk" {
-                    storage.add_flag(&mailbox_path, "\\Junk").await?;
-                    storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                } else if folder.to_lowercase() == ".drafts" {
-                    storage.add_flag(&mailbox_path, "\\Drafts").await?;
-                    storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                } else if folder.to_lowercase() == ".archive" {
-                    storage.add_flag(&mailbox_path, "\\Archive").await?;
-                    storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                } else if folder.to_lowercase() == ".trash" {
-                    storage.add_flag(&mailbox_path, "\\Trash").await?;
-                    storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                }
+                create_mailbox(&storage, &mailbox_path, special_use_for_name(&ondisk_name)).await?;
             }
 
-            let append_args = command_data.arguments[1..].join(" ");
-            let append_args_borrow: &str = &append_args;
-            debug!("Append args: {}", append_args_borrow);
-            match append_arguments(append_args_borrow).finish() {
-                Ok((left, (flags, datetime, literal))) => {
-                    debug!("[Append] leftover: {}", left);
-                    write_lock.state = State::Appending(AppendingState {
-                        folder: folder.to_string(),
-                        flags: flags.map(|x| x.iter().map(ToString::to_string).collect::<Vec<_>>()),
-                        datetime,
-                        data: None,
-                        datalen: literal.length,
-                        tag: command_data.tag.to_string(),
-                    });
-                    if !literal.continuation {
-                        lines.send(String::from("+ Ready for literal data")).await?;
-                    }
-                }
-                Err(e) => {
-                    error!(
-                        "[Append] Error parsing arguments: {}",
-                        convert_error(append_args_borrow, e)
-                    );
+            let size = messages
+                .iter()
+                .map(|message| message.message.len())
+                .sum::<usize>();
+            // Mails in shared mailboxes count towards the quota of the owner
+            if !fits_quota(
+                &database,
+                &storage,
+                &owner,
+                u64::try_from(size)?,
+                u64::try_from(messages.len())?,
+            )
+            .await?
+            {
+                lines
+                    .send(format!(
+                        "{} NO [OVERQUOTA] Quota exceeded",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+
+            let message_ids = match store_messages(&storage, &mailbox_path, messages).await {
+                Ok(message_ids) => message_ids,
+                Err(error) => {
+                    error!("[Append] Failed to store messages: {}", error);
                     lines
-                        .send(format!(
-                            "{} BAD failed to parse arguments",
-                            command_data.tag
-                        ))
+                        .send(format!("{} NO APPEND failed", command_data.tag))
                         .await?;
+                    return Ok(());
+                }
+            };
+            // Clients use the uids of the appended mails to avoid duplicate drafts
+            let mut uids = Vec::with_capacity(message_ids.len());
+            for message_id in &message_ids {
+                if let Some(mail) = storage.find(&mailbox_path, message_id).await {
+                    uids.push(mail.uid());
                 }
             }
+            if uids.len() == message_ids.len() {
+                let uid_validity = storage.get_uid_validity(&mailbox_path).await?;
+                lines
+                    .send(format!(
+                        "{} OK [APPENDUID {} {}] APPEND completed",
+                        command_data.tag,
+                        uid_validity,
+                        to_sequence_set(&uids)
+                    ))
+                    .await?;
+            } else {
+                lines
+                    .send(format!("{} OK APPEND completed", command_data.tag))
+                    .await?;
+            }
         } else {
             lines
                 .send(format!("{} NO invalid state", command_data.tag))
@@ -108,55 +159,141 @@ impl Append<'_> {
         }
         Ok(())
     }
+}
 
-    #[instrument(skip(self, lines, storage, config, append_data))]
-    pub async fn append<S>(
-        &self,
-        lines: &mut S,
-        storage: Arc<Storage>,
-        append_data: &str,
-        config: Arc<Config>,
-        tag: String,
-    ) -> color_eyre::eyre::Result<()>
-    where
-        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
-    {
-        let mut write_lock = self.data.con_state.write().await;
-        let username = write_lock.username.clone().unwrap();
-        if let State::Appending(state) = &mut write_lock.state {
-            if let Some(buffer) = &mut state.data {
-                let mut bytes = format!("{}\r\n", append_data).as_bytes().to_vec();
-                buffer.append(&mut bytes);
-                if buffer.len() + bytes.len() >= state.datalen {
-                    debug!("[Append] Saving data");
-                    let folder = &state.folder;
-                    let mailbox_path = Path::new(&config.mail.maildir_folders)
-                        .join(username)
-                        .join(folder.clone());
-                    debug!("[Append] Mailbox path: {:?}", mailbox_path);
-                    if let Some(flags) = &state.flags {
-                        let message_id = storage
-                            .store_cur_with_flags(&mailbox_path, buffer, flags.clone())
-                            .await?;
-                        debug!("Stored message via append: {}", message_id);
-                    } else {
-                        let message_id = storage
-                            .store_cur_with_flags(&mailbox_path, buffer, vec![])
-                            .await?;
-                        debug!("Stored message via append: {}", message_id);
+#[derive(Debug, PartialEq, Eq)]
+struct AppendArguments<'a> {
+    flags: Vec<String>,
+    datetime: Option<DateTime>,
+    message: &'a [u8],
+}
+
+/// Stores all messages or none of them if one fails and returns their ids in order
+#[instrument(skip(storage, mailbox_path, messages))]
+async fn store_messages(
+    storage: &Storage,
+    mailbox_path: &Path,
+    messages: Vec<AppendArguments<'_>>,
+) -> color_eyre::eyre::Result<Vec<String>> {
+    let mut message_ids = Vec::with_capacity(messages.len());
+    for message in messages {
+        debug!("[Append] Internal date: {:?}", message.datetime);
+        match storage
+            .store_cur_with_flags(mailbox_path, message.message, message.flags)
+            .await
+        {
+            Ok(message_id) => {
+                debug!("Stored message via append: {}", message_id);
+                message_ids.push(message_id);
+            }
+            Err(error) => {
+                // MULTIAPPEND is atomic so the already stored messages get removed again
+                for message_id in &message_ids {
+                    if let Err(error) = storage.delete(mailbox_path, message_id).await {
+                        error!("[Append] Failed to roll back {}: {}", message_id, error);
                     }
-                    lines.send(format!("{} OK APPEND completed", tag)).await?;
                 }
-            } else {
-                let mut buffer = Vec::with_capacity(state.datalen);
-                let mut bytes = append_data.as_bytes().to_vec();
-                buffer.append(&mut bytes);
-
-                state.data = Some(buffer);
+                return Err(error);
             }
-        } else {
-            lines.send(format!("{} NO invalid state", tag)).await?;
         }
-        Ok(())
+    }
+    Ok(message_ids)
+}
+
+/// Parses one or more messages each made of optional flags and date followed by the literal
+fn append_arguments(arguments: &[Argument]) -> Option<Vec<AppendArguments>> {
+    let mut arguments = arguments.iter().peekable();
+    let mut messages = vec![append_message(&mut arguments)?];
+    while arguments.peek().is_some() {
+        messages.push(append_message(&mut arguments)?);
+    }
+    Some(messages)
+}
+
+/// Parses the optional flags and date followed by the message literal
+fn append_message<'a>(arguments: &mut Peekable<Iter<'a, Argument>>) -> Option<AppendArguments<'a>> {
+    let flags = match arguments.next_if(|x| matches!(x, Argument::List(_))) {
+        Some(Argument::List(flags)) => flags
+            .iter()
+            .map(|flag| flag.as_str().map(ToString::to_string))
+            .collect::<Option<Vec<_>>>()?,
+        _ => vec![],
+    };
+    let datetime = match arguments.next_if(|x| matches!(x, Argument::Quoted(_))) {
+        Some(Argument::Quoted(datetime)) => Some(date_time(datetime).finish().ok()?.1),
+        _ => None,
+    };
+    let message = match arguments.next()? {
+        Argument::Literal(message) | Argument::Literal8(message) => message,
+        // RFC 6855 wraps the message as `UTF8 (literal)`
+        utf8 if utf8.is_atom("UTF8") => match arguments.next()? {
+            Argument::List(message) => match message.as_slice() {
+                [Argument::Literal(message) | Argument::Literal8(message)] => message,
+                _ => return None,
+            },
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(AppendArguments {
+        flags,
+        datetime,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_arguments() {
+        let arguments = [
+            Argument::List(vec![Argument::Atom(String::from("\\Seen"))]),
+            Argument::Quoted(String::from("17-Jul-1996 02:44:25 -0700")),
+            Argument::Literal8(b"Subject: Hi\r\n\r\nHello\r\n".to_vec()),
+        ];
+        assert_eq!(
+            append_arguments(&arguments),
+            Some(vec![AppendArguments {
+                flags: vec![String::from("\\Seen")],
+                datetime: Some(DateTime::DateTime(String::from(
+                    "17 Jul 1996 02:44:25 -0700"
+                ))),
+                message: b"Subject: Hi\r\n\r\nHello\r\n",
+            }])
+        );
+
+        let arguments = [
+            Argument::Atom(String::from("UTF8")),
+            Argument::List(vec![Argument::Literal(b"Hello".to_vec())]),
+        ];
+        assert_eq!(
+            append_arguments(&arguments).map(|x| x[0].message),
+            Some(&b"Hello"[..])
+        );
+        assert_eq!(append_arguments(&[]), None);
+    }
+
+    #[test]
+    fn test_multiappend_arguments() {
+        let arguments = [
+            Argument::List(vec![Argument::Atom(String::from("\\Seen"))]),
+            Argument::Literal(b"First".to_vec()),
+            Argument::Literal(b"Second".to_vec()),
+            Argument::Atom(String::from("UTF8")),
+            Argument::List(vec![Argument::Literal8(b"Third".to_vec())]),
+        ];
+        let messages = append_arguments(&arguments).unwrap();
+        assert_eq!(
+            messages.iter().map(|x| x.message).collect::<Vec<_>>(),
+            vec![&b"First"[..], b"Second", b"Third"]
+        );
+        assert_eq!(messages[0].flags, vec![String::from("\\Seen")]);
+        assert!(messages[1].flags.is_empty());
+
+        // A trailing set of flags without a message is invalid
+        let arguments = [Argument::Literal(b"First".to_vec()), Argument::List(vec![])];
+        assert_eq!(append_arguments(&arguments), None);
     }
 }
@@ -1,18 +1,181 @@
 use std::str::FromStr;
 
 use crate::{
-    commands::{CommandData, Data},
-    state::State,
+    commands::{create::provision_default_folders, CommandData, Data},
+    state::{Connection, State},
+};
+use erooster_core::{
+    backend::{
+        database::{normalize_username, Database, DB},
+        storage::Storage,
+    },
+    config::Config,
+    oauth::{oauthbearer_response, verify_token, xoauth2_response, OAUTH_ERROR},
+    scram::{server_nonce, ScramCredentials},
 };
-use erooster_core::backend::database::{Database, DB};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use secrecy::SecretString;
 use simdutf8::compat::from_utf8;
+use std::sync::Arc;
 use tracing::{debug, error, instrument};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AuthenticationMethod {
     Plain,
+    ScramSha256(ScramStep),
+    OAuthBearer,
+    XOAuth2,
+    /// The client got told why the token was rejected and has to respond before it gets the NO
+    OAuthFailed,
+}
+
+/// The progress of a SCRAM-SHA-256 exchange
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ScramStep {
+    /// Waiting for the client-first-message. SCRAM-SHA-256-PLUS requires channel binding.
+    ClientFirst { plus: bool },
+    /// Waiting for the client-final-message
+    ClientFinal(Box<ScramExchange>),
+    /// The server-final-message was sent and the client has to acknowledge it
+    Acknowledge { username: String },
+}
+
+/// The data of a SCRAM-SHA-256 exchange which is needed to verify the client-final-message
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ScramExchange {
+    username: String,
+    /// The expected value of the `c` attribute
+    channel_binding: String,
+    client_first_bare: String,
+    server_first: String,
+    nonce: String,
+    credentials: ScramCredentials,
+}
+
+/// The parts of a client-first-message
+#[derive(Debug, PartialEq, Eq)]
+struct ClientFirst<'a> {
+    gs2_header: &'a str,
+    /// The channel binding type the client requested with `p=`
+    channel_binding_type: Option<&'a str>,
+    /// The client supports channel binding but thinks we don't
+    channel_binding_supported: bool,
+    username: String,
+    nonce: &'a str,
+    bare: &'a str,
+}
+
+/// The parts of a client-final-message
+#[derive(Debug, PartialEq, Eq)]
+struct ClientFinal<'a> {
+    channel_binding: &'a str,
+    nonce: &'a str,
+    without_proof: &'a str,
+    proof: Vec<u8>,
+}
+
+/// Decodes a `saslname` which escapes `,` and `=`
+fn sasl_name(name: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = name;
+    while let Some(index) = rest.find('=') {
+        decoded.push_str(&rest[..index]);
+        match rest.get(index..index + 3)? {
+            "=2C" => decoded.push(','),
+            "=3D" => decoded.push('='),
+            _ => return None,
+        }
+        rest = &rest[index + 3..];
+    }
+    decoded.push_str(rest);
+    Some(decoded)
+}
+
+fn client_first(message: &str) -> Option<ClientFirst<'_>> {
+    let mut parts = message.splitn(3, ',');
+    let channel_binding_flag = parts.next()?;
+    let authzid = parts.next()?;
+    let bare = parts.next()?;
+    let (channel_binding_type, channel_binding_supported) = match channel_binding_flag {
+        "n" => (None, false),
+        "y" => (None, true),
+        flag => (Some(flag.strip_prefix("p=")?), true),
+    };
+
+    // A mandatory extension would come before the username and we don't support any
+    let mut attributes = bare.split(',');
+    let username = sasl_name(attributes.next()?.strip_prefix("n=")?)?;
+    let nonce = attributes.next()?.strip_prefix("r=")?;
+    if username.is_empty() || nonce.is_empty() {
+        return None;
+    }
+    // We don't allow acting as another user
+    if !authzid.is_empty() && sasl_name(authzid.strip_prefix("a=")?)? != username {
+        return None;
+    }
+    Some(ClientFirst {
+        gs2_header: &message[..message.len() - bare.len()],
+        channel_binding_type,
+        channel_binding_supported,
+        username,
+        nonce,
+        bare,
+    })
+}
+
+fn client_final(message: &str) -> Option<ClientFinal<'_>> {
+    let (without_proof, proof) = message.rsplit_once(",p=")?;
+    let mut attributes = without_proof.split(',');
+    let channel_binding = attributes.next()?.strip_prefix("c=")?;
+    let nonce = attributes.next()?.strip_prefix("r=")?;
+    Some(ClientFinal {
+        channel_binding,
+        nonce,
+        without_proof,
+        proof: base64::decode(proof).ok()?,
+    })
+}
+
+/// Decodes a client response. `=` is an empty initial response as of RFC 4959.
+fn decode_response(auth_data: &str) -> Option<String> {
+    if auth_data == "=" {
+        return Some(String::new());
+    }
+    let bytes = base64::decode(auth_data.as_bytes()).ok()?;
+    from_utf8(&bytes).ok().map(ToString::to_string)
+}
+
+/// Finishes a successful authentication
+#[instrument(skip(lines, connection, config, database, storage, username, command_data))]
+async fn authenticated<S>(
+    lines: &mut S,
+    connection: &mut Connection,
+    config: &Config,
+    database: &DB,
+    storage: &Storage,
+    username: &str,
+    command_data: &CommandData<'_>,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    {
+        connection.username = Some(username.to_string());
+        connection.state = State::Authenticated;
+    };
+    if let Err(e) = provision_default_folders(database, storage, config, username).await {
+        error!("[IMAP] Failed to create the default folders: {}", e);
+    }
+    if connection.secure {
+        lines
+            .send(format!("{} OK Success (tls protection)", command_data.tag))
+            .await?;
+    } else {
+        lines
+            .send(format!("{} OK Success (unprotected)", command_data.tag))
+            .await?;
+    }
+    Ok(())
 }
 
 pub struct Authenticate<'a> {
@@ -21,11 +184,13 @@ pub struct Authenticate<'a> {
 }
 
 impl Authenticate<'_> {
-    #[instrument(skip(self, lines, database, command_data))]
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
     pub async fn plain<S>(
         &self,
         lines: &mut S,
+        config: Arc<Config>,
         database: DB,
+        storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
@@ -50,13 +215,13 @@ impl Authenticate<'_> {
                     .collect();
 
                 if auth_data_vec.len() == 2 {
-                    let username = auth_data_vec[0];
+                    let username = normalize_username(auth_data_vec[0]);
                     let password = SecretString::from_str(auth_data_vec[1])?;
 
                     debug!("[IMAP] Making sure user exists");
-                    if database.user_exists(username).await {
+                    if database.user_exists(&username).await {
                         debug!("[IMAP] Verify credentials");
-                        if !database.verify_user(username, password).await {
+                        if !database.verify_user(&username, password).await {
                             {
                                 write_lock.state = State::NotAuthenticated;
                             };
@@ -66,20 +231,16 @@ impl Authenticate<'_> {
                             debug!("[IMAP] Invalid user or password");
                             return Ok(());
                         }
-                        {
-                            write_lock.username = Some(username.to_string());
-                            write_lock.state = State::Authenticated;
-                        };
-                        let secure = write_lock.secure;
-                        if secure {
-                            lines
-                                .send(format!("{} OK Success (tls protection)", command_data.tag))
-                                .await?;
-                        } else {
-                            lines
-                                .send(format!("{} OK Success (unprotected)", command_data.tag))
-                                .await?;
-                        }
+                        authenticated(
+                            lines,
+                            &mut write_lock,
+                            &config,
+                            &database,
+                            &storage,
+                            &username,
+                            command_data,
+                        )
+                        .await?;
                     } else {
                         {
                             write_lock.state = State::NotAuthenticated;
@@ -110,38 +271,359 @@ impl Authenticate<'_> {
 
         Ok(())
     }
-}
 
-impl Authenticate<'_> {
-    #[instrument(skip(self, lines, database, command_data))]
-    pub async fn exec<S>(
+    #[allow(clippy::too_many_lines)]
+    #[instrument(skip(self, lines, config, database, storage, step, command_data))]
+    pub async fn scram<S>(
         &self,
         lines: &mut S,
+        config: Arc<Config>,
         database: DB,
+        storage: Arc<Storage>,
+        step: ScramStep,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        if self.data.con_state.read().await.state == State::NotAuthenticated {
-            let args = &command_data.arguments;
-            assert!(args.len() == 1);
-            if args.len() == 1 {
-                if args.first().unwrap().to_lowercase() == "plain" {
-                    debug!("[IMAP] Update state to Authenticating");
+        let mut write_lock = self.data.con_state.write().await;
+        let message = if let Some(message) = decode_response(self.auth_data) {
+            message
+        } else {
+            write_lock.state = State::NotAuthenticated;
+            lines
+                .send(format!("{} BAD Invalid arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        match step {
+            ScramStep::ClientFirst { plus } => {
+                let channel_binding = write_lock.channel_binding.clone();
+                let client_first = client_first(&message).filter(|client_first| {
+                    match client_first.channel_binding_type {
+                        Some(channel_binding_type) => {
+                            plus && channel_binding_type == "tls-exporter"
+                        }
+                        // The client would use channel binding but thinks we don't offer it
+                        // while we do which means someone removed it from our capabilities
+                        None => {
+                            !plus
+                                && !(client_first.channel_binding_supported
+                                    && channel_binding.is_some())
+                        }
+                    }
+                });
+                let client_first = if let Some(client_first) = client_first {
+                    client_first
+                } else {
+                    write_lock.state = State::NotAuthenticated;
+                    lines
+                        .send(format!("{} BAD Invalid arguments", command_data.tag))
+                        .await?;
+                    return Ok(());
+                };
+
+                let mut channel_binding_input = client_first.gs2_header.as_bytes().to_vec();
+                if client_first.channel_binding_type.is_some() {
+                    if let Some(channel_binding) = channel_binding {
+                        channel_binding_input.extend(channel_binding);
+                    } else {
+                        write_lock.state = State::NotAuthenticated;
+                        lines
+                            .send(format!(
+                                "{} NO Channel binding is not available",
+                                command_data.tag
+                            ))
+                            .await?;
+                        return Ok(());
+                    }
+                }
+
+                // Unknown users get made up credentials to not give away which users exist.
+                // This also applies to users who didn't log in with their password since SCRAM-SHA-256 got added.
+                let credentials = database
+                    .get_scram_credentials(&client_first.username)
+                    .await
+                    .unwrap_or_else(|| database.fake_scram_credentials(&client_first.username));
+                let nonce = format!("{}{}", client_first.nonce, server_nonce());
+                let server_first = format!(
+                    "r={},s={},i={}",
+                    nonce,
+                    base64::encode(&credentials.salt),
+                    credentials.iterations
+                );
+                let response = format!("+ {}", base64::encode(&server_first));
+                write_lock.state = State::Authenticating(
+                    AuthenticationMethod::ScramSha256(ScramStep::ClientFinal(Box::new(
+                        ScramExchange {
+                            username: client_first.username,
+                            channel_binding: base64::encode(channel_binding_input),
+                            client_first_bare: client_first.bare.to_string(),
+                            server_first,
+                            nonce,
+                            credentials,
+                        },
+                    ))),
+                    command_data.tag.to_string(),
+                );
+                lines.send(response).await?;
+            }
+            ScramStep::ClientFinal(exchange) => {
+                let auth_message = client_final(&message).and_then(|client_final| {
+                    if client_final.channel_binding != exchange.channel_binding
+                        || client_final.nonce != exchange.nonce
                     {
-                        let command_data = command_data;
-                        self.data.con_state.write().await.state = State::Authenticating(
-                            AuthenticationMethod::Plain,
-                            command_data.tag.to_string(),
-                        );
-                    };
-                    debug!("[IMAP] Sending continuation request");
-                    lines.send(String::from("+ ")).await?;
+                        return None;
+                    }
+                    let auth_message = format!(
+                        "{},{},{}",
+                        exchange.client_first_bare,
+                        exchange.server_first,
+                        client_final.without_proof
+                    );
+                    exchange
+                        .credentials
+                        .verify_proof(&auth_message, &client_final.proof)
+                        .then_some(auth_message)
+                });
+                if let Some(auth_message) = auth_message {
+                    let server_final = format!(
+                        "v={}",
+                        base64::encode(exchange.credentials.server_signature(&auth_message))
+                    );
+                    write_lock.state = State::Authenticating(
+                        AuthenticationMethod::ScramSha256(ScramStep::Acknowledge {
+                            username: exchange.username,
+                        }),
+                        command_data.tag.to_string(),
+                    );
+                    lines
+                        .send(format!("+ {}", base64::encode(server_final)))
+                        .await?;
+                } else {
+                    debug!("[IMAP] Invalid user or password");
+                    write_lock.state = State::NotAuthenticated;
+                    lines
+                        .send(format!("{} NO Invalid user or password", command_data.tag))
+                        .await?;
+                }
+            }
+            ScramStep::Acknowledge { username } => {
+                if message.is_empty() {
+                    authenticated(
+                        lines,
+                        &mut write_lock,
+                        &config,
+                        &database,
+                        &storage,
+                        &username,
+                        command_data,
+                    )
+                    .await?;
                 } else {
-                    self.plain(lines, database, command_data).await?;
+                    write_lock.state = State::NotAuthenticated;
+                    lines
+                        .send(format!("{} BAD Invalid arguments", command_data.tag))
+                        .await?;
                 }
-            } else {
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, lines, config, database, storage, method, command_data))]
+    pub async fn oauth<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
+        method: AuthenticationMethod,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let mut write_lock = self.data.con_state.write().await;
+        let message = decode_response(self.auth_data).unwrap_or_default();
+        let response = if method == AuthenticationMethod::OAuthBearer {
+            oauthbearer_response(&message)
+        } else {
+            xoauth2_response(&message)
+        };
+        let response = if let Some(response) = response {
+            response
+        } else {
+            write_lock.state = State::NotAuthenticated;
+            lines
+                .send(format!("{} BAD Invalid arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        let username = match verify_token(&config, response.token).await {
+            // The token has to belong to the user the client asked for
+            Ok(username) if response.user.map_or(true, |user| user == username) => {
+                database.user_exists(&username).await.then_some(username)
+            }
+            Ok(username) => {
+                debug!(
+                    "[IMAP] The token of {} doesn't belong to {:?}",
+                    username, response.user
+                );
+                None
+            }
+            Err(e) => {
+                debug!("[IMAP] Invalid token: {}", e);
+                None
+            }
+        };
+        if let Some(username) = username {
+            authenticated(
+                lines,
+                &mut write_lock,
+                &config,
+                &database,
+                &storage,
+                &username,
+                command_data,
+            )
+            .await?;
+        } else {
+            write_lock.state = State::Authenticating(
+                AuthenticationMethod::OAuthFailed,
+                command_data.tag.to_string(),
+            );
+            lines
+                .send(format!("+ {}", base64::encode(OAUTH_ERROR)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Handles the next client response of the exchange
+    #[instrument(skip(self, lines, config, database, storage, method, command_data))]
+    pub async fn step<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
+        method: AuthenticationMethod,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        if self.auth_data == "*" {
+            self.data.con_state.write().await.state = State::NotAuthenticated;
+            lines
+                .send(format!("{} BAD Authentication cancelled", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        match method {
+            AuthenticationMethod::Plain => {
+                self.plain(lines, config, database, storage, command_data)
+                    .await?;
+            }
+            AuthenticationMethod::ScramSha256(step) => {
+                self.scram(lines, config, database, storage, step, command_data)
+                    .await?;
+            }
+            AuthenticationMethod::OAuthBearer | AuthenticationMethod::XOAuth2 => {
+                self.oauth(lines, config, database, storage, method, command_data)
+                    .await?;
+            }
+            // Whatever the client answers to the error the authentication failed
+            AuthenticationMethod::OAuthFailed => {
+                self.data.con_state.write().await.state = State::NotAuthenticated;
+                lines
+                    .send(format!("{} NO Invalid token", command_data.tag))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Authenticate<'_> {
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (state, secure) = {
+            let read_lock = self.data.con_state.read().await;
+            (read_lock.state.clone(), read_lock.secure)
+        };
+        if state != State::NotAuthenticated {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+
+        let args = &command_data.arguments;
+        let method = match args.first() {
+            Some(mechanism) if mechanism.is_atom("plain") => AuthenticationMethod::Plain,
+            Some(mechanism) if mechanism.is_atom("scram-sha-256") => {
+                AuthenticationMethod::ScramSha256(ScramStep::ClientFirst { plus: false })
+            }
+            // Channel binding needs TLS
+            Some(mechanism) if secure && mechanism.is_atom("scram-sha-256-plus") => {
+                AuthenticationMethod::ScramSha256(ScramStep::ClientFirst { plus: true })
+            }
+            // Bearer tokens must not be sent in plain text
+            Some(mechanism) if secure && mechanism.is_atom("oauthbearer") => {
+                AuthenticationMethod::OAuthBearer
+            }
+            Some(mechanism) if secure && mechanism.is_atom("xoauth2") => {
+                AuthenticationMethod::XOAuth2
+            }
+            Some(_) => {
+                lines
+                    .send(format!(
+                        "{} NO Unsupported authentication mechanism",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            None => {
+                lines
+                    .send(format!(
+                        "{} BAD [SERVERBUG] unable to parse command",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+        };
+        match args.len() {
+            1 => {
+                debug!("[IMAP] Update state to Authenticating");
+                {
+                    self.data.con_state.write().await.state =
+                        State::Authenticating(method, command_data.tag.to_string());
+                };
+                debug!("[IMAP] Sending continuation request");
+                lines.send(String::from("+ ")).await?;
+            }
+            // The initial response of SASL-IR
+            2 => {
+                self.step(lines, config, database, storage, method, command_data)
+                    .await?;
+            }
+            _ => {
                 lines
                     .send(format!(
                         "{} BAD [SERVERBUG] unable to parse command",
@@ -149,11 +631,128 @@ impl Authenticate<'_> {
                     ))
                     .await?;
             }
-        } else {
-            lines
-                .send(format!("{} NO invalid state", command_data.tag))
-                .await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use erooster_core::oauth::OAuthResponse;
+
+    #[test]
+    fn test_sasl_name() {
+        assert_eq!(sasl_name("user"), Some(String::from("user")));
+        assert_eq!(sasl_name("a=2Cb=3Dc"), Some(String::from("a,b=c")));
+        assert_eq!(sasl_name("a=b"), None);
+        assert_eq!(sasl_name("a="), None);
+    }
+
+    #[test]
+    fn test_client_first() {
+        assert_eq!(
+            client_first("n,,n=user,r=rOprNGfwEbeRWgbNEkqO"),
+            Some(ClientFirst {
+                gs2_header: "n,,",
+                channel_binding_type: None,
+                channel_binding_supported: false,
+                username: String::from("user"),
+                nonce: "rOprNGfwEbeRWgbNEkqO",
+                bare: "n=user,r=rOprNGfwEbeRWgbNEkqO",
+            })
+        );
+        assert_eq!(
+            client_first("p=tls-exporter,a=user,n=user,r=abc"),
+            Some(ClientFirst {
+                gs2_header: "p=tls-exporter,a=user,",
+                channel_binding_type: Some("tls-exporter"),
+                channel_binding_supported: true,
+                username: String::from("user"),
+                nonce: "abc",
+                bare: "n=user,r=abc",
+            })
+        );
+        // Acting as another user
+        assert_eq!(client_first("n,a=admin,n=user,r=abc"), None);
+        // Mandatory extension
+        assert_eq!(client_first("n,,m=ext,n=user,r=abc"), None);
+        assert_eq!(client_first("n,,n=user"), None);
+    }
+
+    #[test]
+    fn test_oauth_responses() {
+        assert_eq!(
+            oauthbearer_response(
+                "n,a=user@example.com,\x01host=server.example.com\x01port=993\x01auth=Bearer vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg==\x01\x01"
+            ),
+            Some(OAuthResponse {
+                user: Some("user@example.com"),
+                token: "vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg==",
+            })
+        );
+        assert_eq!(
+            oauthbearer_response("n,,\x01auth=Bearer abc\x01\x01"),
+            Some(OAuthResponse {
+                user: None,
+                token: "abc",
+            })
+        );
+        // Channel binding isn't possible with OAUTHBEARER
+        assert_eq!(
+            oauthbearer_response("p=tls-exporter,,\x01auth=Bearer abc\x01\x01"),
+            None
+        );
+        assert_eq!(
+            oauthbearer_response("n,,\x01host=example.com\x01\x01"),
+            None
+        );
+        assert_eq!(
+            xoauth2_response("user=user@example.com\x01auth=Bearer abc\x01\x01"),
+            Some(OAuthResponse {
+                user: Some("user@example.com"),
+                token: "abc",
+            })
+        );
+        assert_eq!(xoauth2_response("auth=Bearer abc\x01\x01"), None);
+    }
+
+    #[test]
+    fn test_client_final() {
+        let client_final = client_final(
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+        )
+        .unwrap();
+        assert_eq!(client_final.channel_binding, "biws");
+        assert_eq!(
+            client_final.nonce,
+            "rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0"
+        );
+        assert_eq!(
+            client_final.without_proof,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0"
+        );
+
+        // The example exchange of RFC 7677
+        let credentials = ScramCredentials::from_password(
+            "pencil",
+            base64::decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap(),
+            4096,
+        );
+        let auth_message = format!(
+            "n=user,r=rOprNGfwEbeRWgbNEkqO,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096,{}",
+            client_final.without_proof
+        );
+        assert!(credentials.verify_proof(&auth_message, &client_final.proof));
+        assert_eq!(
+            base64::encode(credentials.server_signature(&auth_message)),
+            "6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        );
+        assert!(!ScramCredentials::from_password(
+            "pen",
+            base64::decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap(),
+            4096,
+        )
+        .verify_proof(&auth_message, &client_final.proof));
+    }
+}
@@ -1,10 +1,15 @@
-use crate::commands::CommandData;
+use crate::{
+    commands::{CommandData, Data},
+    state::{Compression, Connection, State},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use tracing::instrument;
 
-pub struct Capability;
+pub struct Capability<'a> {
+    pub data: &'a Data,
+}
 
-impl Capability {
+impl Capability<'_> {
     #[instrument(skip(self, lines, command_data))]
     pub async fn exec<S>(
         &self,
@@ -14,8 +19,21 @@ impl Capability {
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let capabilities = get_capabilities();
-        lines.feed(format!("* {}", capabilities)).await?;
+        let response = {
+            let read_lock = self.data.con_state.read().await;
+            let capabilities = get_capabilities(&read_lock);
+            // COMPRESS is only allowed once after authentication
+            if matches!(
+                read_lock.state,
+                State::Authenticated | State::Selected(_, _)
+            ) && read_lock.compression == Compression::Off
+            {
+                format!("* {} COMPRESS=DEFLATE", capabilities)
+            } else {
+                format!("* {}", capabilities)
+            }
+        };
+        lines.feed(response).await?;
         lines
             .feed(format!("{} OK CAPABILITY completed", command_data.tag))
             .await?;
@@ -24,19 +42,119 @@ impl Capability {
     }
 }
 
-pub const fn get_capabilities() -> &'static str {
-    "CAPABILITY AUTH=PLAIN LOGINDISABLED UTF8=ONLY ENABLE IMAP4rev2 IMAP4rev1"
+/// The capabilities which don't depend on the security of the connection
+const COMMON_CAPABILITIES: &str = "SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1";
+
+/// The capabilities we offer on a connection.
+///
+/// LOGIN, bearer tokens and the channel binding of SCRAM-SHA-256-PLUS need TLS
+/// and STARTTLS is only offered as long as the connection can be upgraded.
+/// Bearer tokens also need an identity provider to be configured.
+pub fn get_capabilities(connection: &Connection) -> String {
+    if connection.secure && connection.oauth {
+        format!(
+            "CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS AUTH=OAUTHBEARER AUTH=XOAUTH2 {}",
+            COMMON_CAPABILITIES
+        )
+    } else if connection.secure {
+        format!(
+            "CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS {}",
+            COMMON_CAPABILITIES
+        )
+    } else if connection.starttls {
+        format!(
+            "CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 LOGINDISABLED STARTTLS {}",
+            COMMON_CAPABILITIES
+        )
+    } else {
+        format!(
+            "CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 LOGINDISABLED {}",
+            COMMON_CAPABILITIES
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::commands::{CommandData, Commands};
+    use crate::state::Connection;
     use futures::{channel::mpsc, StreamExt};
 
     #[tokio::test]
     async fn test_get_capabilities() {
-        let caps = Capability {};
+        let caps = Capability {
+            data: &Data {
+                con_state: Connection::new(false),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "",
+            command: Commands::Capability,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = caps.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 LOGINDISABLED STARTTLS SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities_without_tls() {
+        let data = Data {
+            con_state: Connection::new(false),
+        };
+        data.con_state.write().await.starttls = false;
+        let cmd_data = CommandData {
+            tag: "",
+            command: Commands::Capability,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Capability { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 LOGINDISABLED SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities_secure() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        data.con_state.write().await.oauth = true;
+        let cmd_data = CommandData {
+            tag: "",
+            command: Commands::Capability,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Capability { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS AUTH=OAUTHBEARER AUTH=XOAUTH2 SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities_without_oauth() {
+        let caps = Capability {
+            data: &Data {
+                con_state: Connection::new(true),
+            },
+        };
         let cmd_data = CommandData {
             tag: "",
             command: Commands::Capability,
@@ -48,7 +166,44 @@ mod tests {
         assert_eq!(
             rx.next().await,
             Some(String::from(
-                "* CAPABILITY AUTH=PLAIN LOGINDISABLED UTF8=ONLY ENABLE IMAP4rev2 IMAP4rev1"
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities_compress() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        data.con_state.write().await.oauth = true;
+        data.con_state.write().await.state = State::Authenticated;
+        let cmd_data = CommandData {
+            tag: "",
+            command: Commands::Capability,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Capability { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS AUTH=OAUTHBEARER AUTH=XOAUTH2 SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1 COMPRESS=DEFLATE"
+            ))
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(" OK CAPABILITY completed"))
+        );
+
+        data.con_state.write().await.compression = Compression::Active;
+        let res = Capability { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* CAPABILITY AUTH=PLAIN AUTH=SCRAM-SHA-256 AUTH=SCRAM-SHA-256-PLUS AUTH=OAUTHBEARER AUTH=XOAUTH2 SASL-IR UTF8=ONLY ENABLE IDLE MOVE UIDPLUS MULTIAPPEND QUOTA QUOTA=RES-STORAGE QUOTA=RES-MESSAGE QUOTASET ACL RIGHTS=texk NAMESPACE LIST-EXTENDED LIST-STATUS SPECIAL-USE CREATE-SPECIAL-USE SEARCHRES CONDSTORE QRESYNC BINARY LITERAL- IMAP4rev2 IMAP4rev1"
             ))
         );
     }
@@ -41,7 +41,7 @@ impl Check<'_> {
 mod tests {
     use super::*;
     use crate::commands::{CommandData, Commands};
-    use crate::state::{Access, Connection};
+    use crate::state::{Access, Compression, Connection, SequenceMap};
     use futures::{channel::mpsc, StreamExt};
     use std::sync::Arc;
     use tokio::sync::RwLock;
@@ -53,9 +53,15 @@ mod tests {
                 con_state: Arc::new(RwLock::new(Connection {
                     state: State::Selected("INBOX".to_string(), Access::ReadWrite),
                     secure: true,
+                    starttls: false,
+                    oauth: false,
                     // TODO this may be invalid actuallly
                     username: None,
                     active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
                 })),
             },
         };
@@ -77,8 +83,14 @@ mod tests {
                 con_state: Arc::new(RwLock::new(Connection {
                     state: State::NotAuthenticated,
                     secure: true,
+                    starttls: false,
+                    oauth: false,
                     username: None,
                     active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
                 })),
             },
         };
@@ -1,11 +1,13 @@
 use crate::{
-    commands::{CommandData, Data},
-    state::{Access, State},
+    commands::{acl::mailbox_rights, expunge::expunge, CommandData, Data},
+    state::{Access, SequenceMap, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailStorage, Storage},
 };
-use erooster_core::backend::storage::{MailEntry, MailStorage, Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
-use tokio::fs;
 use tracing::{debug, instrument};
 
 pub struct Close<'a> {
@@ -13,10 +15,11 @@ pub struct Close<'a> {
 }
 
 impl Close<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
@@ -24,34 +27,26 @@ impl Close<'_> {
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
         let mut write_lock = self.data.con_state.write().await;
+        let connection = &mut *write_lock;
 
-        if let State::Selected(folder, access) = &write_lock.state {
-            if access == &Access::ReadOnly {
-                lines
-                    .send(format!("{} NO in read-only mode", command_data.tag))
-                    .await?;
-                return Ok(());
-            }
-
-            let mailbox_path =
-                storage.to_ondisk_path(folder.clone(), write_lock.username.clone().unwrap())?;
-
-            // We need to check all messages it seems?
-            let mails = storage
-                .list_cur(&mailbox_path)
-                .await
-                .into_iter()
-                .chain(storage.list_new(&mailbox_path).await);
-            for mail in mails {
-                debug!("Checking mails");
-                if mail.is_trashed() {
-                    let path = mail.path();
-                    fs::remove_file(path).await?;
-                }
+        if let State::Selected(folder, access) = &connection.state {
+            // Mails are only removed silently if the mailbox wasn't opened read-only
+            // and the user may still expunge it
+            let username = connection.username.clone().unwrap();
+            if access == &Access::ReadWrite
+                && mailbox_rights(&database, &storage, &username, folder)
+                    .await?
+                    .contains('e')
+            {
+                let mailbox_path = storage.to_ondisk_path(folder.clone(), username)?;
+                let expunged =
+                    expunge(&storage, &mailbox_path, &mut connection.sequence_map, None).await?;
+                debug!("Expunged {} mails", expunged.len());
             }
 
             {
-                write_lock.state = State::Authenticated;
+                connection.state = State::Authenticated;
+                connection.sequence_map = SequenceMap::default();
             };
             lines
                 .send(format!("{} OK CLOSE completed", command_data.tag))
@@ -70,7 +65,7 @@ impl Close<'_> {
 mod tests {
     use super::*;
     use crate::commands::{CommandData, Commands};
-    use crate::state::{Access, Connection};
+    use crate::state::{Access, Compression, Connection};
     use futures::{channel::mpsc, StreamExt};
     use std::sync::Arc;
     use tokio::sync::RwLock;
@@ -82,8 +77,14 @@ mod tests {
                 con_state: Arc::new(RwLock::new(Connection {
                     state: State::Selected("INBOX".to_string(), Access::ReadWrite),
                     secure: true,
+                    starttls: false,
+                    oauth: false,
                     username: Some(String::from("test")),
                     active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
                 })),
             },
         };
@@ -101,11 +102,11 @@ mod tests {
                 .unwrap(),
         );
         let storage = Arc::new(erooster_core::backend::storage::get_storage(
-            database,
+            Arc::clone(&database),
             Arc::clone(&config),
         ));
         let (mut tx, mut rx) = mpsc::unbounded();
-        let res = caps.exec(&mut tx, storage, &cmd_data).await;
+        let res = caps.exec(&mut tx, database, storage, &cmd_data).await;
         assert!(res.is_ok());
         assert_eq!(rx.next().await, Some(String::from("1 OK CLOSE completed")));
     }
@@ -117,8 +118,14 @@ mod tests {
                 con_state: Arc::new(RwLock::new(Connection {
                     state: State::Selected("INBOX".to_string(), Access::ReadOnly),
                     secure: true,
+                    starttls: false,
+                    oauth: false,
                     username: Some(String::from("test")),
                     active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
                 })),
             },
         };
@@ -137,15 +144,13 @@ mod tests {
                 .unwrap(),
         );
         let storage = Arc::new(erooster_core::backend::storage::get_storage(
-            database,
+            Arc::clone(&database),
             Arc::clone(&config),
         ));
-        let res = caps.exec(&mut tx, storage, &cmd_data).await;
+        let res = caps.exec(&mut tx, database, storage, &cmd_data).await;
         assert!(res.is_ok());
-        assert_eq!(
-            rx.next().await,
-            Some(String::from("1 NO in read-only mode"))
-        );
+        assert_eq!(rx.next().await, Some(String::from("1 OK CLOSE completed")));
+        assert_eq!(caps.data.con_state.read().await.state, State::Authenticated);
     }
 
     #[tokio::test]
@@ -155,8 +160,14 @@ mod tests {
                 con_state: Arc::new(RwLock::new(Connection {
                     state: State::NotAuthenticated,
                     secure: true,
+                    starttls: false,
+                    oauth: false,
                     username: None,
                     active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
                 })),
             },
         };
@@ -175,10 +186,10 @@ mod tests {
                 .unwrap(),
         );
         let storage = Arc::new(erooster_core::backend::storage::get_storage(
-            database,
+            Arc::clone(&database),
             Arc::clone(&config),
         ));
-        let res = caps.exec(&mut tx, storage, &cmd_data).await;
+        let res = caps.exec(&mut tx, database, storage, &cmd_data).await;
         assert!(res.is_ok());
         assert_eq!(rx.next().await, Some(String::from("1 NO invalid state")));
     }
@@ -0,0 +1,117 @@
+use crate::{
+    commands::{CommandData, Data},
+    state::{Compression, State},
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use tracing::instrument;
+
+pub struct Compress<'a> {
+    pub data: &'a Data,
+}
+
+impl Compress<'_> {
+    #[instrument(skip(self, lines, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let response = {
+            let mut write_lock = self.data.con_state.write().await;
+            if !matches!(command_data.arguments, [mechanism] if mechanism.is_atom("deflate")) {
+                format!("{} BAD Unsupported compression mechanism", command_data.tag)
+            } else if !matches!(
+                write_lock.state,
+                State::Authenticated | State::Selected(_, _)
+            ) {
+                format!("{} NO invalid state", command_data.tag)
+            } else if write_lock.compression != Compression::Off {
+                format!(
+                    "{} NO [COMPRESSIONACTIVE] DEFLATE active via COMPRESS",
+                    command_data.tag
+                )
+            } else {
+                // The server loop wraps the transport once it sees this state
+                write_lock.compression = Compression::Requested;
+                format!("{} OK DEFLATE active", command_data.tag)
+            }
+        };
+        lines.send(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{parsers::Argument, CommandData, Commands};
+    use crate::state::Connection;
+    use futures::{channel::mpsc, StreamExt};
+
+    #[tokio::test]
+    async fn test_compress() {
+        let data = Data {
+            con_state: Connection::new(false),
+        };
+        data.con_state.write().await.state = State::Authenticated;
+        let arguments = [Argument::Atom(String::from("DEFLATE"))];
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Compress,
+            arguments: &arguments,
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Compress { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 OK DEFLATE active")));
+        assert_eq!(
+            data.con_state.read().await.compression,
+            Compression::Requested
+        );
+
+        data.con_state.write().await.compression = Compression::Active;
+        let res = Compress { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "1 NO [COMPRESSIONACTIVE] DEFLATE active via COMPRESS"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compress_invalid() {
+        let data = Data {
+            con_state: Connection::new(false),
+        };
+        let arguments = [Argument::Atom(String::from("DEFLATE"))];
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Compress,
+            arguments: &arguments,
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Compress { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 NO invalid state")));
+
+        data.con_state.write().await.state = State::Authenticated;
+        let arguments = [Argument::Atom(String::from("GZIP"))];
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Compress,
+            arguments: &arguments,
+        };
+        let res = Compress { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 BAD Unsupported compression mechanism"))
+        );
+        assert_eq!(data.con_state.read().await.compression, Compression::Off);
+    }
+}
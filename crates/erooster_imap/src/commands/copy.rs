@@ -0,0 +1,258 @@
+use crate::{
+    commands::{
+        acl::{mailbox_owner, mailbox_rights, missing_rights},
+        parsers::parse_selected_range,
+        quota::{fits_quota, mails_size},
+        search::{mails_in_set, to_sequence_set},
+        CommandData, Data,
+    },
+    state::State,
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use nom::Finish;
+use std::{path::Path, sync::Arc};
+use tracing::{debug, error, instrument};
+
+pub struct Copy<'a> {
+    pub data: &'a Data,
+}
+
+impl Copy<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+        is_uid: bool,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let offset = if is_uid { 1 } else { 0 };
+        let arguments = &command_data.arguments;
+        if arguments.len() < 2 + offset {
+            lines
+                .send(format!("{} BAD Missing arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        let (state, username, sequence_map) = {
+            let read_lock = self.data.con_state.read().await;
+            (
+                read_lock.state.clone(),
+                read_lock.username.clone(),
+                read_lock.sequence_map.clone(),
+            )
+        };
+        if let State::Selected(folder, _) = state {
+            let username = username.unwrap();
+            let ranges = if let Ok(("", ranges)) =
+                parse_selected_range(arguments[offset].as_str().unwrap_or_default()).finish()
+            {
+                ranges
+            } else {
+                lines
+                    .send(format!("{} BAD Invalid sequence set", command_data.tag))
+                    .await?;
+                return Ok(());
+            };
+
+            let target_folder = if let Some(target_folder) = arguments[offset + 1].as_str() {
+                target_folder.to_string()
+            } else {
+                lines
+                    .send(format!("{} BAD Invalid mailbox name", command_data.tag))
+                    .await?;
+                return Ok(());
+            };
+            let target_path = storage.to_ondisk_path(target_folder.clone(), username.clone())?;
+            if !target_path.exists() {
+                lines
+                    .send(format!(
+                        "{} NO [TRYCREATE] Target mailbox does not exist",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            let rights = mailbox_rights(&database, &storage, &username, &target_folder).await?;
+            if let Some(response) = missing_rights(command_data.tag, &rights, "i") {
+                lines.send(response).await?;
+                return Ok(());
+            }
+
+            let mailbox_path = storage.to_ondisk_path(folder, username.clone())?;
+            let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+            let mails: Vec<MailEntryType> = mails_in_set(mails, &sequence_map, &ranges, is_uid)
+                .into_iter()
+                .map(|(_, mail)| mail)
+                .collect();
+            // The copies count towards the quota of the owner of the target mailbox
+            let (target_owner, _) = mailbox_owner(&storage, &username, &target_folder)?;
+            if !fits_quota(
+                &database,
+                &storage,
+                target_owner,
+                mails_size(&mails).await,
+                u64::try_from(mails.len())?,
+            )
+            .await?
+            {
+                lines
+                    .send(format!(
+                        "{} NO [OVERQUOTA] Quota exceeded",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            let source_uids: Vec<i64> = mails.iter().map(MailEntry::uid).collect();
+            let copied_ids =
+                match copy_messages(&storage, &mailbox_path, &target_path, &mails).await {
+                    Ok(copied_ids) => copied_ids,
+                    Err(error) => {
+                        error!("Failed to copy messages: {}", error);
+                        let name = if is_uid { "UID COPY" } else { "COPY" };
+                        lines
+                            .send(format!("{} NO {} failed", command_data.tag, name))
+                            .await?;
+                        return Ok(());
+                    }
+                };
+
+            let copy_uid = if copied_ids.is_empty() {
+                String::new()
+            } else {
+                let target_mails: Vec<MailEntryType> = storage.list_all(&target_path).await;
+                let target_uids = copied_ids
+                    .iter()
+                    .filter_map(|id| target_mails.iter().find(|mail| mail.id() == id))
+                    .map(MailEntry::uid)
+                    .collect::<Vec<_>>();
+                // A COPYUID with differently sized sets would be malformed
+                if target_uids.len() == source_uids.len() {
+                    let uid_validity = storage.get_uid_validity(&target_path).await?;
+                    format!(
+                        "[COPYUID {} {} {}] ",
+                        uid_validity,
+                        to_sequence_set(&source_uids),
+                        to_sequence_set(&target_uids)
+                    )
+                } else {
+                    String::new()
+                }
+            };
+            if is_uid {
+                lines
+                    .send(format!(
+                        "{} OK {}UID COPY completed",
+                        command_data.tag, copy_uid
+                    ))
+                    .await?;
+            } else {
+                lines
+                    .send(format!(
+                        "{} OK {}COPY completed",
+                        command_data.tag, copy_uid
+                    ))
+                    .await?;
+            }
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies all mails to the target or none of them if one fails and returns the new ids in order
+#[instrument(skip(storage, mailbox_path, target_path, mails))]
+async fn copy_messages(
+    storage: &Storage,
+    mailbox_path: &Path,
+    target_path: &Path,
+    mails: &[MailEntryType],
+) -> color_eyre::eyre::Result<Vec<String>> {
+    let mut copied_ids = Vec::with_capacity(mails.len());
+    for mail in mails {
+        match storage.copy_to(mailbox_path, mail.id(), target_path).await {
+            Ok(new_id) => {
+                debug!("Copied {} to {}", mail.id(), new_id);
+                copied_ids.push(new_id);
+            }
+            Err(error) => {
+                // A failed COPY has to leave the target mailbox unchanged
+                for copied_id in &copied_ids {
+                    if let Err(error) = storage.delete(target_path, copied_id).await {
+                        error!("Failed to roll back the copy {}: {}", copied_id, error);
+                    }
+                }
+                return Err(error);
+            }
+        }
+    }
+    Ok(copied_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{parsers::Argument, CommandData, Commands};
+    use crate::state::{Compression, Connection, SequenceMap};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_no_selected() {
+        let copy = Copy {
+            data: &Data {
+                con_state: Arc::new(RwLock::new(Connection {
+                    state: State::Authenticated,
+                    secure: true,
+                    starttls: false,
+                    oauth: false,
+                    username: Some(String::from("test")),
+                    active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
+                })),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Copy,
+            arguments: &[
+                Argument::Atom(String::from("1:*")),
+                Argument::Atom(String::from("Archive")),
+            ],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(erooster_core::backend::storage::get_storage(
+            Arc::clone(&database),
+            Arc::clone(&config),
+        ));
+        let res = copy
+            .exec(&mut tx, database, storage, &cmd_data, false)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 NO invalid state")));
+    }
+}
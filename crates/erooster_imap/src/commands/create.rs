@@ -1,7 +1,18 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::{MailStorage, Storage};
+use crate::commands::{
+    list::{is_reserved, is_special_use},
+    parsers::create_arguments,
+    CommandData, Data,
+};
+use erooster_core::{
+    backend::{
+        database::{Database, DB},
+        storage::{MailStorage, Storage},
+    },
+    config::Config,
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
-use std::sync::Arc;
+use nom::Finish;
+use std::{path::Path, sync::Arc};
 use tracing::{error, instrument};
 
 pub struct Create<'a> {
@@ -18,35 +29,41 @@ impl Create<'_> {
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let arguments = &command_data.arguments;
-        assert!(arguments.len() == 1);
-        if arguments.len() == 1 {
-            let folder = arguments[0].replace('/', ".");
+        let create_args = command_data.joined_arguments(0);
+        if let Ok(("", (folder, special_use))) = create_arguments(&create_args).finish() {
+            if special_use.len() > 1 || !special_use.iter().all(|x| is_special_use(x)) {
+                lines
+                    .send(format!(
+                        "{} NO [USE] Unsupported special-use attribute",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            // Mailboxes of other users can't be created and own ones would be hidden by them
+            if is_reserved(&folder) {
+                lines
+                    .send(format!(
+                        "{} NO [CANNOT] Mailboxes can't be created in the shared namespace",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            let folder = folder.replace('/', ".");
 
             let mailbox_path = storage.to_ondisk_path(
                 folder.clone(),
                 self.data.con_state.read().await.username.clone().unwrap(),
             )?;
             let folder = storage.to_ondisk_path_name(folder)?;
+            let special_use = special_use
+                .first()
+                .copied()
+                .or_else(|| special_use_for_name(&folder));
 
-            match storage.create_dirs(&mailbox_path) {
-                Ok(_) => {
-                    if folder.to_lowercase() == ".sent" {
-                        storage.add_flag(&mailbox_path, "\\Sent").await?;
-                        storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                    } else if folder.to_lowercase() == ".junk" {
-                        storage.add_flag(&mailbox_path, "\\Junk").await?;
-                        storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                    } else if folder.to_lowercase() == ".drafts" {
-                        storage.add_flag(&mailbox_path, "\\Drafts").await?;
-                        storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                    } else if folder.to_lowercase() == ".archive" {
-                        storage.add_flag(&mailbox_path, "\\Archive").await?;
-                        storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                    } else if folder.to_lowercase() == ".trash" {
-                        storage.add_flag(&mailbox_path, "\\Trash").await?;
-                        storage.add_flag(&mailbox_path, "\\Subscribed").await?;
-                    }
+            match create_mailbox(&storage, &mailbox_path, special_use).await {
+                Ok(()) => {
                     lines
                         .send(format!("{} OK CREATE completed", command_data.tag))
                         .await?;
@@ -70,3 +87,68 @@ impl Create<'_> {
         Ok(())
     }
 }
+
+/// Guesses the special-use attribute for well known folder names like `.Sent`
+pub fn special_use_for_name(folder: &str) -> Option<&'static str> {
+    match folder.to_lowercase().as_str() {
+        ".sent" => Some("\\Sent"),
+        ".junk" => Some("\\Junk"),
+        ".drafts" => Some("\\Drafts"),
+        ".archive" => Some("\\Archive"),
+        ".trash" => Some("\\Trash"),
+        _ => None,
+    }
+}
+
+/// Creates the mailbox and subscribes to it.
+///
+/// Mailboxes with a special-use attribute get it added to their flags.
+#[instrument(skip(storage, mailbox_path))]
+pub async fn create_mailbox(
+    storage: &Storage,
+    mailbox_path: &Path,
+    special_use: Option<&str>,
+) -> color_eyre::eyre::Result<()> {
+    storage.create_dirs(mailbox_path)?;
+    if let Some(special_use) = special_use {
+        storage.add_flag(mailbox_path, special_use).await?;
+    }
+    storage.add_flag(mailbox_path, "\\Subscribed").await?;
+    Ok(())
+}
+
+/// Creates the configured default folders of the user on their first login
+///
+/// Folders the user deletes later on don't come back.
+#[instrument(skip(database, storage, config))]
+pub async fn provision_default_folders(
+    database: &DB,
+    storage: &Storage,
+    config: &Config,
+    username: &str,
+) -> color_eyre::eyre::Result<()> {
+    if database.is_provisioned(username).await? {
+        return Ok(());
+    }
+    for default_folder in &config.mail.default_folders {
+        let mailbox_path = storage.to_ondisk_path(
+            default_folder.name.replace('/', "."),
+            username.to_string(),
+        )?;
+        if !mailbox_path.exists() {
+            create_mailbox(storage, &mailbox_path, Some(&default_folder.special_use)).await?;
+        }
+    }
+    database.set_provisioned(username).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_special_use_for_name() {
+        assert_eq!(special_use_for_name(".Sent"), Some("\\Sent"));
+        assert_eq!(special_use_for_name(".Projects"), None);
+    }
+}
@@ -1,36 +1,60 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::{MailStorage, Storage};
+use crate::commands::{
+    acl::{mailbox_owner, mailbox_rights, missing_rights},
+    CommandData, Data,
+};
+use erooster_core::backend::{
+    database::{Database, DB},
+    storage::{MailStorage, Storage},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
-use tokio::fs;
-use tracing::instrument;
+use tracing::{error, instrument};
 
 pub struct Delete<'a> {
     pub data: &'a Data,
 }
 
 impl Delete<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let arguments = &command_data.arguments;
-        assert!(arguments.len() == 1);
-        if arguments.len() == 1 {
-            let folder = arguments[0].replace('/', ".");
-            let mailbox_path = storage.to_ondisk_path(
-                folder.clone(),
-                self.data.con_state.read().await.username.clone().unwrap(),
-            )?;
-            // TODO error handling
+        let folder = match command_data.arguments {
+            [folder] => folder.as_str(),
+            _ => None,
+        };
+        if let Some(folder) = folder {
+            let username = self.data.con_state.read().await.username.clone().unwrap();
+            // Shared mailboxes are resolved to the directory of their owner
+            let mailbox_path = storage.to_ondisk_path(folder.to_string(), username.clone())?;
+            let (owner, ondisk_name) = mailbox_owner(&storage, &username, folder)?;
+            let rights = if mailbox_path.exists() {
+                mailbox_rights(&database, &storage, &username, folder).await?
+            } else {
+                String::new()
+            };
+            if let Some(response) = missing_rights(command_data.tag, &rights, "x") {
+                lines.send(response).await?;
+                return Ok(());
+            }
             // TODO all the extra rules when to not delete
-            fs::remove_dir_all(mailbox_path).await?;
+            if let Err(e) = storage.delete_mailbox(&mailbox_path).await {
+                error!("Failed to delete folder: {}", e);
+                lines
+                    .send(format!("{} NO DELETE failure", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            // A new mailbox with the same name must not inherit the old rights.
+            // Children are separate folders which stay so they keep their rights.
+            database.delete_acl(owner, &ondisk_name, None).await?;
             lines
                 .send(format!("{} OK DELETE completed", command_data.tag))
                 .await?;
@@ -22,13 +22,20 @@ impl Enable<'_> {
         let mut write_lock = self.data.con_state.write().await;
 
         for arg in command_data.arguments.iter() {
-            if arg == &"UTF8=ACCEPT" {
+            if arg.is_atom("UTF8=ACCEPT") {
                 write_lock.active_capabilities.push(Capabilities::UTF8);
                 lines.feed(format!("* ENABLED {}", arg)).await?;
+            } else if arg.is_atom("CONDSTORE") {
+                write_lock.active_capabilities.push(Capabilities::CondStore);
+                lines.feed(format!("* ENABLED {}", arg)).await?;
+            } else if arg.is_atom("QRESYNC") {
+                // QRESYNC implies CONDSTORE
+                write_lock.active_capabilities.push(Capabilities::QResync);
+                lines.feed(format!("* ENABLED {}", arg)).await?;
             } else {
                 write_lock
                     .active_capabilities
-                    .push(Capabilities::Other((*arg).to_string()));
+                    .push(Capabilities::Other(arg.to_string()));
             }
         }
         lines.feed(format!("{} OK", command_data.tag)).await?;
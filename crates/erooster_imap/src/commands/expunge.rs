@@ -0,0 +1,201 @@
+use crate::{
+    commands::{
+        acl::{mailbox_rights, missing_rights},
+        noop::send_expunged,
+        parsers::{parse_selected_range, Argument, Range},
+        search::in_set,
+        CommandData, Data,
+    },
+    state::{Access, SequenceMap, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use nom::Finish;
+use std::{path::Path, sync::Arc};
+use tracing::{debug, instrument};
+
+pub struct Expunge<'a> {
+    pub data: &'a Data,
+}
+
+impl Expunge<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+        is_uid: bool,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let (state, username, qresync) = {
+            let read_lock = self.data.con_state.read().await;
+            (
+                read_lock.state.clone(),
+                read_lock.username.clone(),
+                read_lock.qresync_enabled(),
+            )
+        };
+        if let State::Selected(folder, access) = state {
+            if access == Access::ReadOnly {
+                lines
+                    .send(format!("{} NO in read-only mode", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            let username = username.unwrap();
+            let rights = mailbox_rights(&database, &storage, &username, &folder).await?;
+            if let Some(response) = missing_rights(command_data.tag, &rights, "e") {
+                lines.send(response).await?;
+                return Ok(());
+            }
+            // UID EXPUNGE only removes the deleted mails in the given uid set
+            let ranges = if is_uid {
+                if let Some(Ok(("", ranges))) = command_data
+                    .arguments
+                    .get(1)
+                    .and_then(Argument::as_str)
+                    .map(|x| parse_selected_range(x).finish())
+                {
+                    Some(ranges)
+                } else {
+                    lines
+                        .send(format!("{} BAD Invalid sequence set", command_data.tag))
+                        .await?;
+                    return Ok(());
+                }
+            } else {
+                None
+            };
+
+            let mailbox_path = storage.to_ondisk_path(folder, username)?;
+            let expunged = {
+                let mut write_lock = self.data.con_state.write().await;
+                expunge(
+                    &storage,
+                    &mailbox_path,
+                    &mut write_lock.sequence_map,
+                    ranges.as_deref(),
+                )
+                .await?
+            };
+            send_expunged(lines, &expunged, qresync).await?;
+
+            if is_uid {
+                lines
+                    .feed(format!("{} OK UID EXPUNGE completed", command_data.tag))
+                    .await?;
+            } else {
+                lines
+                    .feed(format!("{} OK EXPUNGE completed", command_data.tag))
+                    .await?;
+            }
+            lines.flush().await?;
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes all mails flagged as `\Deleted` and returns the sequence numbers and uids to send
+/// EXPUNGE responses for in the order they have to be sent.
+///
+/// If a uid set is given only the mails in it are removed.
+#[instrument(skip(storage, mailbox_path, sequence_map, uid_ranges))]
+pub async fn expunge(
+    storage: &Storage,
+    mailbox_path: &Path,
+    sequence_map: &mut SequenceMap,
+    uid_ranges: Option<&[Range]>,
+) -> color_eyre::eyre::Result<Vec<(usize, i64)>> {
+    let mails: Vec<MailEntryType> = storage.list_all(mailbox_path).await;
+    let mut expunged_uids = Vec::new();
+    for mail in mails {
+        if !mail.is_trashed() {
+            continue;
+        }
+        if let Some(uid_ranges) = uid_ranges {
+            // The client can only name mails it knows about
+            match sequence_map.sequence(mail.uid()) {
+                Some(sequence) if in_set(sequence_map, uid_ranges, sequence, mail.uid(), true) => {}
+                _ => continue,
+            }
+        }
+        debug!("Expunging {}", mail.id());
+        storage.delete(mailbox_path, mail.id()).await?;
+        expunged_uids.push(mail.uid());
+    }
+
+    // Going from the highest sequence number down keeps the lower ones valid
+    expunged_uids.sort_unstable();
+    Ok(expunged_uids
+        .iter()
+        .rev()
+        .filter_map(|uid| sequence_map.remove(*uid).map(|sequence| (sequence, *uid)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{CommandData, Commands};
+    use crate::state::{Compression, Connection};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_selected_ro() {
+        let expunge = Expunge {
+            data: &Data {
+                con_state: Arc::new(RwLock::new(Connection {
+                    state: State::Selected("INBOX".to_string(), Access::ReadOnly),
+                    secure: true,
+                    starttls: false,
+                    oauth: false,
+                    username: Some(String::from("test")),
+                    active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
+                })),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Expunge,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(erooster_core::backend::storage::get_storage(
+            Arc::clone(&database),
+            Arc::clone(&config),
+        ));
+        let res = expunge
+            .exec(&mut tx, database, storage, &cmd_data, false)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 NO in read-only mode"))
+        );
+    }
+}
@@ -1,17 +1,23 @@
 use crate::{
     commands::{
+        acl::{mailbox_rights, missing_rights},
+        mime::{binary_content, body_structure, envelope, has_known_encoding, section_content},
         parsers::{
-            fetch_arguments, parse_selected_range, FetchArguments, FetchAttributes, Range,
-            RangeEnd, SectionText,
+            fetch_arguments, parse_selected_range, Argument, FetchArguments, FetchAttributes,
+            Section, SectionText,
         },
+        search::{in_ranges, mails_in_set, to_sequence_set},
         CommandData, Data,
     },
-    state::State,
+    state::{Access, Capabilities, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
 };
-use erooster_core::backend::storage::{MailEntry, MailEntryType, MailStorage, Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use nom::{error::convert_error, Finish};
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 use tracing::{debug, error, instrument};
 
 pub struct Fetch<'a> {
@@ -20,100 +26,172 @@ pub struct Fetch<'a> {
 
 impl Fetch<'_> {
     #[allow(clippy::too_many_lines)]
-    #[instrument(skip(self, lines, command_data, storage))]
+    #[instrument(skip(self, lines, command_data, database, storage))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         command_data: &CommandData<'_>,
+        database: DB,
         storage: Arc<Storage>,
         is_uid: bool,
     ) -> color_eyre::eyre::Result<()>
     where
-        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+        S: Sink<String, Error = SendError>
+            + Sink<Vec<u8>, Error = SendError>
+            + std::marker::Unpin
+            + std::marker::Send,
     {
         let offset = if is_uid { 1 } else { 0 };
         // TODO handle the various request types defined in https://www.rfc-editor.org/rfc/rfc9051.html#name-fetch-command
-        if let State::Selected(folder, _) = &self.data.con_state.read().await.state {
-            let folder = folder.replace('/', ".");
-            let mailbox_path = storage.to_ondisk_path(
-                folder.clone(),
-                self.data.con_state.read().await.username.clone().unwrap(),
-            )?;
+        let (state, username, sequence_map, qresync) = {
+            let read_lock = self.data.con_state.read().await;
+            (
+                read_lock.state.clone(),
+                read_lock.username.clone(),
+                read_lock.sequence_map.clone(),
+                read_lock.qresync_enabled(),
+            )
+        };
+        if let State::Selected(folder, access) = state {
+            let (arguments, modifiers) = match fetch_modifiers(command_data.arguments) {
+                // VANISHED is only allowed for UID FETCH with CHANGEDSINCE once QRESYNC is enabled
+                Some((_, modifiers))
+                    if modifiers.vanished
+                        && !(is_uid && qresync && modifiers.changed_since.is_some()) =>
+                {
+                    lines
+                        .send(format!("{} BAD VANISHED not allowed", command_data.tag))
+                        .await?;
+                    return Ok(());
+                }
+                Some(split) => split,
+                None => {
+                    lines
+                        .send(format!("{} BAD Invalid fetch modifiers", command_data.tag))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let username = username.unwrap();
+            // The rights might have been revoked since the mailbox was selected
+            let rights = mailbox_rights(&database, &storage, &username, &folder).await?;
+            if let Some(response) = missing_rights(command_data.tag, &rights, "r") {
+                lines.send(response).await?;
+                return Ok(());
+            }
+            let mailbox_path = storage.to_ondisk_path(folder, username)?;
             let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
 
-            let arguments_borrow = command_data.arguments[offset];
+            let arguments_borrow = arguments[offset].as_str().unwrap_or_default();
             let range = parse_selected_range(arguments_borrow).finish();
             debug!("Range: {:?}", range);
             match range {
                 Ok((_, range)) => {
-                    let mut filtered_mails: Vec<MailEntryType> = mails
-                        .into_iter()
-                        .filter(|mail| {
-                            for range in &range {
-                                match range {
-                                    Range::Single(id) => {
-                                        if &mail.uid() == id {
-                                            return true;
-                                        }
-                                    }
-                                    Range::Range(start, end) => match end {
-                                        RangeEnd::End(end_int) => {
-                                            if &mail.uid() >= start && &mail.uid() <= end_int {
-                                                return true;
-                                            }
-                                        }
-                                        RangeEnd::All => {
-                                            if &mail.uid() >= start {
-                                                return true;
-                                            }
-                                        }
-                                    },
-                                }
-                            }
-                            false
-                        })
-                        .collect::<Vec<MailEntryType>>();
+                    let filtered_mails = mails_in_set(mails, &sequence_map, &range, is_uid);
 
-                    let fetch_args = command_data.arguments[1 + offset..].to_vec().join(" ");
-                    let fetch_args_str = &fetch_args[1..fetch_args.len() - 1];
+                    let fetch_args = arguments
+                        .get(1 + offset..)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let fetch_args_str = fetch_args.as_str();
                     debug!("Fetch args: {}", fetch_args_str);
 
-                    filtered_mails.sort_by_key(MailEntry::uid);
                     match fetch_arguments(fetch_args_str).finish() {
                         Ok((_, args)) => {
                             debug!("Parsed Fetch args: {:?}", args);
-                            for mut mail in filtered_mails {
+                            let condstore = {
+                                let mut write_lock = self.data.con_state.write().await;
+                                // Asking for modseqs enables CONDSTORE for the rest of the session
+                                if !write_lock.condstore_enabled()
+                                    && (modifiers.changed_since.is_some() || requests_modseq(&args))
+                                {
+                                    write_lock.active_capabilities.push(Capabilities::CondStore);
+                                }
+                                write_lock.condstore_enabled()
+                            };
+                            if let (true, Some(changed_since)) =
+                                (modifiers.vanished, modifiers.changed_since)
+                            {
+                                let vanished: Vec<i64> = storage
+                                    .get_vanished(&mailbox_path, changed_since)
+                                    .await?
+                                    .into_iter()
+                                    .filter(|uid| in_ranges(&range, *uid, sequence_map.max_uid()))
+                                    .collect();
+                                if !vanished.is_empty() {
+                                    lines
+                                        .feed(format!(
+                                            "* VANISHED (EARLIER) {}",
+                                            to_sequence_set(&vanished)
+                                        ))
+                                        .await?;
+                                }
+                            }
+                            let set_seen = sets_seen(&args)
+                                && access == Access::ReadWrite
+                                && rights.contains('s');
+                            for (sequence, mut mail) in filtered_mails {
+                                if modifiers
+                                    .changed_since
+                                    .map_or(false, |changed_since| mail.modseq() <= changed_since)
+                                {
+                                    continue;
+                                }
+                                if !decodable(&args, &mut mail) {
+                                    lines
+                                        .send(format!(
+                                            "{} NO [UNKNOWN-CTE] Unable to decode the content transfer encoding",
+                                            command_data.tag
+                                        ))
+                                        .await?;
+                                    return Ok(());
+                                }
                                 let uid = mail.uid();
-                                if let Some(resp) = generate_response(args.clone(), &mut mail) {
-                                    if is_uid {
-                                        if resp.contains("UID") {
-                                            lines
-                                                .feed(format!("* {} FETCH ({})", uid, resp))
-                                                .await?;
-                                        } else {
-                                            lines
-                                                .feed(format!(
-                                                    "* {} FETCH (UID {} {})",
-                                                    uid, uid, resp
-                                                ))
-                                                .await?;
-                                        }
+                                let mut flags_changed = false;
+                                if set_seen && !mail.is_seen() {
+                                    mark_seen(&storage, &mailbox_path, &mail)?;
+                                    if let Some(updated) =
+                                        storage.find(&mailbox_path, mail.id()).await
+                                    {
+                                        mail = updated;
+                                        flags_changed = true;
+                                    }
+                                }
+                                if let Some(mut resp) = generate_response(args.clone(), &mut mail) {
+                                    // The client has to learn about the flags we changed
+                                    if flags_changed && !requests_flags(&args) {
+                                        resp.push(b' ');
+                                        resp.extend_from_slice(flags_response(&mail).as_bytes());
+                                    }
+                                    if condstore && !requests_modseq(&args) {
+                                        resp.extend_from_slice(
+                                            format!(" MODSEQ ({})", mail.modseq()).as_bytes(),
+                                        );
+                                    }
+                                    let mut response = if is_uid && !requests_uid(&args) {
+                                        format!("* {} FETCH (UID {} ", sequence, uid)
                                     } else {
-                                        lines.feed(format!("* {} FETCH ({})", uid, resp)).await?;
+                                        format!("* {} FETCH (", sequence)
                                     }
+                                    .into_bytes();
+                                    response.append(&mut resp);
+                                    response.push(b')');
+                                    lines.feed(response).await?;
                                 }
                             }
 
                             if is_uid {
                                 lines
-                                    .feed(format!("{} Ok UID FETCH completed", command_data.tag))
+                                    .send(format!("{} Ok UID FETCH completed", command_data.tag))
                                     .await?;
                             } else {
                                 lines
-                                    .feed(format!("{} Ok FETCH completed", command_data.tag))
+                                    .send(format!("{} Ok FETCH completed", command_data.tag))
                                     .await?;
                             }
-                            lines.flush().await?;
                         }
                         Err(e) => {
                             error!(
@@ -138,46 +216,99 @@ impl Fetch<'_> {
             }
         } else {
             lines
-                .feed(format!(
+                .send(format!(
                     "{} NO [TRYCREATE] No mailbox selected",
                     command_data.tag
                 ))
                 .await?;
-            lines.flush().await?;
         }
         Ok(())
     }
 }
 
+/// Generates the data items of the `FETCH` response.
+///
+/// They are raw octets as literals may contain any data.
 #[instrument(skip(arg, mail))]
-pub fn generate_response(arg: FetchArguments, mail: &mut MailEntryType) -> Option<String> {
+pub fn generate_response(arg: FetchArguments, mail: &mut MailEntryType) -> Option<Vec<u8>> {
     match arg {
         FetchArguments::Single(single_arg) => generate_response_for_attributes(single_arg, mail),
         FetchArguments::List(args) => {
-            let mut resp = String::new();
+            let mut resp = Vec::new();
             for arg in args {
-                if let Some(extra_resp) = generate_response_for_attributes(arg, mail) {
-                    if resp.is_empty() {
-                        resp = extra_resp;
-                    } else {
-                        resp = format!("{} {}", resp, extra_resp);
+                if let Some(mut extra_resp) = generate_response_for_attributes(arg, mail) {
+                    if !resp.is_empty() {
+                        resp.push(b' ');
                     }
+                    resp.append(&mut extra_resp);
                 }
             }
-            debug!("List Response: {}", resp);
+            debug!("List Response: {}", String::from_utf8_lossy(&resp));
             Some(resp)
         }
         _ => None,
     }
 }
 
+/// Generates the `FLAGS` data item of the mail
+pub fn flags_response(mail: &MailEntryType) -> String {
+    let mut flags = String::new();
+    if mail
+        .path()
+        .clone()
+        .into_os_string()
+        .into_string()
+        .unwrap()
+        .contains("new")
+    {
+        flags.push_str("\\Recent");
+    }
+    if mail.is_draft() {
+        if flags.is_empty() {
+            flags = String::from("\\Draft");
+        } else {
+            flags.push_str(" \\Draft");
+        }
+    }
+    if mail.is_flagged() {
+        if flags.is_empty() {
+            flags = String::from("\\Flagged");
+        } else {
+            flags.push_str(" \\Flagged");
+        }
+    }
+    if mail.is_seen() {
+        if flags.is_empty() {
+            flags = String::from("\\Seen");
+        } else {
+            flags.push_str(" \\Seen");
+        }
+    }
+    if mail.is_replied() {
+        if flags.is_empty() {
+            flags = String::from("\\Answered");
+        } else {
+            flags.push_str(" \\Answered");
+        }
+    }
+    if mail.is_trashed() {
+        if flags.is_empty() {
+            flags = String::from("\\Deleted");
+        } else {
+            flags.push_str(" \\Deleted");
+        }
+    }
+
+    format!("FLAGS ({})", flags)
+}
+
 #[allow(clippy::too_many_lines)]
 #[instrument(skip(attr, mail))]
 fn generate_response_for_attributes(
     attr: FetchAttributes,
     mail: &mut MailEntryType,
-) -> Option<String> {
-    match attr {
+) -> Option<Vec<u8>> {
+    let response = match attr {
         FetchAttributes::RFC822Header => {
             if let Ok(headers_vec) = mail.headers() {
                 let headers = headers_vec
@@ -191,56 +322,7 @@ fn generate_response_for_attributes(
                 Some(String::from("RFC822.HEADER\r\n"))
             }
         }
-        FetchAttributes::Flags => {
-            let mut flags = String::new();
-            if mail
-                .path()
-                .clone()
-                .into_os_string()
-                .into_string()
-                .unwrap()
-                .contains("new")
-            {
-                flags.push_str("\\Recent");
-            }
-            if mail.is_draft() {
-                if flags.is_empty() {
-                    flags = String::from("\\Draft");
-                } else {
-                    flags.push_str(" \\Draft");
-                }
-            }
-            if mail.is_flagged() {
-                if flags.is_empty() {
-                    flags = String::from("\\Flagged");
-                } else {
-                    flags.push_str(" \\Flagged");
-                }
-            }
-            if mail.is_seen() {
-                if flags.is_empty() {
-                    flags = String::from("\\Seen");
-                } else {
-                    flags.push_str(" \\Seen");
-                }
-            }
-            if mail.is_replied() {
-                if flags.is_empty() {
-                    flags = String::from("\\Answered");
-                } else {
-                    flags.push_str(" \\Answered");
-                }
-            }
-            if mail.is_trashed() {
-                if flags.is_empty() {
-                    flags = String::from("\\Deleted");
-                } else {
-                    flags.push_str(" \\Deleted");
-                }
-            }
-
-            Some(format!("FLAGS ({})", flags))
-        }
+        FetchAttributes::Flags => Some(flags_response(mail)),
         FetchAttributes::RFC822Size => {
             if let Ok(parsed) = mail.parsed() {
                 let size = parsed.raw_bytes.len();
@@ -250,113 +332,301 @@ fn generate_response_for_attributes(
             }
         }
         FetchAttributes::Uid => Some(format!("UID {}", mail.uid())),
-        FetchAttributes::BodySection(section_text, range) => {
-            Some(body(section_text, range, mail, true))
+        FetchAttributes::ModSeq => Some(format!("MODSEQ ({})", mail.modseq())),
+        FetchAttributes::Envelope => mail
+            .parsed()
+            .ok()
+            .map(|parsed| format!("ENVELOPE {}", envelope(&parsed))),
+        FetchAttributes::BodyStructure => mail
+            .parsed()
+            .ok()
+            .map(|parsed| format!("BODYSTRUCTURE {}", body_structure(&parsed))),
+        FetchAttributes::BodySection(section, range)
+        | FetchAttributes::BodyPeek(section, range) => {
+            let content = mail
+                .parsed()
+                .ok()
+                .and_then(|parsed| section_content(parsed.raw_bytes, &section));
+            let name = format!("BODY[{}]", section_name(&section));
+            return Some(literal_item(&name, "", range, content));
+        }
+        FetchAttributes::Binary(section, range) | FetchAttributes::BinaryPeek(section, range) => {
+            let name = format!("BINARY[{}]", section_name(&section));
+            return Some(literal_item(&name, "~", range, binary_data(&section, mail)));
         }
-        FetchAttributes::BodyPeek(section_text, range) => {
-            Some(body(section_text, range, mail, false))
+        FetchAttributes::BinarySize(section) => {
+            // The size has to match the literal we send for BINARY
+            let size = binary_data(&section, mail).map_or(0, |data| data.len());
+            Some(format!("BINARY.SIZE[{}] {}", section_name(&section), size))
         }
         _ => None,
-    }
+    };
+    response.map(String::into_bytes)
 }
 
-#[allow(clippy::cast_possible_truncation)]
-#[instrument(skip(section_text, range, mail))]
-fn body(
-    section_text: Option<SectionText>,
+/// Generates a data item with the content as literal.
+///
+/// The prefix is `~` for a literal8 which may contain NUL octets.
+fn literal_item(
+    name: &str,
+    prefix: &str,
     range: Option<(u64, u64)>,
-    mail: &mut MailEntryType,
-    seen: bool,
-) -> String {
-    if let Some(section_text) = section_text {
-        match section_text {
-            super::parsers::SectionText::Header => {
-                // TODO implement
-                String::from("BODY[HEADER] NIL\r\n")
-            }
-            super::parsers::SectionText::Text => {
-                if let Some((start, end)) = range {
-                    if let Ok(body) = mail.parsed() {
-                        if let Ok(body_text) = body.get_body_raw() {
-                            let end = if body_text.len() < (end as usize) {
-                                body_text.len()
-                            } else {
-                                end as usize
-                            };
-                            let body_text = body_text.get((start as usize)..end).unwrap_or(&[]);
-                            let body_text = String::from_utf8_lossy(body_text);
-                            format!("BODY[] {{{}}}\r\n{}", body_text.as_bytes().len(), body_text)
-                        } else {
-                            String::from("BODY[TEXT] NIL\r\n")
-                        }
-                    } else {
-                        String::from("BODY[TEXT] NIL\r\n")
-                    }
-                } else if let Ok(body) = mail.parsed() {
-                    if let Ok(body_text) = body.get_body_raw() {
-                        let body_text = String::from_utf8_lossy(&body_text);
-                        format!("BODY[] {{{}}}\r\n{}", body_text.as_bytes().len(), body_text)
-                    } else {
-                        String::from("BODY[TEXT] NIL\r\n")
-                    }
-                } else {
-                    String::from("BODY[TEXT] NIL\r\n")
-                }
-            }
-            super::parsers::SectionText::HeaderFields(headers_requested_vec) => {
-                if let Ok(headers_vec) = mail.headers() {
-                    let lower_headers_requested_vec: Vec<_> = headers_requested_vec
-                        .iter()
-                        .map(|header| header.to_lowercase())
-                        .collect();
-                    let headers = headers_vec
-                        .iter()
-                        .filter(|header| {
-                            lower_headers_requested_vec.contains(&header.get_key().to_lowercase())
-                        })
-                        .map(|header| format!("{}: {}", header.get_key(), header.get_value()))
-                        .collect::<Vec<_>>()
-                        .join("\r\n");
-                    format!(
-                        "BODY[HEADER.FIELDS ({})] {{{}}}\r\n{}",
-                        headers_requested_vec.join(" "),
-                        headers.as_bytes().len(),
-                        headers
-                    )
-                } else {
-                    format!(
-                        "BODY[HEADER.FIELDS ({})] NIL\r\n",
-                        headers_requested_vec.join(" "),
-                    )
-                }
+    content: Option<Vec<u8>>,
+) -> Vec<u8> {
+    let content = if let Some(content) = content {
+        content
+    } else {
+        return format!("{} NIL", name).into_bytes();
+    };
+    let (name, data) = match range {
+        Some((origin, length)) => (
+            format!("{}<{}>", name, origin),
+            partial(&content, origin, length),
+        ),
+        None => (name.to_string(), content.as_slice()),
+    };
+    let mut item = format!("{} {}{{{}}}\r\n", name, prefix, data.len()).into_bytes();
+    item.extend_from_slice(data);
+    item
+}
+
+/// The decoded content of the part in the form it is sent to the client
+fn binary_data(section: &Section, mail: &mut MailEntryType) -> Option<Vec<u8>> {
+    binary_content(mail.parsed().ok()?.raw_bytes, &section.part)
+}
+
+/// Checks if the parts requested with `BINARY` use an encoding we can decode
+fn decodable(args: &FetchArguments, mail: &mut MailEntryType) -> bool {
+    let binary_part = |attr: &FetchAttributes| match attr {
+        FetchAttributes::Binary(section, _)
+        | FetchAttributes::BinaryPeek(section, _)
+        | FetchAttributes::BinarySize(section) => Some(section.part.clone()),
+        _ => None,
+    };
+    let parts: Vec<Vec<u32>> = match args {
+        FetchArguments::Single(attr) => binary_part(attr).into_iter().collect(),
+        FetchArguments::List(attrs) => attrs.iter().filter_map(binary_part).collect(),
+        _ => vec![],
+    };
+    if parts.is_empty() {
+        return true;
+    }
+    mail.parsed().map_or(true, |parsed| {
+        parts
+            .iter()
+            .all(|part| has_known_encoding(parsed.raw_bytes, part))
+    })
+}
+
+/// Cuts out the requested octets. An origin past the end results in an empty string.
+fn partial(content: &[u8], origin: u64, length: u64) -> &[u8] {
+    let start = usize::try_from(origin).map_or(content.len(), |x| x.min(content.len()));
+    let end = usize::try_from(origin.saturating_add(length))
+        .map_or(content.len(), |x| x.min(content.len()));
+    &content[start..end]
+}
+
+/// The section specifier as it has to be echoed in the response
+fn section_name(section: &Section) -> String {
+    let mut parts: Vec<String> = section.part.iter().map(ToString::to_string).collect();
+    if let Some(text) = &section.text {
+        parts.push(match text {
+            SectionText::Header => String::from("HEADER"),
+            SectionText::Text => String::from("TEXT"),
+            SectionText::Mime => String::from("MIME"),
+            SectionText::HeaderFields(fields) => format!("HEADER.FIELDS ({})", fields.join(" ")),
+            SectionText::HeaderFieldsNot(fields) => {
+                format!("HEADER.FIELDS.NOT ({})", fields.join(" "))
             }
-            super::parsers::SectionText::HeaderFieldsNot(headers_requested_vec) => {
-                if let Ok(headers_vec) = mail.headers() {
-                    let lower_headers_requested_vec: Vec<_> = headers_requested_vec
-                        .iter()
-                        .map(|header| header.to_lowercase())
-                        .collect();
+        });
+    }
+    parts.join(".")
+}
 
-                    let headers = headers_vec
-                        .iter()
-                        .filter(|header| {
-                            !lower_headers_requested_vec.contains(&header.get_key().to_lowercase())
-                        })
-                        .map(|header| format!("{}: {}", header.get_key(), header.get_value()))
-                        .collect::<Vec<_>>()
-                        .join("\r\n");
-                    let data = format!("{}\r\n", headers);
-                    format!("BODY[HEADER] {{{}}}\r\n{}", data.as_bytes().len(), data)
+/// Checks if one of the attributes fetches the body or binary without PEEK which sets `\Seen`
+fn sets_seen(args: &FetchArguments) -> bool {
+    let sets_seen = |attr: &FetchAttributes| {
+        matches!(
+            attr,
+            FetchAttributes::BodySection(_, _) | FetchAttributes::Binary(_, _)
+        )
+    };
+    match args {
+        FetchArguments::Single(attr) => sets_seen(attr),
+        FetchArguments::List(attrs) => attrs.iter().any(sets_seen),
+        _ => false,
+    }
+}
+
+fn requests_flags(args: &FetchArguments) -> bool {
+    match args {
+        FetchArguments::Single(attr) => matches!(attr, FetchAttributes::Flags),
+        FetchArguments::List(attrs) => attrs
+            .iter()
+            .any(|attr| matches!(attr, FetchAttributes::Flags)),
+        _ => false,
+    }
+}
+
+fn requests_uid(args: &FetchArguments) -> bool {
+    match args {
+        FetchArguments::Single(attr) => matches!(attr, FetchAttributes::Uid),
+        FetchArguments::List(attrs) => attrs
+            .iter()
+            .any(|attr| matches!(attr, FetchAttributes::Uid)),
+        _ => false,
+    }
+}
+
+fn requests_modseq(args: &FetchArguments) -> bool {
+    match args {
+        FetchArguments::Single(attr) => matches!(attr, FetchAttributes::ModSeq),
+        FetchArguments::List(attrs) => attrs
+            .iter()
+            .any(|attr| matches!(attr, FetchAttributes::ModSeq)),
+        _ => false,
+    }
+}
+
+/// The modifiers CONDSTORE and QRESYNC add to FETCH
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FetchModifiers {
+    /// Only mails with a higher modseq are returned
+    changed_since: Option<i64>,
+    /// The uids expunged since `changed_since` are reported with VANISHED (EARLIER)
+    vanished: bool,
+}
+
+/// Splits the modifiers like `(CHANGEDSINCE 12345 VANISHED)` off the end of the arguments.
+///
+/// Returns `None` if the modifiers are invalid.
+fn fetch_modifiers(arguments: &[Argument]) -> Option<(&[Argument], FetchModifiers)> {
+    let is_modifier =
+        |argument: &Argument| argument.is_atom("CHANGEDSINCE") || argument.is_atom("VANISHED");
+    match arguments.split_last() {
+        Some((Argument::List(list), rest)) if list.first().map_or(false, is_modifier) => {
+            let mut modifiers = FetchModifiers::default();
+            let mut list = list.iter();
+            while let Some(modifier) = list.next() {
+                if modifier.is_atom("CHANGEDSINCE") {
+                    let changed_since = list.next()?.as_str()?.parse().ok()?;
+                    modifiers.changed_since = Some(changed_since);
+                } else if modifier.is_atom("VANISHED") {
+                    modifiers.vanished = true;
                 } else {
-                    String::from("BODY[HEADER] NIL\r\n")
+                    return None;
                 }
             }
+            Some((rest, modifiers))
         }
-    } else if let Ok(mail) = mail.parsed() {
-        let mail = String::from_utf8_lossy(mail.raw_bytes);
+        _ => Some((arguments, FetchModifiers::default())),
+    }
+}
 
-        format!("BODY[] {{{}}}\r\n{}", mail.as_bytes().len(), mail)
+fn mark_seen(
+    storage: &Storage,
+    mailbox_path: &Path,
+    mail: &MailEntryType,
+) -> color_eyre::eyre::Result<()> {
+    if mail.path().parent().map_or(false, |x| x.ends_with("new")) {
+        storage.move_new_to_cur_with_flags(mailbox_path, mail.id(), &["\\Seen"])
     } else {
-        String::from("BODY[] NIL\r\n")
+        storage.add_flags(mailbox_path, mail.id(), &["\\Seen"])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::parsers::argument;
+
+    #[test]
+    fn test_partial() {
+        assert_eq!(partial(b"Hello World", 6, 100), b"World");
+        assert_eq!(partial(b"Hello World", 0, 5), b"Hello");
+        assert_eq!(partial(b"Hello", 10, 5), b"");
+    }
+
+    #[test]
+    fn test_sets_seen() {
+        assert!(sets_seen(&FetchArguments::List(vec![
+            FetchAttributes::Uid,
+            FetchAttributes::Binary(Section::default(), None),
+        ])));
+        assert!(!sets_seen(&FetchArguments::Single(
+            FetchAttributes::BinaryPeek(Section::default(), None)
+        )));
+        assert!(!sets_seen(&FetchArguments::Single(
+            FetchAttributes::BinarySize(Section::default())
+        )));
+    }
+
+    #[test]
+    fn test_fetch_modifiers() {
+        let arguments = [
+            Argument::Atom(String::from("1:*")),
+            Argument::List(vec![Argument::Atom(String::from("FLAGS"))]),
+            Argument::List(vec![
+                Argument::Atom(String::from("CHANGEDSINCE")),
+                Argument::Atom(String::from("12345")),
+                Argument::Atom(String::from("VANISHED")),
+            ]),
+        ];
+        assert_eq!(
+            fetch_modifiers(&arguments),
+            Some((
+                &arguments[..2],
+                FetchModifiers {
+                    changed_since: Some(12345),
+                    vanished: true,
+                }
+            ))
+        );
+        assert_eq!(
+            fetch_modifiers(&arguments[..2]),
+            Some((&arguments[..2], FetchModifiers::default()))
+        );
+        let invalid = [Argument::List(vec![Argument::Atom(String::from(
+            "CHANGEDSINCE",
+        ))])];
+        assert_eq!(fetch_modifiers(&invalid), None);
+    }
+
+    #[test]
+    fn test_section_name() {
+        let section = Section {
+            part: vec![1, 2],
+            text: Some(SectionText::HeaderFields(vec![
+                String::from("From"),
+                String::from("To"),
+            ])),
+        };
+        assert_eq!(section_name(&section), "1.2.HEADER.FIELDS (From To)");
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let data: Vec<u8> = (0x80..=0xFF_u8).collect();
+        let message = format!(
+            "Content-Type: multipart/mixed; boundary=x\r\n\r\n--x\r\nContent-Type: application/octet-stream\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n--x--\r\n",
+            base64::encode(&data)
+        );
+        // The message arrives as literal8 with APPEND
+        let mut command = format!("~{{{}}}\r\n", message.len()).into_bytes();
+        command.extend_from_slice(message.as_bytes());
+        let stored = match argument(&command) {
+            Ok((_, Argument::Literal8(stored))) => stored,
+            other => panic!("Unexpected result: {:?}", other),
+        };
+
+        let content = binary_content(&stored, &[1]).unwrap();
+        // BINARY.SIZE has to count the decoded octets
+        assert_eq!(content.len(), 128);
+        let mut expected = b"BINARY[1] ~{128}\r\n".to_vec();
+        expected.extend_from_slice(&data);
+        assert_eq!(
+            literal_item("BINARY[1]", "~", None, Some(content)),
+            expected
+        );
     }
 }
@@ -0,0 +1,340 @@
+use crate::{
+    commands::{fetch::flags_response, noop::send_uid_updates, CommandData, Data},
+    session::ResponseSender,
+    state::{Connection, IdleState, SequenceMap, State},
+};
+use erooster_core::backend::storage::{MailEntry, MailEntryType, MailStorage, Storage};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use notify::Event;
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tokio::sync::{broadcast, RwLock};
+use tracing::{debug, error, instrument};
+
+pub struct Idle<'a> {
+    pub data: &'a Data,
+}
+
+impl Idle<'_> {
+    #[instrument(skip(self, lines, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let mut write_lock = self.data.con_state.write().await;
+        if matches!(
+            write_lock.state,
+            State::Authenticated | State::Selected(_, _)
+        ) {
+            let known_flags = if let State::Selected(folder, _) = &write_lock.state {
+                let mailbox_path =
+                    storage.to_ondisk_path(folder.clone(), write_lock.username.clone().unwrap())?;
+                let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+                flags_by_uid(&mails)
+            } else {
+                HashMap::new()
+            };
+            let previous = write_lock.state.clone();
+            write_lock.state = State::Idle(IdleState {
+                previous: Box::new(previous),
+                tag: command_data.tag.to_string(),
+                known_flags,
+            });
+            lines.send(String::from("+ idling")).await?;
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, lines, line))]
+    pub async fn done<S>(&self, lines: &mut S, line: &str) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let mut write_lock = self.data.con_state.write().await;
+        if let State::Idle(idle_state) = &write_lock.state {
+            let tag = idle_state.tag.clone();
+            let previous = (*idle_state.previous).clone();
+            write_lock.state = previous;
+            if line.eq_ignore_ascii_case("DONE") {
+                lines.send(format!("{} OK IDLE terminated", tag)).await?;
+            } else {
+                lines
+                    .send(format!("{} BAD Expected DONE to end IDLE", tag))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Listens to the file watcher for the lifetime of a session
+#[instrument(skip(file_watcher, lines, con_state, storage))]
+pub async fn watch_changes(
+    mut file_watcher: broadcast::Receiver<Event>,
+    mut lines: ResponseSender,
+    con_state: Arc<RwLock<Connection>>,
+    storage: Arc<Storage>,
+) {
+    loop {
+        let event = match file_watcher.recv().await {
+            Ok(event) => Some(event),
+            // We missed events and therefore can't tell which mailbox changed
+            Err(broadcast::error::RecvError::Lagged(_)) => None,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if let Err(e) = check_changes(
+            &mut lines,
+            Arc::clone(&con_state),
+            Arc::clone(&storage),
+            event,
+        )
+        .await
+        {
+            error!("[IMAP] Failed to send mailbox changes: {}", e);
+        }
+    }
+}
+
+/// Sends the untagged responses for changes to the mailbox the idling client has selected.
+///
+/// If no event is given the mailbox is checked regardless of which files changed.
+/// This is used when the file watcher lagged behind and we may have missed events.
+#[instrument(skip(lines, con_state, storage, event))]
+pub async fn check_changes<S>(
+    lines: &mut S,
+    con_state: Arc<RwLock<Connection>>,
+    storage: Arc<Storage>,
+    event: Option<Event>,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    // Commands may hold a read lock for a while, so we only wait for a write lock when idling
+    if !matches!(con_state.read().await.state, State::Idle(_)) {
+        return Ok(());
+    }
+    let mut write_lock = con_state.write().await;
+    let connection = &mut *write_lock;
+    let username = if let Some(username) = connection.username.clone() {
+        username
+    } else {
+        return Ok(());
+    };
+    let qresync = connection.qresync_enabled();
+    let condstore = connection.condstore_enabled();
+    if let State::Idle(idle_state) = &mut connection.state {
+        let mailbox_path = if let State::Selected(folder, _) = &*idle_state.previous {
+            storage.to_ondisk_path(folder.clone(), username)?
+        } else {
+            return Ok(());
+        };
+        if let Some(event) = event {
+            let mailbox_path = mailbox_path.canonicalize().unwrap_or_default();
+            if !event
+                .paths
+                .iter()
+                .any(|path| is_mail_in_mailbox(path, &mailbox_path))
+            {
+                return Ok(());
+            }
+        }
+        debug!("[IDLE] Mailbox changed: {:?}", mailbox_path);
+
+        let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+        let current_flags = flags_by_uid(&mails);
+        let modseqs: HashMap<i64, i64> = mails
+            .iter()
+            .map(|mail| (mail.uid(), mail.modseq()))
+            .collect();
+        send_idle_updates(
+            lines,
+            &mut connection.sequence_map,
+            &idle_state.known_flags,
+            &current_flags,
+            &modseqs,
+            qresync,
+            condstore,
+        )
+        .await?;
+        idle_state.known_flags = current_flags;
+        lines.flush().await?;
+    }
+    Ok(())
+}
+
+/// Sends the EXPUNGE, EXISTS and FETCH responses for the changes since the client was last told
+/// about the mailbox and updates the sequence map accordingly.
+#[instrument(skip(lines, sequence_map, known_flags, current_flags, modseqs))]
+async fn send_idle_updates<S>(
+    lines: &mut S,
+    sequence_map: &mut SequenceMap,
+    known_flags: &HashMap<i64, String>,
+    current_flags: &HashMap<i64, String>,
+    modseqs: &HashMap<i64, i64>,
+    qresync: bool,
+    condstore: bool,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    let uids: Vec<i64> = current_flags.keys().copied().collect();
+    send_uid_updates(lines, sequence_map, &uids, qresync).await?;
+
+    for (sequence, uid) in sequence_map.iter() {
+        if let Some(flags) = current_flags.get(&uid) {
+            // New mails were already announced with EXISTS
+            if let Some(known_flags) = known_flags.get(&uid) {
+                if known_flags == flags {
+                    continue;
+                }
+                match modseqs.get(&uid) {
+                    // CONDSTORE clients need the uid and modseq with every flag change
+                    Some(modseq) if condstore => {
+                        lines
+                            .feed(format!(
+                                "* {} FETCH (UID {} {} MODSEQ ({}))",
+                                sequence, uid, flags, modseq
+                            ))
+                            .await?;
+                    }
+                    _ => {
+                        lines
+                            .feed(format!("* {} FETCH ({})", sequence, flags))
+                            .await?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks if the path is a mail file inside the `new` or `cur` folder of the mailbox
+fn is_mail_in_mailbox(path: &Path, mailbox_path: &Path) -> bool {
+    path.parent().map_or(false, |dir| {
+        (dir.ends_with("new") || dir.ends_with("cur"))
+            && dir.parent().and_then(|x| x.canonicalize().ok()).as_deref() == Some(mailbox_path)
+    })
+}
+
+/// Generates the `FLAGS` response of the mails by their uid
+fn flags_by_uid(mails: &[MailEntryType]) -> HashMap<i64, String> {
+    mails
+        .iter()
+        .map(|mail| (mail.uid(), flags_response(mail)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Access, Compression, Connection};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_done() {
+        let data = Data {
+            con_state: Arc::new(RwLock::new(Connection {
+                state: State::Idle(IdleState {
+                    previous: Box::new(State::Selected("INBOX".to_string(), Access::ReadWrite)),
+                    tag: "1".to_string(),
+                    known_flags: HashMap::new(),
+                }),
+                secure: true,
+                starttls: false,
+                oauth: false,
+                username: Some(String::from("test")),
+                active_capabilities: vec![],
+                sequence_map: SequenceMap::default(),
+                pending_command: None,
+                channel_binding: None,
+                compression: Compression::Off,
+            })),
+        };
+        let idle = Idle { data: &data };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = idle.done(&mut tx, "DONE").await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 OK IDLE terminated")));
+        assert_eq!(
+            data.con_state.read().await.state,
+            State::Selected("INBOX".to_string(), Access::ReadWrite)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_updates() {
+        let known_flags = HashMap::from([
+            (1, String::from("FLAGS ()")),
+            (2, String::from("FLAGS ()")),
+            (3, String::from("FLAGS (\\Seen)")),
+        ]);
+        // Mail 1 was read, mail 2 was removed and mail 4 arrived
+        let current_flags = HashMap::from([
+            (1, String::from("FLAGS (\\Seen)")),
+            (3, String::from("FLAGS (\\Seen)")),
+            (4, String::from("FLAGS ()")),
+        ]);
+        let modseqs = HashMap::from([(1, 5), (3, 2), (4, 6)]);
+
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut sequence_map = SequenceMap::new(vec![1, 2, 3]);
+        let res = send_idle_updates(
+            &mut tx,
+            &mut sequence_map,
+            &known_flags,
+            &current_flags,
+            &modseqs,
+            false,
+            false,
+        )
+        .await;
+        assert!(res.is_ok());
+        drop(tx);
+        assert_eq!(
+            rx.collect::<Vec<_>>().await,
+            vec![
+                String::from("* 2 EXPUNGE"),
+                String::from("* 3 EXISTS"),
+                String::from("* 1 FETCH (FLAGS (\\Seen))"),
+            ]
+        );
+        assert_eq!(
+            sequence_map.iter().collect::<Vec<_>>(),
+            vec![(1, 1), (2, 3), (3, 4)]
+        );
+
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut sequence_map = SequenceMap::new(vec![1, 2, 3]);
+        let res = send_idle_updates(
+            &mut tx,
+            &mut sequence_map,
+            &known_flags,
+            &current_flags,
+            &modseqs,
+            true,
+            true,
+        )
+        .await;
+        assert!(res.is_ok());
+        drop(tx);
+        assert_eq!(
+            rx.collect::<Vec<_>>().await,
+            vec![
+                String::from("* VANISHED 2"),
+                String::from("* 3 EXISTS"),
+                String::from("* 1 FETCH (UID 1 FLAGS (\\Seen) MODSEQ (5))"),
+            ]
+        );
+    }
+}
@@ -1,21 +1,34 @@
 use crate::{
-    commands::{CommandData, Commands, Data},
+    commands::{
+        acl::mailbox_rights,
+        parsers::{list_arguments, Argument, ListReturnOption, ListSelectOption},
+        status::status_response,
+        CommandData, Commands, Data,
+    },
     state::State,
 };
 use erooster_core::{
-    backend::storage::{MailStorage, Storage},
+    backend::{
+        database::{Database, DB},
+        storage::{MailStorage, Storage, SHARED_NAMESPACE},
+    },
     config::Config,
 };
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
-use std::{path::Path, sync::Arc};
-use tracing::{debug, instrument};
+use nom::{error::convert_error, Finish};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, error, instrument};
 
 #[allow(clippy::too_many_lines)]
-#[instrument(skip(data, lines, config, storage, command_data))]
+#[instrument(skip(data, lines, config, database, storage, command_data))]
 pub async fn basic<S>(
     data: &Data,
     lines: &mut S,
     config: Arc<Config>,
+    database: DB,
     storage: Arc<Storage>,
     command_data: &CommandData<'_>,
 ) -> color_eyre::eyre::Result<()>
@@ -38,16 +51,16 @@ where
     let arguments = &command_data.arguments;
     assert!(arguments.len() == 2);
 
-    // Cleanup args
-    let reference_name = arguments[0];
-    let reference_name = reference_name.replace('"', "");
-    let mailbox_patterns = arguments[1];
-    let mailbox_patterns = mailbox_patterns.replace('"', "");
+    let reference_name = arguments[0].as_str().unwrap_or_default().to_string();
+    let mailbox_patterns = arguments[1].as_str().unwrap_or_default().to_string();
+    let pattern = format!("{}{}", reference_name, mailbox_patterns).replace('"', "");
 
     if mailbox_patterns.is_empty() {
         lines
             .feed(format!("* {} (\\Noselect) \".\" \"\"", command_resp))
             .await?;
+    } else if is_shared(&pattern) {
+        // Only mailboxes of other users match which are listed below
     } else if mailbox_patterns.ends_with('*') {
         let mut folder = Path::new(&config.mail.maildir_folders)
             .join(data.con_state.read().await.username.clone().unwrap());
@@ -193,6 +206,21 @@ where
             ))
             .await?;
     }
+    if !mailbox_patterns.is_empty() {
+        let username = data.con_state.read().await.username.clone().unwrap();
+        for (name, _, flags) in shared_mailboxes(&database, &storage, &username).await? {
+            if matches_pattern(&name, &pattern) {
+                lines
+                    .feed(format!(
+                        "* {} ({}) \"/\" \"{}\"",
+                        command_resp,
+                        flags.join(" "),
+                        name
+                    ))
+                    .await?;
+            }
+        }
+    }
     lines
         .feed(format!(
             "{} OK {} completed",
@@ -207,48 +235,311 @@ pub struct List<'a> {
 }
 
 impl List<'_> {
-    // TODO parse all arguments
-
-    // TODO setup
-    #[instrument(skip(self, lines, command_data))]
+    #[allow(clippy::too_many_lines)]
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
     pub async fn extended<S>(
         &self,
         lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
         debug!("extended");
-        if self.data.con_state.read().await.state == State::NotAuthenticated {
+        let (state, username) = {
+            let read_lock = self.data.con_state.read().await;
+            (read_lock.state.clone(), read_lock.username.clone())
+        };
+        if state == State::NotAuthenticated {
             lines
                 .send(format!("{} BAD Not Authenticated", command_data.tag))
                 .await?;
-        } else {
-            let arguments = &command_data.arguments;
-            if arguments[0].starts_with('(') && arguments[0].ends_with(')') {
-                // TODO handle selection options
-            } else {
-                // Cleanup args
-                let reference_name = arguments[0];
-                let _reference_name = reference_name.replace('"', "");
-                let mailbox_patterns = arguments[1];
-                let _mailbox_patterns = mailbox_patterns.replace('"', "");
+            return Ok(());
+        }
+
+        let list_args = command_data.joined_arguments(0);
+        let args = match list_arguments(&list_args).finish() {
+            Ok(("", args)) => args,
+            Ok((left, _)) => {
+                error!("Failed to parse list arguments. Leftover: {}", left);
+                lines
+                    .send(format!("{} BAD Unable to parse", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!(
+                    "Failed to parse list arguments: {}",
+                    convert_error(list_args.as_str(), e)
+                );
+                lines
+                    .send(format!("{} BAD Unable to parse", command_data.tag))
+                    .await?;
+                return Ok(());
             }
+        };
+
+        let subscribed_only = args
+            .selection_options
+            .contains(&ListSelectOption::Subscribed);
+        let special_use_only = args
+            .selection_options
+            .contains(&ListSelectOption::SpecialUse);
+        let recursive_match = args
+            .selection_options
+            .contains(&ListSelectOption::RecursiveMatch);
+        // RECURSIVEMATCH only makes sense together with an option that filters mailboxes
+        if recursive_match && !subscribed_only && !special_use_only {
             lines
-                .send(format!("{} BAD LIST Not supported", command_data.tag))
+                .send(format!(
+                    "{} BAD RECURSIVEMATCH requires another selection option",
+                    command_data.tag
+                ))
                 .await?;
+            return Ok(());
         }
+        let return_subscribed = subscribed_only
+            || args
+                .return_options
+                .contains(&ListReturnOption::Subscribed);
+        let return_children = args.return_options.contains(&ListReturnOption::Children);
+        let status_items = args.return_options.iter().find_map(|option| {
+            if let ListReturnOption::Status(items) = option {
+                Some(items)
+            } else {
+                None
+            }
+        });
+
+        let username = username.unwrap();
+        let user_folder = Path::new(&config.mail.maildir_folders).join(&username);
+        let mut mailboxes = all_mailboxes(&storage, &user_folder).await?;
+        mailboxes.extend(shared_mailboxes(&database, &storage, &username).await?);
+        let is_selected = |flags: &[String]| {
+            (!subscribed_only || has_flag(flags, "\\Subscribed"))
+                && (!special_use_only || flags.iter().any(|flag| is_special_use(flag)))
+        };
+
+        for (name, path, flags) in &mailboxes {
+            if !args.mailbox_patterns.iter().any(|pattern| {
+                matches_pattern(name, &format!("{}{}", args.reference_name, pattern))
+            }) {
+                continue;
+            }
+            let selected = is_selected(flags);
+            // Parents of subscribed mailboxes are listed with the CHILDINFO extended data item
+            let child_info = recursive_match
+                && subscribed_only
+                && mailboxes.iter().any(|(child, _, child_flags)| {
+                    is_child(child, name) && is_selected(child_flags)
+                });
+            if !selected && !child_info {
+                continue;
+            }
+
+            let mut attributes = flags
+                .iter()
+                .filter(|flag| {
+                    (return_subscribed && selected) || !flag.eq_ignore_ascii_case("\\Subscribed")
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if return_children && !has_flag(flags, "\\NoInferiors") {
+                if mailboxes.iter().any(|(child, _, _)| is_child(child, name)) {
+                    attributes.push(String::from("\\HasChildren"));
+                } else {
+                    attributes.push(String::from("\\HasNoChildren"));
+                }
+            }
+
+            let mut response = format!(
+                "* LIST ({}) \"{}\" \"{}\"",
+                attributes.join(" "),
+                delimiter(name),
+                name
+            );
+            if child_info {
+                response.push_str(" (\"CHILDINFO\" (\"SUBSCRIBED\"))");
+            }
+            lines.feed(response).await?;
+            if let Some(status_items) = status_items {
+                // STATUS of shared mailboxes is only returned with the read right
+                if selected
+                    && path.exists()
+                    && !has_flag(flags, "\\Noselect")
+                    && (!is_shared(name)
+                        || mailbox_rights(&database, &storage, &username, name)
+                            .await?
+                            .contains('r'))
+                {
+                    lines
+                        .feed(status_response(&storage, path, name, status_items).await?)
+                        .await?;
+                }
+            }
+        }
+
+        lines
+            .feed(format!("{} OK LIST completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
         Ok(())
     }
 }
 
+/// Lists the names, paths and attributes of all mailboxes of the user
+#[instrument(skip(storage, user_folder))]
+async fn all_mailboxes(
+    storage: &Storage,
+    user_folder: &Path,
+) -> color_eyre::eyre::Result<Vec<(String, PathBuf, Vec<String>)>> {
+    let mut mailboxes = Vec::new();
+    let inbox_path = user_folder.join("INBOX");
+    let inbox_flags = storage.get_flags(&inbox_path).await.unwrap_or_default();
+    mailboxes.push((String::from("INBOX"), inbox_path, inbox_flags));
+    if user_folder.exists() {
+        for sub_folder in storage.list_subdirs(user_folder)? {
+            let name = sub_folder
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .trim_start_matches('.')
+                .to_string();
+            let flags = storage.get_flags(&sub_folder).await.unwrap_or_default();
+            mailboxes.push((name, sub_folder, flags));
+        }
+    }
+    Ok(mailboxes)
+}
+
+/// Lists the mailboxes of other users which the user may see through an ACL.
+///
+/// The special-use attributes of the owner don't apply and they are always subscribed.
+#[instrument(skip(database, storage))]
+async fn shared_mailboxes(
+    database: &DB,
+    storage: &Storage,
+    username: &str,
+) -> color_eyre::eyre::Result<Vec<(String, PathBuf, Vec<String>)>> {
+    let mut mailboxes = Vec::new();
+    for (owner, mailbox) in database.get_shared_mailboxes(username).await? {
+        let name = format!(
+            "{}{}/{}",
+            SHARED_NAMESPACE,
+            owner,
+            mailbox.trim_start_matches('.').replace('.', "/")
+        );
+        let path = storage.to_ondisk_path(name.clone(), username.to_string())?;
+        if !path.exists() {
+            continue;
+        }
+        let mut flags = storage
+            .get_flags(&path)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|flag| !is_special_use(flag) && !flag.eq_ignore_ascii_case("\\Subscribed"))
+            .collect::<Vec<_>>();
+        flags.push(String::from("\\Subscribed"));
+        mailboxes.push((name, path, flags));
+    }
+    if !mailboxes.is_empty() {
+        // The namespace itself can't be selected but makes the shared mailboxes reachable with `%`
+        mailboxes.insert(
+            0,
+            (
+                SHARED_NAMESPACE.trim_end_matches('/').to_string(),
+                PathBuf::new(),
+                vec![String::from("\\Noselect")],
+            ),
+        );
+    }
+    Ok(mailboxes)
+}
+
+/// Checks if the name is in the shared namespace
+pub fn is_shared(name: &str) -> bool {
+    name.starts_with(SHARED_NAMESPACE) || name == SHARED_NAMESPACE.trim_end_matches('/')
+}
+
+/// Checks if a personal mailbox with the name would be mistaken for the shared namespace.
+///
+/// Personal mailboxes use `.` as delimiter so `Shared.Projects` is a child of `Shared` as well.
+pub fn is_reserved(name: &str) -> bool {
+    let name = name.trim_matches('"');
+    let shared = SHARED_NAMESPACE.trim_end_matches('/');
+    is_shared(name) || name.strip_prefix(shared).map_or(false, |rest| rest.starts_with('.'))
+}
+
+/// Returns the hierarchy delimiter of the mailbox.
+///
+/// Owners in the shared namespace may contain dots so it uses `/` instead.
+fn delimiter(name: &str) -> char {
+    if is_shared(name) {
+        '/'
+    } else {
+        '.'
+    }
+}
+
+fn has_flag(flags: &[String], flag: &str) -> bool {
+    flags.iter().any(|x| x.eq_ignore_ascii_case(flag))
+}
+
+/// Checks if the attribute is one of the special-use attributes of RFC 6154
+pub fn is_special_use(flag: &str) -> bool {
+    [
+        "\\All",
+        "\\Archive",
+        "\\Drafts",
+        "\\Flagged",
+        "\\Junk",
+        "\\Sent",
+        "\\Trash",
+    ]
+    .iter()
+    .any(|x| x.eq_ignore_ascii_case(flag))
+}
+
+/// Checks if the mailbox is somewhere below the parent in the hierarchy
+fn is_child(mailbox: &str, parent: &str) -> bool {
+    mailbox
+        .strip_prefix(parent)
+        .map_or(false, |rest| rest.starts_with(delimiter(mailbox)))
+}
+
+/// Matches a mailbox name against a LIST pattern.
+///
+/// `*` matches any characters and `%` matches any characters except the hierarchy delimiter.
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    if name == "INBOX" && pattern.eq_ignore_ascii_case("INBOX") {
+        return true;
+    }
+    let delimiter = if is_shared(name) { b'/' } else { b'.' };
+    wildcard_match(name.as_bytes(), pattern.as_bytes(), delimiter)
+}
+
+fn wildcard_match(name: &[u8], pattern: &[u8], delimiter: u8) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| wildcard_match(&name[i..], rest, delimiter)),
+        Some((b'%', rest)) => (0..=name.len())
+            .take_while(|&i| i == 0 || name[i - 1] != delimiter)
+            .any(|i| wildcard_match(&name[i..], rest, delimiter)),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_match(&name[1..], rest, delimiter),
+    }
+}
+
 impl List<'_> {
-    #[instrument(skip(self, lines, config, storage, command_data))]
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         config: Arc<Config>,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
@@ -257,16 +548,11 @@ impl List<'_> {
     {
         let arguments = &command_data.arguments;
         assert!(arguments.len() >= 2);
-        if arguments.len() == 2 {
-            basic(self.data, lines, config, storage, command_data).await?;
-        } else if arguments.len() == 4 {
-            self.extended(lines, command_data).await?;
+        // Selection options, multiple patterns and return options need the extended LIST
+        if arguments.len() == 2 && !arguments.iter().any(|x| matches!(x, Argument::List(_))) {
+            basic(self.data, lines, config, database, storage, command_data).await?;
         } else {
-            lines
-                .send(format!(
-                    "{} BAD [SERVERBUG] invalid arguments",
-                    command_data.tag
-                ))
+            self.extended(lines, config, database, storage, command_data)
                 .await?;
         }
         Ok(())
@@ -278,11 +564,12 @@ pub struct LSub<'a> {
 }
 
 impl LSub<'_> {
-    #[instrument(skip(self, lines, config, storage, command_data))]
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         config: Arc<Config>,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
@@ -292,7 +579,7 @@ impl LSub<'_> {
         let arguments = &command_data.arguments;
         assert!(arguments.len() == 2);
         if arguments.len() == 2 {
-            basic(self.data, lines, config, storage, command_data).await?;
+            basic(self.data, lines, config, database, storage, command_data).await?;
         } else {
             lines
                 .send(format!(
@@ -304,3 +591,42 @@ impl LSub<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("INBOX", "inbox"));
+        assert!(matches_pattern("Lists.Rust", "*"));
+        assert!(matches_pattern("Lists.Rust", "Lists.%"));
+        assert!(!matches_pattern("Lists.Rust.Announce", "Lists.%"));
+        assert!(matches_pattern("Lists.Rust.Announce", "Lists.*"));
+        assert!(!matches_pattern("Lists", "%.Rust"));
+        assert!(is_child("Lists.Rust", "Lists"));
+        assert!(!is_child("ListsOld", "Lists"));
+    }
+
+    #[test]
+    fn test_shared_patterns() {
+        assert!(matches_pattern("Shared/bob@example.com/INBOX", "Shared/*"));
+        assert!(matches_pattern(
+            "Shared/bob@example.com/INBOX",
+            "Shared/%/%"
+        ));
+        assert!(!matches_pattern(
+            "Shared/bob@example.com/Lists/Rust",
+            "Shared/%/%"
+        ));
+        assert!(matches_pattern("Shared", "%"));
+        assert!(!matches_pattern("Shared/bob@example.com/INBOX", "%"));
+        assert!(is_child("Shared/bob@example.com/Lists", "Shared"));
+        assert_eq!(delimiter("Shared/bob@example.com/INBOX"), '/');
+        assert_eq!(delimiter("SharedFiles.Old"), '.');
+        assert!(is_reserved("Shared"));
+        assert!(is_reserved("\"Shared/bob@example.com/INBOX\""));
+        assert!(is_reserved("Shared.Projects"));
+        assert!(!is_reserved("SharedFiles.Old"));
+    }
+}
@@ -1,25 +1,166 @@
-use crate::commands::CommandData;
+use crate::{
+    commands::{create::provision_default_folders, CommandData, Data},
+    state::State,
+};
+use erooster_core::{
+    backend::{
+        database::{normalize_username, Database, DB},
+        storage::Storage,
+    },
+    config::Config,
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
-use tracing::instrument;
+use secrecy::SecretString;
+use std::{str::FromStr, sync::Arc};
+use tracing::{debug, error, instrument};
 
-pub struct Login;
+pub struct Login<'a> {
+    pub data: &'a Data,
+}
 
-impl Login {
-    #[instrument(skip(self, lines, command_data))]
+impl Login<'_> {
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
+        let mut write_lock = self.data.con_state.write().await;
+        if !write_lock.secure {
+            lines
+                .send(format!(
+                    "{} NO [PRIVACYREQUIRED] LOGIN COMMAND DISABLED FOR SECURITY. USE AUTH",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        if write_lock.state != State::NotAuthenticated {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+
+        // Both may be atoms, quoted strings or literals
+        let credentials = match command_data.arguments {
+            [username, password] => username.as_str().zip(password.as_str()),
+            _ => None,
+        };
+        let (username, password) = if let Some(credentials) = credentials {
+            credentials
+        } else {
+            lines
+                .send(format!("{} BAD Invalid arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+        let username = normalize_username(username);
+        let password = SecretString::from_str(password)?;
+
+        debug!("[IMAP] Making sure user exists");
+        if !database.user_exists(&username).await {
+            lines
+                .send(format!("{} NO Invalid user or password", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        debug!("[IMAP] Verify credentials");
+        if !database.verify_user(&username, password).await {
+            debug!("[IMAP] Invalid user or password");
+            lines
+                .send(format!("{} NO Invalid user or password", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        write_lock.username = Some(username.clone());
+        write_lock.state = State::Authenticated;
+        if let Err(e) = provision_default_folders(&database, &storage, &config, &username).await {
+            error!("[IMAP] Failed to create the default folders: {}", e);
+        }
         lines
-            .send(format!(
-                "{} NO [PRIVACYREQUIRED] LOGIN COMMAND DISABLED FOR SECURITY. USE AUTH",
-                command_data.tag
-            ))
+            .send(format!("{} OK LOGIN completed", command_data.tag))
             .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{parsers::Argument, CommandData, Commands};
+    use crate::state::{Compression, Connection, SequenceMap};
+    use futures::{channel::mpsc, StreamExt};
+    use tokio::sync::RwLock;
+
+    async fn run_login(secure: bool, arguments: &[Argument]) -> Option<String> {
+        let login = Login {
+            data: &Data {
+                con_state: Arc::new(RwLock::new(Connection {
+                    state: State::NotAuthenticated,
+                    secure,
+                    starttls: false,
+                    oauth: false,
+                    username: None,
+                    active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
+                })),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Login,
+            arguments,
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(erooster_core::backend::storage::get_storage(
+            Arc::clone(&database),
+            Arc::clone(&config),
+        ));
+        let res = login
+            .exec(&mut tx, config, database, storage, &cmd_data)
+            .await;
+        assert!(res.is_ok());
+        rx.next().await
+    }
+
+    #[tokio::test]
+    async fn test_login_insecure() {
+        let arguments = [
+            Argument::Atom(String::from("user")),
+            Argument::Quoted(String::from("secret password")),
+        ];
+        assert_eq!(
+            run_login(false, &arguments).await,
+            Some(String::from(
+                "1 NO [PRIVACYREQUIRED] LOGIN COMMAND DISABLED FOR SECURITY. USE AUTH"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_invalid_arguments() {
+        let arguments = [Argument::Atom(String::from("user"))];
+        assert_eq!(
+            run_login(true, &arguments).await,
+            Some(String::from("1 BAD Invalid arguments"))
+        );
+    }
+}
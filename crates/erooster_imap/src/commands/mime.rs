@@ -0,0 +1,490 @@
+use crate::commands::parsers::{Section, SectionText};
+use mailparse::{addrparse, DispositionType, MailAddr, MailHeader, MailHeaderMap, ParsedMail};
+
+/// Formats the value as a quoted string or as a literal if it can't be quoted
+pub fn string(value: &str) -> String {
+    if value.contains(|c: char| c == '\r' || c == '\n' || c == '\0') {
+        format!("{{{}}}\r\n{}", value.len(), value)
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Like [`string`] but `NIL` if there is no value
+pub fn nstring(value: Option<&str>) -> String {
+    value.map_or_else(|| String::from("NIL"), string)
+}
+
+/// Generates the ENVELOPE of the mail as defined in RFC 9051
+pub fn envelope(mail: &ParsedMail) -> String {
+    let headers = &mail.headers;
+    let from = address_list(headers, "From");
+    // Sender and Reply-To default to the From header if they are missing
+    let sender = match address_list(headers, "Sender") {
+        nil if nil == "NIL" => from.clone(),
+        sender => sender,
+    };
+    let reply_to = match address_list(headers, "Reply-To") {
+        nil if nil == "NIL" => from.clone(),
+        reply_to => reply_to,
+    };
+    format!(
+        "({} {} {} {} {} {} {} {} {} {})",
+        nstring(header_value(headers, "Date").as_deref()),
+        nstring(header_value(headers, "Subject").as_deref()),
+        from,
+        sender,
+        reply_to,
+        address_list(headers, "To"),
+        address_list(headers, "Cc"),
+        address_list(headers, "Bcc"),
+        nstring(header_value(headers, "In-Reply-To").as_deref()),
+        nstring(header_value(headers, "Message-ID").as_deref()),
+    )
+}
+
+/// Generates the BODYSTRUCTURE including the extension data for the MIME tree of the mail
+pub fn body_structure(part: &ParsedMail) -> String {
+    let (media_type, subtype) = part
+        .ctype
+        .mimetype
+        .split_once('/')
+        .unwrap_or(("text", "plain"));
+    let media_type = media_type.to_uppercase();
+    let subtype = subtype.to_uppercase();
+
+    if media_type == "MULTIPART" && !part.subparts.is_empty() {
+        let parts: String = part.subparts.iter().map(body_structure).collect();
+        return format!(
+            "({} {} {} {} NIL NIL)",
+            parts,
+            string(&subtype),
+            body_parameters(part),
+            disposition(part)
+        );
+    }
+
+    let body = encoded_body(part);
+    let encoding = header_value(&part.headers, "Content-Transfer-Encoding")
+        .map_or_else(|| String::from("7BIT"), |x| x.to_uppercase());
+    let mut fields = vec![
+        string(&media_type),
+        string(&subtype),
+        body_parameters(part),
+        nstring(header_value(&part.headers, "Content-ID").as_deref()),
+        nstring(header_value(&part.headers, "Content-Description").as_deref()),
+        string(&encoding),
+        body.len().to_string(),
+    ];
+    if media_type == "MESSAGE" && subtype == "RFC822" {
+        // The attached mail may be encoded as well
+        let decoded = part.get_body_raw().unwrap_or_default();
+        if let Ok(attached) = mailparse::parse_mail(&decoded) {
+            fields.push(envelope(&attached));
+            fields.push(body_structure(&attached));
+        } else {
+            fields.push(String::from("NIL"));
+            fields.push(String::from("NIL"));
+        }
+        fields.push(line_count(body).to_string());
+    } else if media_type == "TEXT" {
+        fields.push(line_count(body).to_string());
+    }
+    // We don't calculate the MD5 and don't know the language or location of the body
+    fields.push(String::from("NIL"));
+    fields.push(disposition(part));
+    fields.push(String::from("NIL"));
+    fields.push(String::from("NIL"));
+    format!("({})", fields.join(" "))
+}
+
+/// The body of the part as it is transferred without decoding it
+pub fn encoded_body<'a>(part: &ParsedMail<'a>) -> &'a [u8] {
+    &part.raw_bytes[body_start(part.raw_bytes)..]
+}
+
+/// Finds where the body starts after the empty line which ends the header
+fn body_start(raw: &[u8]) -> usize {
+    // A part without headers starts with the empty line
+    if raw.starts_with(b"\r\n") {
+        return 2;
+    }
+    if raw.starts_with(b"\n") {
+        return 1;
+    }
+    let crlf = raw
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .map(|index| (index, index + 4));
+    let lf = raw
+        .windows(2)
+        .position(|x| x == b"\n\n")
+        .map(|index| (index, index + 2));
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) => crlf.min(lf).1,
+        (Some((_, start)), None) | (None, Some((_, start))) => start,
+        (None, None) => raw.len(),
+    }
+}
+
+/// Returns the raw MIME part including its header which is addressed by the part numbers.
+///
+/// The parts of an attached message are addressed through the `message/rfc822` part.
+fn resolve_part(raw: &[u8], part: &[u32]) -> Option<Vec<u8>> {
+    let (first, rest) = match part.split_first() {
+        Some(split) => split,
+        None => return Some(raw.to_vec()),
+    };
+    let parsed = mailparse::parse_mail(raw).ok()?;
+    let child = if parsed.ctype.mimetype.starts_with("multipart/") {
+        let index = usize::try_from(first.checked_sub(1)?).ok()?;
+        parsed.subparts.get(index)?.raw_bytes.to_vec()
+    } else if *first == 1 {
+        // A mail which isn't multipart only has the part 1 which is its body
+        raw.to_vec()
+    } else {
+        return None;
+    };
+    if rest.is_empty() {
+        return Some(child);
+    }
+
+    let child_parsed = mailparse::parse_mail(&child).ok()?;
+    if child_parsed.ctype.mimetype == "message/rfc822" {
+        resolve_part(&child_parsed.get_body_raw().ok()?, rest)
+    } else if child_parsed.ctype.mimetype.starts_with("multipart/") {
+        resolve_part(&child, rest)
+    } else {
+        None
+    }
+}
+
+/// Extracts the content of the section from the raw mail
+pub fn section_content(raw: &[u8], section: &Section) -> Option<Vec<u8>> {
+    let part = resolve_part(raw, &section.part)?;
+    let text = match &section.text {
+        None if section.part.is_empty() => return Some(part),
+        None => return Some(part[body_start(&part)..].to_vec()),
+        Some(SectionText::Mime) => return Some(part[..body_start(&part)].to_vec()),
+        Some(text) => text,
+    };
+
+    // HEADER and TEXT of a part refer to the message attached by it
+    let message = if section.part.is_empty() {
+        part
+    } else {
+        let parsed = mailparse::parse_mail(&part).ok()?;
+        if parsed.ctype.mimetype != "message/rfc822" {
+            return None;
+        }
+        parsed.get_body_raw().ok()?
+    };
+    let (header, body) = message.split_at(body_start(&message));
+    match text {
+        SectionText::Header => Some(header.to_vec()),
+        SectionText::Text => Some(body.to_vec()),
+        SectionText::HeaderFields(names) => Some(header_fields(header, names, false)),
+        SectionText::HeaderFieldsNot(names) => Some(header_fields(header, names, true)),
+        SectionText::Mime => None,
+    }
+}
+
+/// The content transfer encodings `BINARY` is able to decode
+const KNOWN_ENCODINGS: [&str; 5] = ["7bit", "8bit", "binary", "base64", "quoted-printable"];
+
+/// Checks if we are able to decode the content transfer encoding of the part.
+///
+/// Parts which don't exist count as decodable as they are answered with `NIL`.
+pub fn has_known_encoding(raw: &[u8], part: &[u32]) -> bool {
+    if part.is_empty() {
+        return true;
+    }
+    resolve_part(raw, part)
+        .and_then(|content| {
+            let parsed = mailparse::parse_mail(&content).ok()?;
+            header_value(&parsed.headers, "Content-Transfer-Encoding")
+        })
+        .map_or(true, |encoding| {
+            KNOWN_ENCODINGS
+                .iter()
+                .any(|known| encoding.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Extracts the body of the part with its content transfer encoding decoded
+pub fn binary_content(raw: &[u8], part: &[u32]) -> Option<Vec<u8>> {
+    // The whole message keeps its header and therefore isn't decoded
+    if part.is_empty() {
+        return Some(raw.to_vec());
+    }
+    let content = resolve_part(raw, part)?;
+    mailparse::parse_mail(&content).ok()?.get_body_raw().ok()
+}
+
+/// Picks the header fields with the names or all others if `exclude` is set.
+///
+/// The fields are returned as they are including the empty line which ends the header.
+fn header_fields(header: &[u8], names: &[String], exclude: bool) -> Vec<u8> {
+    let mut fields = Vec::new();
+    let mut keep = false;
+    for line in header.split_inclusive(|x| *x == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        // Folded lines belong to the field before them
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            let name = line.split(|x| *x == b':').next().unwrap_or_default();
+            let name = String::from_utf8_lossy(name);
+            keep = names.iter().any(|x| x.eq_ignore_ascii_case(name.trim())) != exclude;
+        }
+        if keep {
+            fields.extend_from_slice(line);
+        }
+    }
+    fields.extend_from_slice(b"\r\n");
+    fields
+}
+
+fn line_count(body: &[u8]) -> usize {
+    let lines = body.iter().filter(|x| **x == b'\n').count();
+    if body.is_empty() || body.ends_with(b"\n") {
+        lines
+    } else {
+        lines + 1
+    }
+}
+
+/// The value of the first header with the name with folded lines joined
+fn header_value(headers: &[MailHeader], name: &str) -> Option<String> {
+    headers.get_first_value(name).map(|value| {
+        value
+            .replace("\r\n", "")
+            .replace('\n', "")
+            .trim()
+            .to_string()
+    })
+}
+
+fn address_list(headers: &[MailHeader], name: &str) -> String {
+    let addresses = match header_value(headers, name).map(|value| addrparse(&value)) {
+        Some(Ok(addresses)) if !addresses.is_empty() => addresses,
+        _ => return String::from("NIL"),
+    };
+    let mut list = String::from("(");
+    for address in addresses.iter() {
+        match address {
+            MailAddr::Single(info) => {
+                list.push_str(&address_structure(info.display_name.as_deref(), &info.addr));
+            }
+            // Groups are marked by a start with the group name and an empty end
+            MailAddr::Group(group) => {
+                list.push_str(&format!("(NIL NIL {} NIL)", string(&group.group_name)));
+                for info in &group.addrs {
+                    list.push_str(&address_structure(info.display_name.as_deref(), &info.addr));
+                }
+                list.push_str("(NIL NIL NIL NIL)");
+            }
+        }
+    }
+    list.push(')');
+    list
+}
+
+fn address_structure(name: Option<&str>, address: &str) -> String {
+    let (mailbox, host) = match address.rsplit_once('@') {
+        Some((mailbox, host)) => (mailbox, Some(host)),
+        None => (address, None),
+    };
+    format!(
+        "({} NIL {} {})",
+        nstring(name),
+        string(mailbox),
+        nstring(host)
+    )
+}
+
+fn body_parameters(part: &ParsedMail) -> String {
+    let mut parameters: Vec<(&str, &str)> = part
+        .ctype
+        .params
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    if part.ctype.mimetype.starts_with("text/") && !part.ctype.params.contains_key("charset") {
+        parameters.push(("charset", &part.ctype.charset));
+    }
+    parameter_list(&parameters)
+}
+
+fn disposition(part: &ParsedMail) -> String {
+    if part
+        .headers
+        .get_first_value("Content-Disposition")
+        .is_none()
+    {
+        return String::from("NIL");
+    }
+    let disposition = part.get_content_disposition();
+    let kind = match &disposition.disposition {
+        DispositionType::Inline => String::from("INLINE"),
+        DispositionType::Attachment => String::from("ATTACHMENT"),
+        DispositionType::FormData => String::from("FORM-DATA"),
+        DispositionType::Extension(kind) => kind.to_uppercase(),
+    };
+    let parameters: Vec<(&str, &str)> = disposition
+        .params
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    format!("({} {})", string(&kind), parameter_list(&parameters))
+}
+
+fn parameter_list(parameters: &[(&str, &str)]) -> String {
+    if parameters.is_empty() {
+        return String::from("NIL");
+    }
+    let parameters = parameters
+        .iter()
+        .map(|(key, value)| format!("{} {}", string(&key.to_uppercase()), string(value)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("({})", parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(string("a\r\nb"), "{4}\r\na\r\nb");
+        assert_eq!(nstring(None), "NIL");
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mail = mailparse::parse_mail(
+            b"From: Alice <alice@example.com>\r\n\
+            To: bob@example.com, Carol <carol@example.com>\r\n\
+            Subject: Hello\r\n\
+            Date: Mon, 7 Feb 1994 21:52:25 -0800\r\n\
+            Message-ID: <1@example.com>\r\n\
+            \r\n\
+            Hi\r\n",
+        )
+        .unwrap();
+        let alice = "((\"Alice\" NIL \"alice\" \"example.com\"))";
+        assert_eq!(
+            envelope(&mail),
+            format!(
+                "(\"Mon, 7 Feb 1994 21:52:25 -0800\" \"Hello\" {} {} {} \
+                ((NIL NIL \"bob\" \"example.com\")(\"Carol\" NIL \"carol\" \"example.com\")) \
+                NIL NIL NIL \"<1@example.com>\")",
+                alice, alice, alice
+            )
+        );
+        assert_eq!(
+            body_structure(&mail),
+            "(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"us-ascii\") NIL NIL \"7BIT\" 4 1 NIL NIL NIL NIL)"
+        );
+    }
+
+    #[test]
+    fn test_section_content() {
+        let raw = b"Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            Subject: Test\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            Hi\r\n\
+            --b\r\n\
+            Content-Type: message/rfc822\r\n\
+            \r\n\
+            Subject: Inner\r\n\
+            To: a@example.com\r\n\
+            \r\n\
+            Inner body\r\n\
+            --b--\r\n";
+        let section = |part: Vec<u32>, text: Option<SectionText>| {
+            section_content(raw, &Section { part, text }).map(|x| String::from_utf8(x).unwrap())
+        };
+        assert_eq!(
+            section(vec![1], Some(SectionText::Mime)),
+            Some(String::from("Content-Type: text/plain\r\n\r\n"))
+        );
+        assert_eq!(
+            section(
+                vec![2],
+                Some(SectionText::HeaderFields(vec![String::from("subject")]))
+            ),
+            Some(String::from("Subject: Inner\r\n\r\n"))
+        );
+        assert_eq!(
+            section(
+                vec![],
+                Some(SectionText::HeaderFieldsNot(vec![String::from(
+                    "Content-Type"
+                )]))
+            ),
+            Some(String::from("Subject: Test\r\n\r\n"))
+        );
+        assert_eq!(section(vec![3], None), None);
+    }
+
+    #[test]
+    fn test_binary_content() {
+        let raw = b"Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            Gr=C3=BC=C3=9Fe\r\n\
+            --b\r\n\
+            Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            AAECAw==\r\n\
+            --b\r\n\
+            Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: x-uuencode\r\n\
+            \r\n\
+            begin 644 test\r\n\
+            --b--\r\n";
+        assert!(binary_content(raw, &[1])
+            .unwrap()
+            .starts_with("Grüße".as_bytes()));
+        assert_eq!(binary_content(raw, &[2]), Some(vec![0, 1, 2, 3]));
+        assert_eq!(binary_content(raw, &[4]), None);
+        assert!(has_known_encoding(raw, &[2]));
+        assert!(has_known_encoding(raw, &[4]));
+        assert!(!has_known_encoding(raw, &[3]));
+    }
+
+    #[test]
+    fn test_multipart_body_structure() {
+        let mail = mailparse::parse_mail(
+            b"Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            \r\n\
+            Hi\r\n\
+            --b\r\n\
+            Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            Content-Disposition: attachment; filename=\"a.bin\"\r\n\
+            \r\n\
+            AAEC\r\n\
+            --b--\r\n",
+        )
+        .unwrap();
+        let body_structure = body_structure(&mail);
+        assert!(body_structure.starts_with("((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"utf-8\")"));
+        assert!(body_structure.contains("(\"APPLICATION\" \"OCTET-STREAM\" NIL NIL NIL \"BASE64\""));
+        assert!(body_structure.contains("(\"ATTACHMENT\" (\"FILENAME\" \"a.bin\"))"));
+        assert!(body_structure.ends_with("\"MIXED\" (\"BOUNDARY\" \"b\") NIL NIL NIL)"));
+    }
+}
@@ -1,20 +1,31 @@
 use crate::{
     commands::{
+        acl::{DeleteAcl, GetAcl, ListRights, MyRights, SetAcl},
         append::Append,
-        auth::{Authenticate, AuthenticationMethod},
+        auth::Authenticate,
         capability::Capability,
         check::Check,
         close::Close,
+        compress::Compress,
+        copy::Copy,
         create::Create,
         delete::Delete,
         enable::Enable,
+        expunge::Expunge,
         fetch::Fetch,
+        idle::Idle,
         list::{LSub, List},
         login::Login,
         logout::Logout,
+        move_command::Move,
+        namespace::Namespace,
         noop::Noop,
+        parsers::{argument, trailing_literal, Argument, BytesRes},
+        quota::{GetQuota, GetQuotaRoot, SetQuota},
         rename::Rename,
+        search::Search,
         select::{Examine, Select},
+        starttls::StartTls,
         status::Status,
         store::Store,
         subscribe::Subscribe,
@@ -26,16 +37,18 @@ use crate::{
 use erooster_core::{
     backend::{database::DB, storage::Storage},
     config::Config,
+    LINE_LIMIT,
 };
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while1},
-    character::complete::alpha1,
-    error::{context, convert_error, VerboseError},
-    multi::many0,
+    character::complete::{alpha1, space0, space1},
+    combinator::{all_consuming, map_res},
+    error::context,
+    multi::separated_list0,
     sequence::{terminated, tuple},
-    Finish, IResult,
+    Finish,
 };
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -44,22 +57,42 @@ use tracing::{debug, error, instrument, warn};
 #[cfg(test)]
 use std::fmt::Display;
 
+/// The largest non-synchronizing literal we accept as we advertise `LITERAL-`
+const LITERAL_MINUS_LIMIT: usize = 4096;
+
+/// The largest command with literals we accept. This limits the size of mails sent with APPEND.
+const LITERAL_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The largest command with literals we accept before the client authenticated
+const UNAUTHENTICATED_LITERAL_LIMIT: usize = LINE_LIMIT;
+
+mod acl;
 mod append;
 pub mod auth;
 pub mod capability;
 mod check;
 mod close;
+mod compress;
+mod copy;
 mod create;
 mod delete;
 mod enable;
+mod expunge;
 mod fetch;
+pub mod idle;
 mod list;
 mod login;
 mod logout;
+mod mime;
+mod move_command;
+mod namespace;
 mod noop;
 pub mod parsers;
+mod quota;
 mod rename;
+mod search;
 mod select;
+mod starttls;
 mod status;
 mod store;
 mod subscribe;
@@ -75,7 +108,20 @@ pub struct Data {
 pub struct CommandData<'a> {
     tag: &'a str,
     command: Commands,
-    arguments: &'a [&'a str],
+    arguments: &'a [Argument],
+}
+
+impl CommandData<'_> {
+    /// Writes the arguments starting at the offset back into the syntax the command grammars expect
+    fn joined_arguments(&self, offset: usize) -> String {
+        self.arguments
+            .get(offset..)
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
 #[derive(Debug)]
@@ -95,18 +141,34 @@ pub enum Commands {
     Capability,
     Check,
     Close,
+    Compress,
+    Copy,
     Create,
     Delete,
+    DeleteAcl,
     Enable,
     Examine,
+    Expunge,
     Fetch,
+    GetAcl,
+    GetQuota,
+    GetQuotaRoot,
+    Idle,
     List,
+    ListRights,
     Login,
     Logout,
     LSub,
+    Move,
+    MyRights,
+    Namespace,
     Noop,
     Rename,
+    Search,
     Select,
+    SetAcl,
+    SetQuota,
+    StartTls,
     Store,
     Subscribe,
     Unsubscribe,
@@ -128,6 +190,7 @@ impl TryFrom<&str> for Commands {
             "logout" => Ok(Commands::Logout),
             "select" => Ok(Commands::Select),
             "examine" => Ok(Commands::Examine),
+            "expunge" => Ok(Commands::Expunge),
             "noop" => Ok(Commands::Noop),
             "check" => Ok(Commands::Check),
             "create" => Ok(Commands::Create),
@@ -135,6 +198,8 @@ impl TryFrom<&str> for Commands {
             "subscribe" => Ok(Commands::Subscribe),
             "unsubscribe" => Ok(Commands::Unsubscribe),
             "close" => Ok(Commands::Close),
+            "copy" => Ok(Commands::Copy),
+            "move" => Ok(Commands::Move),
             "rename" => Ok(Commands::Rename),
             "uid" => Ok(Commands::Uid),
             "fetch" => Ok(Commands::Fetch),
@@ -142,6 +207,19 @@ impl TryFrom<&str> for Commands {
             "append" => Ok(Commands::Append),
             "enable" => Ok(Commands::Enable),
             "status" => Ok(Commands::Status),
+            "idle" => Ok(Commands::Idle),
+            "search" => Ok(Commands::Search),
+            "starttls" => Ok(Commands::StartTls),
+            "compress" => Ok(Commands::Compress),
+            "getquota" => Ok(Commands::GetQuota),
+            "getquotaroot" => Ok(Commands::GetQuotaRoot),
+            "setquota" => Ok(Commands::SetQuota),
+            "setacl" => Ok(Commands::SetAcl),
+            "deleteacl" => Ok(Commands::DeleteAcl),
+            "getacl" => Ok(Commands::GetAcl),
+            "listrights" => Ok(Commands::ListRights),
+            "myrights" => Ok(Commands::MyRights),
+            "namespace" => Ok(Commands::Namespace),
             _ => {
                 warn!("[IMAP] Got unknown command: {}", i);
                 Err(String::from("no other commands supported"))
@@ -150,8 +228,6 @@ impl TryFrom<&str> for Commands {
     }
 }
 
-type Res<'a, U> = IResult<&'a str, U, VerboseError<&'a str>>;
-
 #[instrument(skip(c))]
 fn is_imaptag_char(c: char) -> bool {
     (c.is_ascii() || c == '1' || c == '*' || c == ']')
@@ -166,42 +242,157 @@ fn is_imaptag_char(c: char) -> bool {
         && !c.is_control()
 }
 
-/// Takes as input the full string
+/// Takes as input the full command
 #[instrument(skip(input))]
-fn imaptag(input: &str) -> Res<&str> {
+fn imaptag(input: &[u8]) -> BytesRes<&str> {
     //alphanumeric1, none_of("+(){ %\\\""), one_of("1*]")
     context(
         "imaptag",
-        terminated(take_while1(is_imaptag_char), tag(" ")),
+        terminated(
+            map_res(
+                take_while1(|c: u8| is_imaptag_char(char::from(c))),
+                std::str::from_utf8,
+            ),
+            tag(" "),
+        ),
     )(input)
-    .map(|(next_input, res)| (next_input, res))
 }
 
 /// Gets the input minus the tag
 #[instrument(skip(input))]
-fn command(input: &str) -> Res<Result<Commands, String>> {
-    context("command", alt((terminated(alpha1, tag(" ")), alpha1)))(input)
-        .map(|(next_input, res)| (next_input, res.try_into()))
+fn command(input: &[u8]) -> BytesRes<Result<Commands, String>> {
+    context(
+        "command",
+        map_res(
+            alt((terminated(alpha1, tag(" ")), alpha1)),
+            std::str::from_utf8,
+        ),
+    )(input)
+    .map(|(next_input, res)| (next_input, res.try_into()))
 }
 
 /// Gets the input minus the tag and minus the command
 #[instrument(skip(input))]
-fn arguments(input: &str) -> Res<Vec<&str>> {
+fn arguments(input: &[u8]) -> BytesRes<Vec<Argument>> {
     debug!("parsing arguments");
     context(
         "arguments",
-        many0(alt((
-            terminated(take_while1(|c: char| !c.is_whitespace()), tag(" ")),
-            take_while1(|c: char| c != ' '),
-        ))),
+        terminated(separated_list0(space1, argument), space0),
     )(input)
-    .map(|(x, y)| (x, y))
 }
 
 impl Data {
+    /// Returns the username if the connection is in the authenticated or selected state
+    async fn authenticated_user(&self) -> Option<String> {
+        let read_lock = self.con_state.read().await;
+        if matches!(
+            read_lock.state,
+            State::Authenticated | State::Selected(_, _)
+        ) {
+            read_lock.username.clone()
+        } else {
+            None
+        }
+    }
+
     #[instrument(skip(line))]
-    fn parse_internal(line: &str) -> Res<(&str, Result<Commands, String>, Vec<&str>)> {
-        context("parse_internal", tuple((imaptag, command, arguments)))(line)
+    fn parse_internal(line: &[u8]) -> BytesRes<(&str, Result<Commands, String>, Vec<Argument>)> {
+        context(
+            "parse_internal",
+            all_consuming(tuple((imaptag, command, arguments))),
+        )(line)
+    }
+
+    /// Collects the lines of a command which contains literals.
+    ///
+    /// Returns the whole command once all announced literals arrived.
+    /// The data of the literals gets added with [`Data::add_literal`] in between.
+    #[instrument(skip(self, lines, line))]
+    async fn complete_command<S>(
+        &self,
+        lines: &mut S,
+        line: &str,
+    ) -> color_eyre::eyre::Result<Option<Vec<u8>>>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let response = {
+            let mut write_lock = self.con_state.write().await;
+            let mut pending = write_lock.pending_command.take().unwrap_or_default();
+            if !pending.too_big {
+                pending.text.extend_from_slice(line.as_bytes());
+            }
+            let literal = match trailing_literal(line) {
+                // The client only keeps sending a rejected command if it doesn't wait for us
+                Some(literal) if !pending.too_big || literal.non_synchronizing => literal,
+                _ if pending.too_big => return Ok(None),
+                _ => return Ok(Some(pending.text)),
+            };
+            pending.remaining = literal.length;
+            // Mails are the biggest literals and only get appended once authenticated
+            let limit = if write_lock.state == State::NotAuthenticated {
+                UNAUTHENTICATED_LITERAL_LIMIT
+            } else {
+                LITERAL_LIMIT
+            };
+            let too_big = pending.text.len().saturating_add(literal.length) > limit;
+            let tag = pending
+                .text
+                .split(|x| *x == b' ')
+                .next()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default()
+                .into_owned();
+            if too_big && !literal.non_synchronizing {
+                // The client waits for our answer and doesn't send the literal.
+                // Therefore the command ends here.
+                Some(format!(
+                    "{} NO [TOOBIG] Commands are limited to {} octets",
+                    tag, limit
+                ))
+            } else {
+                let response = if pending.too_big {
+                    None
+                } else if literal.non_synchronizing && literal.length > LITERAL_MINUS_LIMIT {
+                    pending.too_big = true;
+                    Some(format!(
+                        "{} BAD [TOOBIG] Non-synchronizing literals are limited to {} octets",
+                        tag, LITERAL_MINUS_LIMIT
+                    ))
+                } else if too_big {
+                    pending.too_big = true;
+                    Some(format!(
+                        "{} BAD [TOOBIG] Commands are limited to {} octets",
+                        tag, limit
+                    ))
+                } else if literal.non_synchronizing {
+                    None
+                } else {
+                    Some(String::from("+ Ready for literal data"))
+                };
+                if !pending.too_big {
+                    pending.text.extend_from_slice(b"\r\n");
+                }
+                write_lock.pending_command = Some(pending);
+                response
+            }
+        };
+        if let Some(response) = response {
+            lines.send(response).await?;
+        }
+        Ok(None)
+    }
+
+    /// Adds the data of the literal the pending command is waiting for
+    #[instrument(skip(self, literal))]
+    pub async fn add_literal(&self, literal: Vec<u8>) {
+        let mut write_lock = self.con_state.write().await;
+        if let Some(pending) = &mut write_lock.pending_command {
+            if !pending.too_big {
+                pending.text.extend(literal);
+            }
+            pending.remaining = 0;
+        }
     }
 
     #[allow(clippy::too_many_lines)]
@@ -215,14 +406,17 @@ impl Data {
         line: String,
     ) -> color_eyre::eyre::Result<bool>
     where
-        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+        S: Sink<String, Error = SendError>
+            + Sink<Vec<u8>, Error = SendError>
+            + std::marker::Unpin
+            + std::marker::Send,
     {
         debug!("Current state: {:?}", self.con_state.read().await.state);
 
         let con_clone = Arc::clone(&self.con_state);
         let state = { con_clone.read().await.state.clone() };
-        if let State::Authenticating(AuthenticationMethod::Plain, tag) = state {
-            debug!("Second auth stage");
+        if let State::Authenticating(method, tag) = state {
+            debug!("Next auth stage");
             let command_data = CommandData {
                 tag: &tag,
                 // This is unused but needed. We just assume Authenticate here
@@ -233,20 +427,23 @@ impl Data {
                 data: self,
                 auth_data: &line,
             }
-            .plain(lines, database, &command_data)
+            .step(lines, config, database, storage, method, &command_data)
             .await?;
             // We are done here
             return Ok(false);
-        } else if let State::Appending(state) = state {
-            Append { data: self }
-                .append(lines, storage, &line, config, state.tag)
-                .await?;
+        } else if matches!(state, State::Idle(_)) {
+            Idle { data: self }.done(lines, &line).await?;
             // We are done here
             return Ok(false);
         }
+        let line = if let Some(line) = self.complete_command(lines, &line).await? {
+            line
+        } else {
+            // The command continues with a literal
+            return Ok(false);
+        };
         debug!("Starting to parse");
-        let line_borrow: &str = &line;
-        match Data::parse_internal(line_borrow).finish() {
+        match Data::parse_internal(&line).finish() {
             Ok((_, (tag, command, arguments))) => {
                 let command_data = match command {
                     Ok(command) => CommandData {
@@ -268,10 +465,18 @@ impl Data {
                         Enable { data: self }.exec(lines, &command_data).await?;
                     }
                     Commands::Capability => {
-                        Capability.exec(lines, &command_data).await?;
+                        Capability { data: self }.exec(lines, &command_data).await?;
                     }
                     Commands::Login => {
-                        Login.exec(lines, &command_data).await?;
+                        Login { data: self }
+                            .exec(lines, config, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::StartTls => {
+                        StartTls { data: self }.exec(lines, &command_data).await?;
+                    }
+                    Commands::Compress => {
+                        Compress { data: self }.exec(lines, &command_data).await?;
                     }
                     Commands::Logout => {
                         Logout.exec(lines, &command_data).await?;
@@ -279,37 +484,42 @@ impl Data {
                         return Ok(true);
                     }
                     Commands::Authenticate => {
-                        let auth_data = command_data.arguments.last().unwrap();
+                        // The optional initial response of SASL-IR
+                        let auth_data = command_data
+                            .arguments
+                            .get(1)
+                            .and_then(Argument::as_str)
+                            .unwrap_or_default();
                         Authenticate {
                             data: self,
                             auth_data,
                         }
-                        .exec(lines, database, &command_data)
+                        .exec(lines, config, database, storage, &command_data)
                         .await?;
                     }
                     Commands::List => {
                         List { data: self }
-                            .exec(lines, config, storage, &command_data)
+                            .exec(lines, config, database, storage, &command_data)
                             .await?;
                     }
                     Commands::LSub => {
                         LSub { data: self }
-                            .exec(lines, config, storage, &command_data)
+                            .exec(lines, config, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Select => {
                         Select { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Store => {
                         Store { data: self }
-                            .exec(lines, storage, &command_data, false)
+                            .exec(lines, database, storage, &command_data, false)
                             .await?;
                     }
                     Commands::Examine => {
                         Examine { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Create => {
@@ -319,61 +529,134 @@ impl Data {
                     }
                     Commands::Delete => {
                         Delete { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Subscribe => {
                         Subscribe { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Unsubscribe => {
                         Unsubscribe { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::Noop => {
-                        Noop.exec(lines, &command_data).await?;
+                        Noop { data: self }
+                            .exec(lines, storage, &command_data)
+                            .await?;
                     }
                     Commands::Check => {
                         Check { data: self }.exec(lines, &command_data).await?;
                     }
                     Commands::Close => {
                         Close { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::Copy => {
+                        Copy { data: self }
+                            .exec(lines, database, storage, &command_data, false)
+                            .await?;
+                    }
+                    Commands::Move => {
+                        Move { data: self }
+                            .exec(lines, database, storage, &command_data, false)
+                            .await?;
+                    }
+                    Commands::Expunge => {
+                        Expunge { data: self }
+                            .exec(lines, database, storage, &command_data, false)
                             .await?;
                     }
                     Commands::Rename => {
                         Rename { data: self }
-                            .exec(lines, &command_data, storage)
+                            .exec(lines, &command_data, database, storage)
                             .await?;
                     }
                     Commands::Uid => {
                         Uid { data: self }
-                            .exec(lines, &command_data, storage)
+                            .exec(lines, &command_data, database, storage)
                             .await?;
                     }
                     Commands::Fetch => {
                         Fetch { data: self }
-                            .exec(lines, &command_data, storage, false)
+                            .exec(lines, &command_data, database, storage, false)
                             .await?;
                     }
                     Commands::Append => {
                         Append { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::Idle => {
+                        Idle { data: self }
                             .exec(lines, storage, &command_data)
                             .await?;
                     }
                     Commands::Status => {
                         Status { data: self }
-                            .exec(lines, storage, &command_data)
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::Search => {
+                        Search { data: self }
+                            .exec(lines, storage, &command_data, false)
+                            .await?;
+                    }
+                    Commands::GetQuota => {
+                        GetQuota { data: self }
+                            .exec(lines, config, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::GetQuotaRoot => {
+                        GetQuotaRoot { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::SetQuota => {
+                        SetQuota { data: self }
+                            .exec(lines, config, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::SetAcl => {
+                        SetAcl { data: self }
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
+                    Commands::DeleteAcl => {
+                        DeleteAcl { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::GetAcl => {
+                        GetAcl { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::ListRights => {
+                        ListRights { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::MyRights => {
+                        MyRights { data: self }
+                            .exec(lines, database, storage, &command_data)
+                            .await?;
+                    }
+                    Commands::Namespace => {
+                        Namespace { data: self }.exec(lines, &command_data).await?;
+                    }
                 }
             }
             Err(e) => {
                 error!(
-                    "[IMAP] Error parsing command: {}",
-                    convert_error(line_borrow, e)
+                    "[IMAP] Error parsing command: {:?}",
+                    e.errors
+                        .iter()
+                        .map(|(input, kind)| (String::from_utf8_lossy(input), kind))
+                        .collect::<Vec<_>>()
                 );
                 lines
                     .send(String::from("* BAD [SERVERBUG] unable to parse command"))
@@ -391,69 +674,111 @@ mod tests {
     use super::*;
     use convert_case::{Case, Casing};
     use enum_iterator::all;
+    use futures::{channel::mpsc, StreamExt};
 
     #[test]
     fn test_parsing_imaptag() {
-        assert_eq!(imaptag("abcd CAPABILITY"), Ok(("CAPABILITY", "abcd")));
-        assert_eq!(imaptag("12345 CAPABILITY"), Ok(("CAPABILITY", "12345")));
-        assert_eq!(imaptag("abd124 CAPABILITY"), Ok(("CAPABILITY", "abd124")));
+        assert_eq!(
+            imaptag(b"abcd CAPABILITY"),
+            Ok((&b"CAPABILITY"[..], "abcd"))
+        );
+        assert_eq!(
+            imaptag(b"12345 CAPABILITY"),
+            Ok((&b"CAPABILITY"[..], "12345"))
+        );
+        assert_eq!(
+            imaptag(b"abd124 CAPABILITY"),
+            Ok((&b"CAPABILITY"[..], "abd124"))
+        );
     }
 
     #[test]
     fn test_parsing_commands() {
         for command_variant in all::<Commands>() {
             assert_eq!(
-                command(&command_variant.to_string().to_uppercase()),
-                Ok(("", Ok(command_variant)))
+                command(command_variant.to_string().to_uppercase().as_bytes()),
+                Ok((&b""[..], Ok(command_variant)))
             );
             assert_eq!(
-                command(&command_variant.to_string().to_lowercase()),
-                Ok(("", Ok(command_variant)))
+                command(command_variant.to_string().to_lowercase().as_bytes()),
+                Ok((&b""[..], Ok(command_variant)))
             );
             assert_eq!(
                 command(
-                    &command_variant
+                    command_variant
                         .to_string()
                         .to_lowercase()
                         .to_case(Case::Alternating)
+                        .as_bytes()
                 ),
-                Ok(("", Ok(command_variant)))
+                Ok((&b""[..], Ok(command_variant)))
             );
         }
         assert_eq!(
-            command("beeeeep"),
-            Ok(("", Err(String::from("no other commands supported"))))
+            command(b"beeeeep"),
+            Ok((&b""[..], Err(String::from("no other commands supported"))))
         );
     }
 
     #[test]
     fn test_parsing_arguments() {
-        assert_eq!(arguments("PLAIN abd=="), Ok(("", vec!["PLAIN", "abd=="])));
-        assert_eq!(arguments("PLAIN"), Ok(("", vec!["PLAIN"])));
+        assert_eq!(
+            arguments(b"PLAIN abd=="),
+            Ok((
+                &b""[..],
+                vec![
+                    Argument::Atom(String::from("PLAIN")),
+                    Argument::Atom(String::from("abd=="))
+                ]
+            ))
+        );
+        assert_eq!(
+            arguments(b"PLAIN"),
+            Ok((&b""[..], vec![Argument::Atom(String::from("PLAIN"))]))
+        );
+        assert_eq!(
+            arguments(b"\"My Folder\" (MESSAGES UNSEEN)"),
+            Ok((
+                &b""[..],
+                vec![
+                    Argument::Quoted(String::from("My Folder")),
+                    Argument::List(vec![
+                        Argument::Atom(String::from("MESSAGES")),
+                        Argument::Atom(String::from("UNSEEN"))
+                    ])
+                ]
+            ))
+        );
     }
 
     #[test]
     fn test_parsing_authenticate_command() {
-        let result = Data::parse_internal("a AUTHENTICATE PLAIN abcde");
+        let result = Data::parse_internal(b"a AUTHENTICATE PLAIN abcde");
         assert!(result.is_ok());
         let (_, (tag, command, arguments)) = result.unwrap();
         assert!(command.is_ok());
         assert_eq!(tag, "a");
         assert_eq!(command.unwrap(), Commands::Authenticate);
-        assert_eq!(arguments, &["PLAIN", "abcde"]);
+        assert_eq!(
+            arguments,
+            &[
+                Argument::Atom(String::from("PLAIN")),
+                Argument::Atom(String::from("abcde"))
+            ]
+        );
 
-        let result = Data::parse_internal("a AUTHENTICATE PLAIN");
+        let result = Data::parse_internal(b"a AUTHENTICATE PLAIN");
         assert!(result.is_ok());
         let (_, (tag, command, arguments)) = result.unwrap();
         assert!(command.is_ok());
         assert_eq!(tag, "a");
         assert_eq!(command.unwrap(), Commands::Authenticate);
-        assert_eq!(arguments, &["PLAIN"]);
+        assert_eq!(arguments, &[Argument::Atom(String::from("PLAIN"))]);
     }
 
     #[test]
     fn test_parsing_capability_command() {
-        let result = Data::parse_internal("a CAPABILITY");
+        let result = Data::parse_internal(b"a CAPABILITY");
         assert!(result.is_ok());
         let (_, (tag, command, arguments)) = result.unwrap();
         assert!(command.is_ok());
@@ -464,20 +789,144 @@ mod tests {
 
     #[test]
     fn test_parsing_list_command() {
-        let result = Data::parse_internal("18 list \"\" \"*\"");
+        let result = Data::parse_internal(b"18 list \"\" \"*\"");
         assert!(result.is_ok());
         let (_, (tag, command, arguments)) = result.unwrap();
         assert!(command.is_ok());
         assert_eq!(tag, "18");
         assert_eq!(command.unwrap(), Commands::List);
-        assert_eq!(arguments, &["\"\"", "\"*\""]);
+        assert_eq!(
+            arguments,
+            &[
+                Argument::Quoted(String::new()),
+                Argument::Quoted(String::from("*"))
+            ]
+        );
 
-        let result = Data::parse_internal("18 list \"\" \"\"");
+        let result = Data::parse_internal(b"18 list \"\" \"\"");
         assert!(result.is_ok());
         let (_, (tag, command, arguments)) = result.unwrap();
         assert!(command.is_ok());
         assert_eq!(tag, "18");
         assert_eq!(command.unwrap(), Commands::List);
-        assert_eq!(arguments, &["\"\"", "\"\""]);
+        assert_eq!(
+            arguments,
+            &[
+                Argument::Quoted(String::new()),
+                Argument::Quoted(String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_literal_command() {
+        let result = Data::parse_internal(b"a SELECT {9}\r\nMy Folder");
+        assert!(result.is_ok());
+        let (_, (tag, command, arguments)) = result.unwrap();
+        assert_eq!(tag, "a");
+        assert_eq!(command.unwrap(), Commands::Select);
+        assert_eq!(arguments, &[Argument::Literal(b"My Folder".to_vec())]);
+
+        assert!(Data::parse_internal(b"a SELECT \"My Folder").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_complete_command() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        assert_eq!(
+            data.complete_command(&mut tx, "a LOGIN {4}").await.unwrap(),
+            None
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("+ Ready for literal data"))
+        );
+        assert_eq!(
+            data.con_state
+                .read()
+                .await
+                .pending_command
+                .as_ref()
+                .map(|x| x.remaining),
+            Some(4)
+        );
+        data.add_literal(b"user".to_vec()).await;
+        assert_eq!(data.complete_command(&mut tx, " {6+}").await.unwrap(), None);
+        // Line breaks in a literal don't end the command
+        data.add_literal(b"pa\r\n\xffs".to_vec()).await;
+        assert_eq!(
+            data.complete_command(&mut tx, "").await.unwrap(),
+            Some(b"a LOGIN {4}\r\nuser {6+}\r\npa\r\n\xffs".to_vec())
+        );
+        assert!(data.con_state.read().await.pending_command.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_literal_too_big() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        assert_eq!(
+            data.complete_command(&mut tx, "a APPEND INBOX {5000+}")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "a BAD [TOOBIG] Non-synchronizing literals are limited to 4096 octets"
+            ))
+        );
+        // The codec discards the data of the rejected literal
+        data.add_literal(Vec::new()).await;
+        // The rest of the rejected command must not be taken as a new command
+        assert_eq!(data.complete_command(&mut tx, "").await.unwrap(), None);
+        assert_eq!(
+            data.complete_command(&mut tx, "b NOOP").await.unwrap(),
+            Some(b"b NOOP".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_synchronizing_literal_limit() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        assert_eq!(
+            data.complete_command(&mut tx, "a LOGIN {100000}")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "a NO [TOOBIG] Commands are limited to 8192 octets"
+            ))
+        );
+        // The client doesn't send the literal and continues with the next command
+        assert!(data.con_state.read().await.pending_command.is_none());
+        assert_eq!(
+            data.complete_command(&mut tx, "b NOOP").await.unwrap(),
+            Some(b"b NOOP".to_vec())
+        );
+
+        data.con_state.write().await.state = State::Authenticated;
+        assert_eq!(
+            data.complete_command(&mut tx, "c APPEND INBOX {100000}")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("+ Ready for literal data"))
+        );
     }
 }
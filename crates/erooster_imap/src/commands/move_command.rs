@@ -0,0 +1,264 @@
+use crate::{
+    commands::{
+        acl::{mailbox_owner, mailbox_rights, missing_rights},
+        noop::send_expunged,
+        parsers::parse_selected_range,
+        quota::{fits_quota, mails_size},
+        search::{mails_in_set, to_sequence_set},
+        CommandData, Data,
+    },
+    state::{Access, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use nom::Finish;
+use std::sync::Arc;
+use tracing::{error, instrument};
+
+pub struct Move<'a> {
+    pub data: &'a Data,
+}
+
+impl Move<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+        is_uid: bool,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let offset = if is_uid { 1 } else { 0 };
+        let arguments = &command_data.arguments;
+        if arguments.len() < 2 + offset {
+            lines
+                .send(format!("{} BAD Missing arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        let (state, username, sequence_map) = {
+            let read_lock = self.data.con_state.read().await;
+            (
+                read_lock.state.clone(),
+                read_lock.username.clone(),
+                read_lock.sequence_map.clone(),
+            )
+        };
+        if let State::Selected(folder, access) = state {
+            if access == Access::ReadOnly {
+                lines
+                    .send(format!("{} NO in read-only mode", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            let username = username.unwrap();
+            // Moving removes the mails from the source mailbox
+            let rights = mailbox_rights(&database, &storage, &username, &folder).await?;
+            if let Some(response) = missing_rights(command_data.tag, &rights, "te") {
+                lines.send(response).await?;
+                return Ok(());
+            }
+            let ranges = if let Ok(("", ranges)) =
+                parse_selected_range(arguments[offset].as_str().unwrap_or_default()).finish()
+            {
+                ranges
+            } else {
+                lines
+                    .send(format!("{} BAD Invalid sequence set", command_data.tag))
+                    .await?;
+                return Ok(());
+            };
+
+            let target_folder = if let Some(target_folder) = arguments[offset + 1].as_str() {
+                target_folder.to_string()
+            } else {
+                lines
+                    .send(format!("{} BAD Invalid mailbox name", command_data.tag))
+                    .await?;
+                return Ok(());
+            };
+            let target_path = storage.to_ondisk_path(target_folder.clone(), username.clone())?;
+            if !target_path.exists() {
+                lines
+                    .send(format!(
+                        "{} NO [TRYCREATE] Target mailbox does not exist",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            let rights = mailbox_rights(&database, &storage, &username, &target_folder).await?;
+            if let Some(response) = missing_rights(command_data.tag, &rights, "i") {
+                lines.send(response).await?;
+                return Ok(());
+            }
+
+            let mailbox_path = storage.to_ondisk_path(folder.clone(), username.clone())?;
+            let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+            let mails: Vec<MailEntryType> = mails_in_set(mails, &sequence_map, &ranges, is_uid)
+                .into_iter()
+                .map(|(_, mail)| mail)
+                .collect();
+
+            // The quota of the owner only changes when the mails leave their mailboxes
+            let (source_owner, _) = mailbox_owner(&storage, &username, &folder)?;
+            let (target_owner, _) = mailbox_owner(&storage, &username, &target_folder)?;
+            if source_owner != target_owner
+                && !fits_quota(
+                    &database,
+                    &storage,
+                    target_owner,
+                    mails_size(&mails).await,
+                    u64::try_from(mails.len())?,
+                )
+                .await?
+            {
+                lines
+                    .send(format!(
+                        "{} NO [OVERQUOTA] Quota exceeded",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+
+            let mut source_uids = Vec::new();
+            let mut moved_ids = Vec::new();
+            let mut failed = false;
+            for mail in mails {
+                match storage
+                    .move_to(&mailbox_path, mail.id(), &target_path)
+                    .await
+                {
+                    Ok(moved_id) => {
+                        moved_ids.push(moved_id);
+                        source_uids.push(mail.uid());
+                    }
+                    Err(error) => {
+                        error!("Failed to move {}: {}", mail.id(), error);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+
+            // The mails which were moved before a failure are gone from this mailbox and
+            // the client has to learn about that either way
+            if !moved_ids.is_empty() {
+                let target_mails: Vec<MailEntryType> = storage.list_all(&target_path).await;
+                let target_uids = moved_ids
+                    .iter()
+                    .filter_map(|id| target_mails.iter().find(|mail| mail.id() == id))
+                    .map(MailEntry::uid)
+                    .collect::<Vec<_>>();
+                if target_uids.len() == source_uids.len() {
+                    let uid_validity = storage.get_uid_validity(&target_path).await?;
+                    lines
+                        .feed(format!(
+                            "* OK [COPYUID {} {} {}] Moved",
+                            uid_validity,
+                            to_sequence_set(&source_uids),
+                            to_sequence_set(&target_uids)
+                        ))
+                        .await?;
+                }
+                let mut write_lock = self.data.con_state.write().await;
+                // Going from the highest sequence number down keeps the lower ones valid
+                let expunged: Vec<(usize, i64)> = source_uids
+                    .iter()
+                    .rev()
+                    .filter_map(|uid| {
+                        write_lock
+                            .sequence_map
+                            .remove(*uid)
+                            .map(|sequence| (sequence, *uid))
+                    })
+                    .collect();
+                send_expunged(lines, &expunged, write_lock.qresync_enabled()).await?;
+            }
+
+            let name = if is_uid { "UID MOVE" } else { "MOVE" };
+            if failed {
+                lines
+                    .feed(format!("{} NO {} failed", command_data.tag, name))
+                    .await?;
+            } else {
+                lines
+                    .feed(format!("{} OK {} completed", command_data.tag, name))
+                    .await?;
+            }
+            lines.flush().await?;
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{parsers::Argument, CommandData, Commands};
+    use crate::state::{Compression, Connection, SequenceMap};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_selected_ro() {
+        let move_command = Move {
+            data: &Data {
+                con_state: Arc::new(RwLock::new(Connection {
+                    state: State::Selected("INBOX".to_string(), Access::ReadOnly),
+                    secure: true,
+                    starttls: false,
+                    oauth: false,
+                    username: Some(String::from("test")),
+                    active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
+                })),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Move,
+            arguments: &[
+                Argument::Atom(String::from("1:*")),
+                Argument::Atom(String::from("Archive")),
+            ],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(erooster_core::backend::storage::get_storage(
+            Arc::clone(&database),
+            Arc::clone(&config),
+        ));
+        let res = move_command
+            .exec(&mut tx, database, storage, &cmd_data, false)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 NO in read-only mode"))
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::commands::{CommandData, Data};
+use erooster_core::backend::storage::SHARED_NAMESPACE;
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use tracing::instrument;
+
+pub struct Namespace<'a> {
+    pub data: &'a Data,
+}
+
+impl Namespace<'_> {
+    #[instrument(skip(self, lines, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        if self.data.authenticated_user().await.is_none() {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        // The owner in the shared namespace may contain dots so it uses its own delimiter
+        lines
+            .feed(format!(
+                "* NAMESPACE ((\"\" \".\")) NIL ((\"{}\" \"/\"))",
+                SHARED_NAMESPACE
+            ))
+            .await?;
+        lines
+            .feed(format!("{} OK NAMESPACE completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{CommandData, Commands};
+    use crate::state::{Connection, State};
+    use futures::{channel::mpsc, StreamExt};
+
+    #[tokio::test]
+    async fn test_namespace() {
+        let data = Data {
+            con_state: Connection::new(true),
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Namespace,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = Namespace { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 NO invalid state")));
+
+        {
+            let mut write_lock = data.con_state.write().await;
+            write_lock.state = State::Authenticated;
+            write_lock.username = Some(String::from("test"));
+        }
+        let res = Namespace { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from(
+                "* NAMESPACE ((\"\" \".\")) NIL ((\"Shared/\" \"/\"))"
+            ))
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 OK NAMESPACE completed"))
+        );
+    }
+}
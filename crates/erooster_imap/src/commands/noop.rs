@@ -1,23 +1,105 @@
-use crate::commands::CommandData;
+use crate::{
+    commands::{search::to_sequence_set, CommandData, Data},
+    state::{SequenceMap, State},
+};
+use erooster_core::backend::storage::{MailEntry, MailEntryType, MailStorage, Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use std::sync::Arc;
 use tracing::instrument;
 
-pub struct Noop;
+pub struct Noop<'a> {
+    pub data: &'a Data,
+}
 
-impl Noop {
-    #[instrument(skip(self, lines, command_data))]
+impl Noop<'_> {
+    #[instrument(skip(self, lines, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        // TODO return status as suggested in https://www.rfc-editor.org/rfc/rfc9051.html#name-noop-command
+        // Clients poll with NOOP so we tell them about new and removed mails
+        let mut write_lock = self.data.con_state.write().await;
+        if let State::Selected(folder, _) = write_lock.state.clone() {
+            let mailbox_path =
+                storage.to_ondisk_path(folder, write_lock.username.clone().unwrap())?;
+            let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+            let qresync = write_lock.qresync_enabled();
+            send_mailbox_updates(lines, &mut write_lock.sequence_map, &mails, qresync).await?;
+        }
         lines
             .send(format!("{} OK NOOP completed", command_data.tag))
             .await?;
         Ok(())
     }
 }
+
+/// Sends EXPUNGE and EXISTS responses for mails which were removed from or added to the mailbox
+/// and updates the sequence map accordingly.
+#[instrument(skip(lines, sequence_map, mails))]
+pub async fn send_mailbox_updates<S>(
+    lines: &mut S,
+    sequence_map: &mut SequenceMap,
+    mails: &[MailEntryType],
+    qresync: bool,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    let uids: Vec<i64> = mails.iter().map(MailEntry::uid).collect();
+    send_uid_updates(lines, sequence_map, &uids, qresync).await
+}
+
+/// Sends EXPUNGE and EXISTS responses for the difference between the sequence map and the uids
+/// currently in the mailbox and updates the sequence map accordingly.
+#[instrument(skip(lines, sequence_map, uids))]
+pub async fn send_uid_updates<S>(
+    lines: &mut S,
+    sequence_map: &mut SequenceMap,
+    uids: &[i64],
+    qresync: bool,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    let (expunged, exists) = sequence_map.update(uids);
+    send_expunged(lines, &expunged, qresync).await?;
+    if exists {
+        lines
+            .feed(format!("* {} EXISTS", sequence_map.len()))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sends the EXPUNGE responses for the sequence numbers and uids in the order they are given.
+///
+/// Once QRESYNC is enabled a single VANISHED response with the uids is sent instead.
+#[instrument(skip(lines, expunged))]
+pub async fn send_expunged<S>(
+    lines: &mut S,
+    expunged: &[(usize, i64)],
+    qresync: bool,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    if qresync {
+        let mut uids: Vec<i64> = expunged.iter().map(|(_, uid)| *uid).collect();
+        if !uids.is_empty() {
+            uids.sort_unstable();
+            lines
+                .feed(format!("* VANISHED {}", to_sequence_set(&uids)))
+                .await?;
+        }
+    } else {
+        for (sequence, _) in expunged {
+            lines.feed(format!("* {} EXPUNGE", sequence)).await?;
+        }
+    }
+    Ok(())
+}
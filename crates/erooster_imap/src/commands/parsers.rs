@@ -1,17 +1,21 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag_no_case, take_while1},
-    character::complete::{char, digit1, space1},
-    combinator::{map, opt},
-    error::{context, VerboseError},
-    multi::separated_list0,
-    sequence::{delimited, pair, separated_pair, tuple},
+    bytes::complete::{is_not, tag, tag_no_case, take_while, take_while1},
+    character::complete::{char, digit1, none_of, one_of, space1},
+    combinator::{map, map_opt, map_res, opt, recognize},
+    error::{context, ErrorKind, ParseError, VerboseError},
+    multi::{many0, many1, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
+use std::fmt;
 use tracing::instrument;
 
 type Res<'a, U> = IResult<&'a str, U, VerboseError<&'a str>>;
 
+/// The result of parsing a command which may contain literals with any octet
+pub type BytesRes<'a, U> = IResult<&'a [u8], U, VerboseError<&'a [u8]>>;
+
 #[instrument(skip(input))]
 fn header_list(input: &str) -> Res<Vec<&str>> {
     context(
@@ -27,12 +31,22 @@ fn header_list(input: &str) -> Res<Vec<&str>> {
     )(input)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SectionText {
     Header,
     Text,
     HeaderFields(Vec<String>),
     HeaderFieldsNot(Vec<String>),
+    /// The MIME header of a body part
+    Mime,
+}
+
+/// The section of a mail requested by `BODY[...]`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    /// The part numbers of the MIME part. Empty for the whole mail.
+    pub part: Vec<u32>,
+    pub text: Option<SectionText>,
 }
 
 #[instrument(skip(input))]
@@ -50,15 +64,61 @@ fn section_text(input: &str) -> Res<SectionText> {
             ),
             map(tag_no_case("Header"), |_| SectionText::Header),
             map(tag_no_case("Text"), |_| SectionText::Text),
+            map(tag_no_case("Mime"), |_| SectionText::Mime),
+        )),
+    )(input)
+}
+
+#[instrument(skip(input))]
+fn section_part(input: &str) -> Res<Vec<u32>> {
+    context(
+        "section_part",
+        separated_list1(char('.'), map_res(digit1, str::parse::<u32>)),
+    )(input)
+}
+
+#[instrument(skip(input))]
+fn section_spec(input: &str) -> Res<Section> {
+    context(
+        "section_spec",
+        alt((
+            map(
+                pair(section_part, opt(preceded(char('.'), section_text))),
+                |(part, text)| Section { part, text },
+            ),
+            map(section_text, |text| Section {
+                part: vec![],
+                text: Some(text),
+            }),
         )),
     )(input)
 }
 
 #[instrument(skip(input))]
-fn section(input: &str) -> Res<Option<SectionText>> {
+fn section(input: &str) -> Res<Section> {
     context(
         "section",
-        delimited(char('['), opt(section_text), char(']')),
+        delimited(
+            char('['),
+            map(opt(section_spec), Option::unwrap_or_default),
+            char(']'),
+        ),
+    )(input)
+}
+
+/// The part specifier of `BINARY` which can't address header or text sections
+#[instrument(skip(input))]
+fn section_binary(input: &str) -> Res<Section> {
+    context(
+        "section_binary",
+        delimited(
+            char('['),
+            map(opt(section_part), |part| Section {
+                part: part.unwrap_or_default(),
+                text: None,
+            }),
+            char(']'),
+        ),
     )(input)
 }
 
@@ -71,12 +131,13 @@ pub enum FetchAttributes {
     // TODO remove this with imap4rev2 adaption in clients or feature flag
     RFC822Header,
     Uid,
+    ModSeq,
     BodyStructure,
-    BodySection(Option<SectionText>, Option<(u64, u64)>),
-    BodyPeek(Option<SectionText>, Option<(u64, u64)>),
-    Binary(Option<SectionText>, Option<(u64, u64)>),
-    BinaryPeek(Option<SectionText>, Option<(u64, u64)>),
-    BinarySize(Option<SectionText>),
+    BodySection(Section, Option<(u64, u64)>),
+    BodyPeek(Section, Option<(u64, u64)>),
+    Binary(Section, Option<(u64, u64)>),
+    BinaryPeek(Section, Option<(u64, u64)>),
+    BinarySize(Section),
 }
 
 #[allow(clippy::too_many_lines)]
@@ -95,6 +156,7 @@ fn fetch_attributes(input: &str) -> Res<FetchAttributes> {
                 FetchAttributes::RFC822Header
             }),
             map(tag_no_case("UID"), |_| FetchAttributes::Uid),
+            map(tag_no_case("MODSEQ"), |_| FetchAttributes::ModSeq),
             map(
                 tuple((
                     tag_no_case("BODY.PEEK"),
@@ -142,7 +204,7 @@ fn fetch_attributes(input: &str) -> Res<FetchAttributes> {
             map(
                 tuple((
                     tag_no_case("BINARY.PEEK"),
-                    section,
+                    section_binary,
                     opt(space1),
                     opt(delimited(
                         char('<'),
@@ -161,9 +223,10 @@ fn fetch_attributes(input: &str) -> Res<FetchAttributes> {
                     )
                 },
             ),
-            map(tuple((tag_no_case("BINARY.SIZE"), section)), |(_, x)| {
-                FetchAttributes::BinarySize(x)
-            }),
+            map(
+                tuple((tag_no_case("BINARY.SIZE"), section_binary)),
+                |(_, x)| FetchAttributes::BinarySize(x),
+            ),
             map(
                 tuple((
                     tag_no_case("BODY"),
@@ -189,7 +252,7 @@ fn fetch_attributes(input: &str) -> Res<FetchAttributes> {
             map(
                 tuple((
                     tag_no_case("BINARY"),
-                    section,
+                    section_binary,
                     opt(space1),
                     opt(delimited(
                         char('<'),
@@ -213,13 +276,13 @@ fn fetch_attributes(input: &str) -> Res<FetchAttributes> {
                 |_| FetchAttributes::BodyStructure,
             ),
             map(tag_no_case("BODY.PEEK"), |_| {
-                FetchAttributes::BinaryPeek(None, None)
+                FetchAttributes::BodyPeek(Section::default(), None)
             }),
             map(tag_no_case("RFC822.PEEK"), |_| {
-                FetchAttributes::BinaryPeek(None, None)
+                FetchAttributes::BodyPeek(Section::default(), None)
             }),
             map(tag_no_case("BODY"), |_| {
-                FetchAttributes::BodySection(None, None)
+                FetchAttributes::BodySection(Section::default(), None)
             }),
         )),
     )(input)
@@ -264,37 +327,55 @@ pub fn fetch_arguments(input: &str) -> Res<FetchArguments> {
     context("fetch_arguments", inner_fetch_arguments)(input)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RangeEnd {
     End(i64),
     All,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Range {
     Single(i64),
     Range(i64, RangeEnd),
+    /// `*` on its own which is the highest sequence number or uid
+    Last,
+    /// `$` which refers to the result saved by `SEARCH RETURN (SAVE)`
+    Saved,
+}
+
+#[instrument(skip(input))]
+fn seq_number(input: &str) -> Res<RangeEnd> {
+    context(
+        "seq_number",
+        alt((
+            map(char('*'), |_| RangeEnd::All),
+            map(map_res(digit1, str::parse::<i64>), RangeEnd::End),
+        )),
+    )(input)
 }
 
 #[instrument(skip(input))]
 pub fn parse_selected_range(input: &str) -> Res<Vec<Range>> {
     context(
         "parse_selected_range",
-        separated_list0(
+        separated_list1(
             char(','),
             alt((
+                map(char('$'), |_| Range::Saved),
                 map(
-                    separated_pair(
-                        digit1,
-                        char(':'),
-                        map(alt((tag_no_case("*"), digit1)), |x: &str| match x {
-                            "*" => RangeEnd::All,
-                            _ => RangeEnd::End(x.parse::<i64>().unwrap()),
-                        }),
-                    ),
-                    |(x, y): (&str, RangeEnd)| Range::Range(x.parse::<i64>().unwrap(), y),
+                    separated_pair(seq_number, char(':'), seq_number),
+                    // `*:4` is the same as `4:*`
+                    |range| match range {
+                        (RangeEnd::End(start), end) | (end, RangeEnd::End(start)) => {
+                            Range::Range(start, end)
+                        }
+                        (RangeEnd::All, RangeEnd::All) => Range::Last,
+                    },
                 ),
-                map(digit1, |x: &str| Range::Single(x.parse::<i64>().unwrap())),
+                map(seq_number, |x| match x {
+                    RangeEnd::End(id) => Range::Single(id),
+                    RangeEnd::All => Range::Last,
+                }),
             )),
         ),
     )(input)
@@ -368,16 +449,17 @@ pub enum DateTime {
 }
 
 #[instrument(skip(input))]
-fn date_time(input: &str) -> Res<DateTime> {
+pub fn date_time(input: &str) -> Res<DateTime> {
     context(
         "date_time",
         map(
             tuple((
                 opt(tuple((day_of_week, tag_no_case(","), space1))),
-                digit1,
-                space1,
+                // IMAP separates the date with dashes and pads the day with a space
+                preceded(opt(char(' ')), digit1),
+                alt((space1, tag("-"))),
                 month,
-                space1,
+                alt((space1, tag("-"))),
                 digit1,
                 space1,
                 time,
@@ -401,50 +483,701 @@ fn date_time(input: &str) -> Res<DateTime> {
     )(input)
 }
 
-pub struct LiteralSize {
+/// Checks if the char is allowed in an atom as defined in RFC 9051
+const fn is_atom_char(c: char) -> bool {
+    !c.is_ascii_control() && !matches!(c, '(' | ')' | '{' | ' ' | '%' | '*' | '"' | '\\' | ']')
+}
+
+/// Checks if the char is allowed in an astring as defined in RFC 9051
+const fn is_astring_char(c: char) -> bool {
+    is_atom_char(c) || c == ']'
+}
+
+#[instrument(skip(input))]
+pub fn atom(input: &str) -> Res<&str> {
+    context("atom", take_while1(is_atom_char))(input)
+}
+
+/// Parses a quoted string and removes the escaping
+#[instrument(skip(input))]
+pub fn quoted(input: &str) -> Res<String> {
+    context(
+        "quoted",
+        delimited(
+            char('"'),
+            map(
+                many0(alt((none_of("\\\""), preceded(char('\\'), one_of("\\\""))))),
+                |chars: Vec<char>| chars.into_iter().collect::<String>(),
+            ),
+            char('"'),
+        ),
+    )(input)
+}
+
+#[instrument(skip(input))]
+pub fn astring(input: &str) -> Res<String> {
+    context(
+        "astring",
+        alt((
+            quoted,
+            map(take_while1(is_astring_char), ToString::to_string),
+        )),
+    )(input)
+}
+
+/// A single argument of a command as defined by the generic IMAP grammar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Argument {
+    /// An atom like a flag or a sequence set.
+    ///
+    /// Sections like `BODY[HEADER.FIELDS (To)]` are part of the atom.
+    Atom(String),
+    /// A quoted string without the escaping
+    Quoted(String),
+    /// The content of a `{n}` literal
+    Literal(Vec<u8>),
+    /// The content of a `~{n}` literal8 which may contain NUL octets
+    Literal8(Vec<u8>),
+    /// A parenthesized list
+    List(Vec<Argument>),
+}
+
+impl Argument {
+    /// The value of the argument if it is an atom or a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Argument::Atom(value) | Argument::Quoted(value) => Some(value),
+            Argument::Literal(value) | Argument::Literal8(value) => std::str::from_utf8(value).ok(),
+            Argument::List(_) => None,
+        }
+    }
+
+    /// Checks if the argument is the atom ignoring the case
+    pub fn is_atom(&self, name: &str) -> bool {
+        matches!(self, Argument::Atom(value) if value.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Writes the argument back in the IMAP syntax for the grammars of the single commands.
+///
+/// These grammars only deal with text so literals which aren't valid UTF-8 get replaced.
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Argument::Atom(value) => write!(f, "{}", value),
+            Argument::Quoted(value) => write_string(f, value, ""),
+            Argument::Literal(value) => write_string(f, &String::from_utf8_lossy(value), ""),
+            Argument::Literal8(value) => write_string(f, &String::from_utf8_lossy(value), "~"),
+            Argument::List(values) => {
+                let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+                write!(f, "({})", values.join(" "))
+            }
+        }
+    }
+}
+
+/// Writes the string quoted if possible and as literal with the prefix otherwise
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str, prefix: &str) -> fmt::Result {
+    if value.contains(['\r', '\n', '\0']) {
+        write!(f, "{}{{{}}}\r\n{}", prefix, value.len(), value)
+    } else {
+        write!(
+            f,
+            "\"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+}
+
+/// Checks if the char may be part of an atom argument outside of a section
+const fn is_argument_char(c: char) -> bool {
+    !c.is_ascii_control() && !matches!(c, '(' | ')' | '{' | ' ' | '"' | '[')
+}
+
+#[instrument(skip(input))]
+fn literal(input: &[u8]) -> BytesRes<Argument> {
+    let (input, (binary, length)) = context(
+        "literal",
+        terminated(
+            pair(
+                map(opt(char('~')), |x| x.is_some()),
+                delimited(
+                    char('{'),
+                    terminated(
+                        map_opt(digit1, |digits: &[u8]| {
+                            std::str::from_utf8(digits).ok()?.parse::<usize>().ok()
+                        }),
+                        opt(char('+')),
+                    ),
+                    char('}'),
+                ),
+            ),
+            tag("\r\n"),
+        ),
+    )(input)?;
+    // The length counts octets. Only literal8 may contain NUL.
+    if input.len() < length || (!binary && input[..length].contains(&0)) {
+        return Err(nom::Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::Eof,
+        )));
+    }
+    let (data, input) = input.split_at(length);
+    let argument = if binary {
+        Argument::Literal8(data.to_vec())
+    } else {
+        Argument::Literal(data.to_vec())
+    };
+    Ok((input, argument))
+}
+
+/// Parses a quoted string argument and removes the escaping
+#[instrument(skip(input))]
+fn quoted_argument(input: &[u8]) -> BytesRes<String> {
+    context(
+        "quoted",
+        map_res(
+            delimited(
+                char('"'),
+                many0(alt((
+                    is_not("\\\""),
+                    preceded(char('\\'), alt((tag("\\"), tag("\"")))),
+                ))),
+                char('"'),
+            ),
+            |parts: Vec<&[u8]>| String::from_utf8(parts.concat()),
+        ),
+    )(input)
+}
+
+/// Parses an argument of a command.
+///
+/// The command is made of octets as the literals in it may contain any data.
+/// Everything else is text.
+#[instrument(skip(input))]
+pub fn argument(input: &[u8]) -> BytesRes<Argument> {
+    context(
+        "argument",
+        alt((
+            literal,
+            map(quoted_argument, Argument::Quoted),
+            map(
+                delimited(char('('), separated_list0(space1, argument), char(')')),
+                Argument::List,
+            ),
+            map_res(
+                recognize(many1(alt((
+                    take_while1(|c: u8| is_argument_char(char::from(c))),
+                    recognize(delimited(
+                        char('['),
+                        take_while(|c: u8| c != b']'),
+                        char(']'),
+                    )),
+                )))),
+                |atom: &[u8]| {
+                    std::str::from_utf8(atom).map(|atom| Argument::Atom(atom.to_string()))
+                },
+            ),
+        )),
+    )(input)
+}
+
+/// The announcement of a literal at the end of a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiteralMarker {
     pub length: usize,
-    pub continuation: bool,
+    /// Set for `{n+}` which the client sends without waiting for our continuation request
+    pub non_synchronizing: bool,
 }
 
-pub type AppendArgs<'a> = (Option<Vec<&'a str>>, Option<DateTime>, LiteralSize);
+/// Finds the literal which is announced at the end of the line
+pub fn trailing_literal(line: &str) -> Option<LiteralMarker> {
+    let inner = line.strip_suffix('}')?;
+    let length = &inner[inner.rfind('{')? + 1..];
+    let (length, non_synchronizing) = match length.strip_suffix('+') {
+        Some(length) => (length, true),
+        None => (length, false),
+    };
+    if length.is_empty() || !length.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    Some(LiteralMarker {
+        length: length.parse().ok()?,
+        non_synchronizing,
+    })
+}
 
 #[instrument(skip(input))]
-pub fn append_arguments(input: &str) -> Res<AppendArgs> {
+fn number(input: &str) -> Res<u64> {
+    context("number", map_res(digit1, str::parse::<u64>))(input)
+}
+
+/// Converts a date of the gregorian calendar to the days since the unix epoch
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn month_number(month: &str) -> i64 {
+    match month.to_lowercase().as_str() {
+        "jan" => 1,
+        "feb" => 2,
+        "mar" => 3,
+        "apr" => 4,
+        "may" => 5,
+        "jun" => 6,
+        "jul" => 7,
+        "aug" => 8,
+        "sep" => 9,
+        "oct" => 10,
+        "nov" => 11,
+        // The month parser only leaves december
+        _ => 12,
+    }
+}
+
+#[instrument(skip(input))]
+fn date_text(input: &str) -> Res<i64> {
     context(
-        "append_arguments",
+        "date_text",
+        map(
+            tuple((
+                map_res(digit1, str::parse::<i64>),
+                char('-'),
+                month,
+                char('-'),
+                map_res(digit1, str::parse::<i64>),
+            )),
+            |(day, _, month, _, year)| days_from_civil(year, month_number(month), day),
+        ),
+    )(input)
+}
+
+/// Parses a date like `1-Feb-1994` to the days since the unix epoch
+#[instrument(skip(input))]
+pub fn date(input: &str) -> Res<i64> {
+    context(
+        "date",
+        alt((delimited(char('"'), date_text, char('"')), date_text)),
+    )(input)
+}
+
+/// A search key as defined in RFC 9051.
+///
+/// Dates are stored as days since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchKey {
+    All,
+    Answered,
+    Bcc(String),
+    Before(i64),
+    Body(String),
+    Cc(String),
+    Deleted,
+    Draft,
+    Flagged,
+    From(String),
+    Header(String, String),
+    Keyword(String),
+    Larger(u64),
+    New,
+    Not(Box<SearchKey>),
+    Old,
+    On(i64),
+    Or(Box<SearchKey>, Box<SearchKey>),
+    Recent,
+    Seen,
+    SentBefore(i64),
+    SentOn(i64),
+    SentSince(i64),
+    Since(i64),
+    Smaller(u64),
+    Subject(String),
+    Text(String),
+    To(String),
+    Uid(Vec<Range>),
+    Unanswered,
+    Undeleted,
+    Undraft,
+    Unflagged,
+    Unkeyword(String),
+    Unseen,
+    SequenceSet(Vec<Range>),
+    /// All of the keys have to match
+    And(Vec<SearchKey>),
+}
+
+#[allow(clippy::too_many_lines)]
+#[instrument(skip(input))]
+pub fn search_key(input: &str) -> Res<SearchKey> {
+    context(
+        "search_key",
+        alt((
+            alt((
+                map(tag_no_case("ALL"), |_| SearchKey::All),
+                map(tag_no_case("ANSWERED"), |_| SearchKey::Answered),
+                map(
+                    preceded(pair(tag_no_case("BCC"), space1), astring),
+                    SearchKey::Bcc,
+                ),
+                map(
+                    preceded(pair(tag_no_case("BEFORE"), space1), date),
+                    SearchKey::Before,
+                ),
+                map(
+                    preceded(pair(tag_no_case("BODY"), space1), astring),
+                    SearchKey::Body,
+                ),
+                map(
+                    preceded(pair(tag_no_case("CC"), space1), astring),
+                    SearchKey::Cc,
+                ),
+                map(tag_no_case("DELETED"), |_| SearchKey::Deleted),
+                map(tag_no_case("DRAFT"), |_| SearchKey::Draft),
+                map(tag_no_case("FLAGGED"), |_| SearchKey::Flagged),
+                map(
+                    preceded(pair(tag_no_case("FROM"), space1), astring),
+                    SearchKey::From,
+                ),
+                map(
+                    tuple((tag_no_case("HEADER"), space1, astring, space1, astring)),
+                    |(_, _, name, _, value)| SearchKey::Header(name, value),
+                ),
+                map(preceded(pair(tag_no_case("KEYWORD"), space1), atom), |x| {
+                    SearchKey::Keyword(x.to_string())
+                }),
+                map(
+                    preceded(pair(tag_no_case("LARGER"), space1), number),
+                    SearchKey::Larger,
+                ),
+                map(tag_no_case("NEW"), |_| SearchKey::New),
+                map(
+                    preceded(pair(tag_no_case("NOT"), space1), search_key),
+                    |x| SearchKey::Not(Box::new(x)),
+                ),
+                map(tag_no_case("OLD"), |_| SearchKey::Old),
+                map(
+                    preceded(pair(tag_no_case("ON"), space1), date),
+                    SearchKey::On,
+                ),
+                map(
+                    tuple((tag_no_case("OR"), space1, search_key, space1, search_key)),
+                    |(_, _, x, _, y)| SearchKey::Or(Box::new(x), Box::new(y)),
+                ),
+            )),
+            alt((
+                map(tag_no_case("RECENT"), |_| SearchKey::Recent),
+                map(tag_no_case("SEEN"), |_| SearchKey::Seen),
+                map(
+                    preceded(pair(tag_no_case("SENTBEFORE"), space1), date),
+                    SearchKey::SentBefore,
+                ),
+                map(
+                    preceded(pair(tag_no_case("SENTON"), space1), date),
+                    SearchKey::SentOn,
+                ),
+                map(
+                    preceded(pair(tag_no_case("SENTSINCE"), space1), date),
+                    SearchKey::SentSince,
+                ),
+                map(
+                    preceded(pair(tag_no_case("SINCE"), space1), date),
+                    SearchKey::Since,
+                ),
+                map(
+                    preceded(pair(tag_no_case("SMALLER"), space1), number),
+                    SearchKey::Smaller,
+                ),
+                map(
+                    preceded(pair(tag_no_case("SUBJECT"), space1), astring),
+                    SearchKey::Subject,
+                ),
+                map(
+                    preceded(pair(tag_no_case("TEXT"), space1), astring),
+                    SearchKey::Text,
+                ),
+                map(
+                    preceded(pair(tag_no_case("TO"), space1), astring),
+                    SearchKey::To,
+                ),
+                map(
+                    preceded(pair(tag_no_case("UID"), space1), parse_selected_range),
+                    SearchKey::Uid,
+                ),
+                map(tag_no_case("UNANSWERED"), |_| SearchKey::Unanswered),
+                map(tag_no_case("UNDELETED"), |_| SearchKey::Undeleted),
+                map(tag_no_case("UNDRAFT"), |_| SearchKey::Undraft),
+                map(tag_no_case("UNFLAGGED"), |_| SearchKey::Unflagged),
+                map(
+                    preceded(pair(tag_no_case("UNKEYWORD"), space1), atom),
+                    |x| SearchKey::Unkeyword(x.to_string()),
+                ),
+                map(tag_no_case("UNSEEN"), |_| SearchKey::Unseen),
+                map(parse_selected_range, SearchKey::SequenceSet),
+                map(
+                    delimited(char('('), separated_list1(space1, search_key), char(')')),
+                    SearchKey::And,
+                ),
+            )),
+        )),
+    )(input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchReturnOption {
+    Min,
+    Max,
+    All,
+    Count,
+    /// Saves the result for `$`
+    Save,
+}
+
+#[instrument(skip(input))]
+fn search_return_option(input: &str) -> Res<SearchReturnOption> {
+    context(
+        "search_return_option",
+        alt((
+            map(tag_no_case("MIN"), |_| SearchReturnOption::Min),
+            map(tag_no_case("MAX"), |_| SearchReturnOption::Max),
+            map(tag_no_case("ALL"), |_| SearchReturnOption::All),
+            map(tag_no_case("COUNT"), |_| SearchReturnOption::Count),
+            map(tag_no_case("SAVE"), |_| SearchReturnOption::Save),
+        )),
+    )(input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchArguments {
+    /// If set the client expects an ESEARCH response
+    pub return_options: Option<Vec<SearchReturnOption>>,
+    pub charset: Option<String>,
+    pub key: SearchKey,
+}
+
+#[instrument(skip(input))]
+pub fn search_arguments(input: &str) -> Res<SearchArguments> {
+    context(
+        "search_arguments",
         map(
             tuple((
                 opt(delimited(
-                    char('('),
-                    separated_list0(
-                        space1,
-                        take_while1(|c: char| c != ')' && !c.is_whitespace()),
+                    pair(tag_no_case("RETURN"), space1),
+                    delimited(
+                        char('('),
+                        separated_list0(space1, search_return_option),
+                        char(')'),
                     ),
-                    char(')'),
+                    space1,
                 )),
-                opt(space1),
-                opt(date_time),
-                opt(space1),
-                opt(tag_no_case("UTF8")),
-                opt(space1),
-                opt(tag_no_case("(")),
-                opt(tag_no_case("~")),
-                tag_no_case("{"),
-                map(
-                    pair(
-                        map(take_while1(|c: char| c.is_ascii_digit()), |s: &str| {
-                            s.parse::<usize>().unwrap()
-                        }),
-                        map(opt(tag_no_case("+")), |x| x.is_some()),
+                opt(delimited(
+                    pair(tag_no_case("CHARSET"), space1),
+                    astring,
+                    space1,
+                )),
+                separated_list1(space1, search_key),
+            )),
+            |(return_options, charset, keys)| SearchArguments {
+                return_options,
+                charset,
+                key: SearchKey::And(keys),
+            },
+        ),
+    )(input)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusDataItem {
+    Messages,
+    UidNext,
+    UidValidity,
+    Unseen,
+    Deleted,
+    Size,
+    Recent,
+    HighestModSeq,
+}
+
+#[instrument(skip(input))]
+fn status_data_item(input: &str) -> Res<StatusDataItem> {
+    context(
+        "status_data_item",
+        alt((
+            map(tag_no_case("MESSAGES"), |_| StatusDataItem::Messages),
+            map(tag_no_case("UIDNEXT"), |_| StatusDataItem::UidNext),
+            map(tag_no_case("UIDVALIDITY"), |_| StatusDataItem::UidValidity),
+            map(tag_no_case("UNSEEN"), |_| StatusDataItem::Unseen),
+            map(tag_no_case("DELETED"), |_| StatusDataItem::Deleted),
+            map(tag_no_case("SIZE"), |_| StatusDataItem::Size),
+            map(tag_no_case("RECENT"), |_| StatusDataItem::Recent),
+            map(tag_no_case("HIGHESTMODSEQ"), |_| {
+                StatusDataItem::HighestModSeq
+            }),
+        )),
+    )(input)
+}
+
+/// Parses a list of status data items like `(MESSAGES UNSEEN)`
+#[instrument(skip(input))]
+pub fn status_data_items(input: &str) -> Res<Vec<StatusDataItem>> {
+    context(
+        "status_data_items",
+        delimited(
+            char('('),
+            separated_list1(space1, status_data_item),
+            char(')'),
+        ),
+    )(input)
+}
+
+/// Parses the mailbox and the requested data items of the STATUS command
+#[instrument(skip(input))]
+pub fn status_arguments(input: &str) -> Res<(String, Vec<StatusDataItem>)> {
+    context(
+        "status_arguments",
+        separated_pair(astring, space1, status_data_items),
+    )(input)
+}
+
+/// Checks if the char is allowed in a list-mailbox as defined in RFC 9051
+const fn is_list_char(c: char) -> bool {
+    is_atom_char(c) || matches!(c, '%' | '*' | ']')
+}
+
+#[instrument(skip(input))]
+pub fn list_mailbox(input: &str) -> Res<String> {
+    context(
+        "list_mailbox",
+        alt((quoted, map(take_while1(is_list_char), ToString::to_string))),
+    )(input)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSelectOption {
+    Subscribed,
+    Remote,
+    RecursiveMatch,
+    SpecialUse,
+}
+
+#[instrument(skip(input))]
+fn list_select_option(input: &str) -> Res<ListSelectOption> {
+    context(
+        "list_select_option",
+        alt((
+            map(tag_no_case("SUBSCRIBED"), |_| ListSelectOption::Subscribed),
+            map(tag_no_case("REMOTE"), |_| ListSelectOption::Remote),
+            map(tag_no_case("RECURSIVEMATCH"), |_| {
+                ListSelectOption::RecursiveMatch
+            }),
+            map(tag_no_case("SPECIAL-USE"), |_| ListSelectOption::SpecialUse),
+        )),
+    )(input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListReturnOption {
+    Subscribed,
+    Children,
+    SpecialUse,
+    Status(Vec<StatusDataItem>),
+}
+
+#[instrument(skip(input))]
+fn list_return_option(input: &str) -> Res<ListReturnOption> {
+    context(
+        "list_return_option",
+        alt((
+            map(tag_no_case("SUBSCRIBED"), |_| ListReturnOption::Subscribed),
+            map(tag_no_case("CHILDREN"), |_| ListReturnOption::Children),
+            map(tag_no_case("SPECIAL-USE"), |_| ListReturnOption::SpecialUse),
+            map(
+                preceded(pair(tag_no_case("STATUS"), space1), status_data_items),
+                ListReturnOption::Status,
+            ),
+        )),
+    )(input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListArguments {
+    pub selection_options: Vec<ListSelectOption>,
+    pub reference_name: String,
+    pub mailbox_patterns: Vec<String>,
+    pub return_options: Vec<ListReturnOption>,
+}
+
+/// Parses the arguments of the LIST command including the extensions of RFC 5258
+#[instrument(skip(input))]
+pub fn list_arguments(input: &str) -> Res<ListArguments> {
+    context(
+        "list_arguments",
+        map(
+            tuple((
+                opt(terminated(
+                    delimited(
+                        char('('),
+                        separated_list0(space1, list_select_option),
+                        char(')'),
                     ),
-                    |(length, continuation)| LiteralSize {
-                        length,
-                        continuation,
-                    },
-                ),
-                tag_no_case("}"),
+                    space1,
+                )),
+                astring,
+                space1,
+                alt((
+                    delimited(char('('), separated_list1(space1, list_mailbox), char(')')),
+                    map(list_mailbox, |x| vec![x]),
+                )),
+                opt(preceded(
+                    tuple((space1, tag_no_case("RETURN"), space1)),
+                    delimited(
+                        char('('),
+                        separated_list0(space1, list_return_option),
+                        char(')'),
+                    ),
+                )),
             )),
-            |(flags, _, datetime, _, _, _, _, _, _, literal, _)| (flags, datetime, literal),
+            |(selection_options, reference_name, _, mailbox_patterns, return_options)| {
+                ListArguments {
+                    selection_options: selection_options.unwrap_or_default(),
+                    reference_name,
+                    mailbox_patterns,
+                    return_options: return_options.unwrap_or_default(),
+                }
+            },
+        ),
+    )(input)
+}
+
+/// Parses the mailbox name and the optional special-use attributes of the CREATE command
+#[instrument(skip(input))]
+pub fn create_arguments(input: &str) -> Res<(String, Vec<&str>)> {
+    context(
+        "create_arguments",
+        map(
+            pair(
+                astring,
+                opt(preceded(
+                    space1,
+                    delimited(
+                        pair(char('('), pair(tag_no_case("USE"), space1)),
+                        delimited(
+                            char('('),
+                            separated_list0(
+                                space1,
+                                take_while1(|c: char| c == '\\' || is_atom_char(c)),
+                            ),
+                            char(')'),
+                        ),
+                        char(')'),
+                    ),
+                )),
+            ),
+            |(mailbox, special_use)| (mailbox, special_use.unwrap_or_default()),
         ),
     )(input)
 }
@@ -463,6 +1196,47 @@ mod tests {
         assert_eq!(unparsed, "");
     }
 
+    #[tokio::test]
+    async fn test_section_part() {
+        assert_eq!(
+            section("[1.2.MIME]"),
+            Ok((
+                "",
+                Section {
+                    part: vec![1, 2],
+                    text: Some(SectionText::Mime)
+                }
+            ))
+        );
+        assert_eq!(
+            section("[HEADER.FIELDS.NOT (To)]"),
+            Ok((
+                "",
+                Section {
+                    part: vec![],
+                    text: Some(SectionText::HeaderFieldsNot(vec![String::from("To")]))
+                }
+            ))
+        );
+        assert_eq!(section("[]"), Ok(("", Section::default())));
+    }
+
+    #[test]
+    fn test_section_binary() {
+        assert_eq!(
+            section_binary("[1.2]"),
+            Ok((
+                "",
+                Section {
+                    part: vec![1, 2],
+                    text: None
+                }
+            ))
+        );
+        assert_eq!(section_binary("[]"), Ok(("", Section::default())));
+        assert!(section_binary("[1.HEADER]").is_err());
+    }
+
     #[tokio::test]
     async fn test_section_text() {
         let input = "HEADER.FIELDS (From To Cc Bcc Subject Date Message-ID Priority X-Priority References Newsgroups In-Reply-To Content-Type Reply-To x-spamd-result x-spam-score x-rspamd-score x-spam-status x-mailscanner-spamcheck X-Spam-Flag x-spam-level)";
@@ -502,4 +1276,228 @@ mod tests {
         let (unparsed, _) = args.unwrap();
         assert_eq!(unparsed, "");
     }
+
+    #[tokio::test]
+    async fn test_parse_selected_range() {
+        assert_eq!(
+            parse_selected_range("1,3:*,*:5,*"),
+            Ok((
+                "",
+                vec![
+                    Range::Single(1),
+                    Range::Range(3, RangeEnd::All),
+                    Range::Range(5, RangeEnd::All),
+                    Range::Last
+                ]
+            ))
+        );
+        assert_eq!(parse_selected_range("$"), Ok(("", vec![Range::Saved])));
+        assert_eq!(
+            parse_selected_range("1,$"),
+            Ok(("", vec![Range::Single(1), Range::Saved]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_date() {
+        assert_eq!(date("1-Jan-1970"), Ok(("", 0)));
+        assert_eq!(date("\"1-Feb-1994\""), Ok(("", 8797)));
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    }
+
+    #[test]
+    fn test_date_time() {
+        assert_eq!(
+            date_time("17-Jul-1996 02:44:25 -0700"),
+            Ok((
+                "",
+                DateTime::DateTime(String::from("17 Jul 1996 02:44:25 -0700"))
+            ))
+        );
+        assert_eq!(
+            date_time(" 7-Jul-1996 02:44:25 +0200"),
+            Ok((
+                "",
+                DateTime::DateTime(String::from("7 Jul 1996 02:44:25 +0200"))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_argument() {
+        assert_eq!(
+            argument(b"\"My \\\"Folder\\\"\""),
+            Ok((&b""[..], Argument::Quoted(String::from("My \"Folder\""))))
+        );
+        assert_eq!(
+            argument("\"Entwürfe\"".as_bytes()),
+            Ok((&b""[..], Argument::Quoted(String::from("Entwürfe"))))
+        );
+        assert_eq!(
+            argument(b"{5}\r\nHello World"),
+            Ok((&b" World"[..], Argument::Literal(b"Hello".to_vec())))
+        );
+        assert_eq!(
+            argument(b"~{3}\r\na\0b"),
+            Ok((&b""[..], Argument::Literal8(b"a\0b".to_vec())))
+        );
+        assert_eq!(
+            argument(b"{2}\r\n\xff\x80"),
+            Ok((&b""[..], Argument::Literal(b"\xff\x80".to_vec())))
+        );
+        assert!(argument(b"{3}\r\na\0b").is_err());
+        assert_eq!(
+            argument(b"(FLAGS BODY.PEEK[HEADER.FIELDS (From To)]<0.100>)"),
+            Ok((
+                &b""[..],
+                Argument::List(vec![
+                    Argument::Atom(String::from("FLAGS")),
+                    Argument::Atom(String::from("BODY.PEEK[HEADER.FIELDS (From To)]<0.100>")),
+                ])
+            ))
+        );
+        assert_eq!(
+            Argument::List(vec![
+                Argument::Atom(String::from("\\Seen")),
+                Argument::Literal(b"My Folder".to_vec()),
+            ])
+            .to_string(),
+            "(\\Seen \"My Folder\")"
+        );
+    }
+
+    #[test]
+    fn test_trailing_literal() {
+        assert_eq!(
+            trailing_literal("a LOGIN {5}"),
+            Some(LiteralMarker {
+                length: 5,
+                non_synchronizing: false
+            })
+        );
+        assert_eq!(
+            trailing_literal("a APPEND INBOX ~{310+}"),
+            Some(LiteralMarker {
+                length: 310,
+                non_synchronizing: true
+            })
+        );
+        assert_eq!(trailing_literal("a SELECT \"{5}\""), None);
+        assert_eq!(trailing_literal("a SELECT {}"), None);
+    }
+
+    #[tokio::test]
+    async fn test_search_key() {
+        let args = search_key("OR FROM alice (SEEN UNDELETED)");
+        assert_eq!(
+            args,
+            Ok((
+                "",
+                SearchKey::Or(
+                    Box::new(SearchKey::From(String::from("alice"))),
+                    Box::new(SearchKey::And(vec![SearchKey::Seen, SearchKey::Undeleted]))
+                )
+            ))
+        );
+        let args = search_key("HEADER \"X-Foo\" \"some \\\"value\\\"\"");
+        assert_eq!(
+            args,
+            Ok((
+                "",
+                SearchKey::Header(String::from("X-Foo"), String::from("some \"value\""))
+            ))
+        );
+        let args = search_key("UID 1:*");
+        assert_eq!(
+            args,
+            Ok(("", SearchKey::Uid(vec![Range::Range(1, RangeEnd::All)])))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_arguments() {
+        let input = "RETURN (MIN COUNT) CHARSET UTF-8 SINCE 1-Feb-1994 NOT SUBJECT \"foo bar\" 2:4";
+        let args = search_arguments(input);
+        println!("{:?}", args);
+        assert!(args.is_ok());
+        let (unparsed, args) = args.unwrap();
+        assert_eq!(unparsed, "");
+        assert_eq!(
+            args.return_options,
+            Some(vec![SearchReturnOption::Min, SearchReturnOption::Count])
+        );
+        assert_eq!(args.charset, Some(String::from("UTF-8")));
+        assert_eq!(
+            args.key,
+            SearchKey::And(vec![
+                SearchKey::Since(8797),
+                SearchKey::Not(Box::new(SearchKey::Subject(String::from("foo bar")))),
+                SearchKey::SequenceSet(vec![Range::Range(2, RangeEnd::End(4))]),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_arguments() {
+        let args = status_arguments("\"My Folder\" (MESSAGES UIDNEXT unseen)");
+        assert_eq!(
+            args,
+            Ok((
+                "",
+                (
+                    String::from("My Folder"),
+                    vec![
+                        StatusDataItem::Messages,
+                        StatusDataItem::UidNext,
+                        StatusDataItem::Unseen
+                    ]
+                )
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_arguments() {
+        let args = list_arguments(
+            "(SUBSCRIBED RECURSIVEMATCH) \"\" (\"INBOX\" Lists/%) RETURN (CHILDREN STATUS (MESSAGES UNSEEN))",
+        );
+        assert_eq!(
+            args,
+            Ok((
+                "",
+                ListArguments {
+                    selection_options: vec![
+                        ListSelectOption::Subscribed,
+                        ListSelectOption::RecursiveMatch
+                    ],
+                    reference_name: String::new(),
+                    mailbox_patterns: vec![String::from("INBOX"), String::from("Lists/%")],
+                    return_options: vec![
+                        ListReturnOption::Children,
+                        ListReturnOption::Status(vec![
+                            StatusDataItem::Messages,
+                            StatusDataItem::Unseen
+                        ])
+                    ],
+                }
+            ))
+        );
+        let args = list_arguments("\"\" *");
+        assert!(args.is_ok());
+        let (unparsed, args) = args.unwrap();
+        assert_eq!(unparsed, "");
+        assert_eq!(args.mailbox_patterns, vec![String::from("*")]);
+    }
+
+    #[tokio::test]
+    async fn test_create_arguments() {
+        assert_eq!(
+            create_arguments("\"Sent Items\" (USE (\\Sent))"),
+            Ok(("", (String::from("Sent Items"), vec!["\\Sent"])))
+        );
+        assert_eq!(
+            create_arguments("Projects"),
+            Ok(("", (String::from("Projects"), vec![])))
+        );
+    }
 }
@@ -0,0 +1,324 @@
+use crate::commands::{
+    acl::{mailbox_owner, mailbox_rights, missing_rights},
+    parsers::Argument,
+    CommandData, Data,
+};
+use erooster_core::{
+    backend::{
+        database::{Database, Quota, DB},
+        storage::{MailEntry, MailEntryType, MailStorage, QuotaUsage, Storage},
+    },
+    config::Config,
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use std::sync::Arc;
+use tracing::{error, instrument};
+
+pub struct GetQuota<'a> {
+    pub data: &'a Data,
+}
+
+impl GetQuota<'_> {
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let username = if let Some(username) = self.data.authenticated_user().await {
+            username
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+        let root = if let [root] = command_data.arguments {
+            root.as_str().unwrap_or_default()
+        } else {
+            lines
+                .send(format!("{} BAD Missing quota root", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        let is_admin = config.admins.contains(&username);
+        if root != username && !(is_admin && database.user_exists(root).await) {
+            lines
+                .send(format!(
+                    "{} NO [NONEXISTENT] No such quota root",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+
+        let quota = database.get_quota(root).await?;
+        let usage = storage.quota_usage(root).await?;
+        lines.feed(quota_response(root, &quota, &usage)).await?;
+        lines
+            .feed(format!("{} OK GETQUOTA completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct GetQuotaRoot<'a> {
+    pub data: &'a Data,
+}
+
+impl GetQuotaRoot<'_> {
+    #[instrument(skip(self, lines, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let username = if let Some(username) = self.data.authenticated_user().await {
+            username
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+        let mailbox = if let [mailbox] = command_data.arguments {
+            mailbox.as_str().unwrap_or_default()
+        } else {
+            lines
+                .send(format!("{} BAD Missing mailbox name", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        let mailbox_path = storage.to_ondisk_path(mailbox.to_string(), username.clone())?;
+        if !mailbox_path.exists() {
+            lines
+                .send(format!(
+                    "{} NO [NONEXISTENT] No such mailbox",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        let rights = mailbox_rights(&database, &storage, &username, mailbox).await?;
+        if let Some(response) = missing_rights(command_data.tag, &rights, "") {
+            lines.send(response).await?;
+            return Ok(());
+        }
+
+        // Every user has a single quota root named like the user which covers all their mailboxes
+        let (root, _) = mailbox_owner(&storage, &username, mailbox)?;
+        let quota = database.get_quota(root).await?;
+        let usage = storage.quota_usage(root).await?;
+        lines
+            .feed(format!("* QUOTAROOT \"{}\" \"{}\"", mailbox, root))
+            .await?;
+        lines.feed(quota_response(root, &quota, &usage)).await?;
+        lines
+            .feed(format!("{} OK GETQUOTAROOT completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct SetQuota<'a> {
+    pub data: &'a Data,
+}
+
+impl SetQuota<'_> {
+    #[instrument(skip(self, lines, config, database, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let username = if let Some(username) = self.data.authenticated_user().await {
+            username
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+        let (root, quota) = if let [root, Argument::List(limits)] = command_data.arguments {
+            if let (Some(root), Some(quota)) = (root.as_str(), parse_quota_limits(limits)) {
+                (root, quota)
+            } else {
+                lines
+                    .send(format!("{} BAD Invalid resource limits", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+        } else {
+            lines
+                .send(format!("{} BAD Missing arguments", command_data.tag))
+                .await?;
+            return Ok(());
+        };
+
+        if !config.admins.contains(&username) {
+            lines
+                .send(format!(
+                    "{} NO [NOPERM] Only admins may change quotas",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        if let Err(e) = database.set_quota(root, quota).await {
+            error!("Failed to set the quota of {}: {}", root, e);
+            lines
+                .send(format!(
+                    "{} NO [NONEXISTENT] No such quota root",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+
+        let usage = storage.quota_usage(root).await?;
+        lines.feed(quota_response(root, &quota, &usage)).await?;
+        lines
+            .feed(format!("{} OK SETQUOTA completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
+        Ok(())
+    }
+}
+
+/// Parses the resource name and limit pairs of SETQUOTA.
+///
+/// Resources which are not listed are no longer limited.
+fn parse_quota_limits(limits: &[Argument]) -> Option<Quota> {
+    let mut quota = Quota::default();
+    for pair in limits.chunks(2) {
+        if let [name, limit] = pair {
+            let limit = limit.as_str()?.parse().ok()?;
+            match name.as_str()?.to_uppercase().as_str() {
+                "STORAGE" => quota.storage = Some(limit),
+                "MESSAGE" => quota.messages = Some(limit),
+                _ => return None,
+            }
+        } else {
+            return None;
+        }
+    }
+    Some(quota)
+}
+
+/// Checks if messages of the given size still fit into the quota of the user they count towards
+#[instrument(skip(database, storage))]
+pub async fn fits_quota(
+    database: &DB,
+    storage: &Storage,
+    owner: &str,
+    size: u64,
+    messages: u64,
+) -> color_eyre::eyre::Result<bool> {
+    let quota = database.get_quota(owner).await?;
+    let usage = storage.quota_usage(owner).await?;
+    Ok(quota.allows(&usage, size, messages))
+}
+
+/// The size of the stored mails in octets
+pub async fn mails_size(mails: &[MailEntryType]) -> u64 {
+    let mut size = 0;
+    for mail in mails {
+        // Mails which got removed in the meantime don't take any space
+        if let Ok(metadata) = tokio::fs::metadata(mail.path()).await {
+            size += metadata.len();
+        }
+    }
+    size
+}
+
+/// Formats the QUOTA response which only lists the limited resources
+fn quota_response(root: &str, quota: &Quota, usage: &QuotaUsage) -> String {
+    let mut resources = Vec::new();
+    if let Some(limit) = quota.storage {
+        // STORAGE is counted in units of 1024 octets
+        resources.push(format!(
+            "STORAGE {} {}",
+            (usage.storage + 1023) / 1024,
+            limit
+        ));
+    }
+    if let Some(limit) = quota.messages {
+        resources.push(format!("MESSAGE {} {}", usage.messages, limit));
+    }
+    format!("* QUOTA \"{}\" ({})", root, resources.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quota_limits() {
+        let limits = [
+            Argument::Atom(String::from("storage")),
+            Argument::Atom(String::from("512")),
+            Argument::Atom(String::from("MESSAGE")),
+            Argument::Atom(String::from("1000")),
+        ];
+        assert_eq!(
+            parse_quota_limits(&limits),
+            Some(Quota {
+                storage: Some(512),
+                messages: Some(1000),
+            })
+        );
+        assert_eq!(parse_quota_limits(&[]), Some(Quota::default()));
+
+        let limits = [
+            Argument::Atom(String::from("MAILBOX")),
+            Argument::Atom(String::from("10")),
+        ];
+        assert_eq!(parse_quota_limits(&limits), None);
+        let limits = [Argument::Atom(String::from("STORAGE"))];
+        assert_eq!(parse_quota_limits(&limits), None);
+    }
+
+    #[test]
+    fn test_quota_response() {
+        let usage = QuotaUsage {
+            storage: 2049,
+            messages: 3,
+        };
+        assert_eq!(
+            quota_response(
+                "test@example.com",
+                &Quota {
+                    storage: Some(512),
+                    messages: Some(1000),
+                },
+                &usage
+            ),
+            "* QUOTA \"test@example.com\" (STORAGE 3 512 MESSAGE 3 1000)"
+        );
+        assert_eq!(
+            quota_response("test@example.com", &Quota::default(), &usage),
+            "* QUOTA \"test@example.com\" ()"
+        );
+    }
+}
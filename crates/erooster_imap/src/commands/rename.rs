@@ -1,38 +1,102 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::{MailStorage, Storage};
+use crate::commands::{
+    acl::{mailbox_owner, mailbox_rights, missing_rights},
+    list::is_reserved,
+    CommandData, Data,
+};
+use erooster_core::backend::{
+    database::{Database, DB},
+    storage::{shared_mailbox, MailStorage, Storage},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
-use tokio::fs;
-use tracing::instrument;
+use tracing::{error, instrument};
 
 pub struct Rename<'a> {
     pub data: &'a Data,
 }
 
 impl Rename<'_> {
-    #[instrument(skip(self, lines, command_data, storage))]
+    #[instrument(skip(self, lines, command_data, database, storage))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         command_data: &CommandData<'_>,
+        database: DB,
         storage: Arc<Storage>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let args = &command_data.arguments;
-        assert!(args.len() == 2);
-        let old_folder = args[0].replace('/', ".");
-        let old_mailbox_path = storage.to_ondisk_path(
-            old_folder.clone(),
-            self.data.con_state.read().await.username.clone().unwrap(),
-        )?;
-        let new_folder = args[1].replace('/', ".");
-        let new_mailbox_path = storage.to_ondisk_path(
-            new_folder.clone(),
-            self.data.con_state.read().await.username.clone().unwrap(),
-        )?;
-        fs::rename(old_mailbox_path, new_mailbox_path).await?;
+        let folders = match command_data.arguments {
+            [old_folder, new_folder] => old_folder.as_str().zip(new_folder.as_str()),
+            _ => None,
+        };
+        let (old_folder, new_folder) = if let Some(folders) = folders {
+            folders
+        } else {
+            lines
+                .send(format!(
+                    "{} BAD [SERVERBUG] invalid arguments",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        };
+        let username = self.data.con_state.read().await.username.clone().unwrap();
+        // Shared mailboxes are resolved to the directory of their owner
+        let old_mailbox_path = storage.to_ondisk_path(old_folder.to_string(), username.clone())?;
+        let new_mailbox_path = storage.to_ondisk_path(new_folder.to_string(), username.clone())?;
+        let (old_owner, old_name) = mailbox_owner(&storage, &username, old_folder)?;
+        let (new_owner, new_name) = mailbox_owner(&storage, &username, new_folder)?;
+        let rights = if old_mailbox_path.exists() {
+            mailbox_rights(&database, &storage, &username, old_folder).await?
+        } else {
+            String::new()
+        };
+        if let Some(response) = missing_rights(command_data.tag, &rights, "x") {
+            lines.send(response).await?;
+            return Ok(());
+        }
+        if old_owner != new_owner {
+            lines
+                .send(format!(
+                    "{} NO [CANNOT] Mailboxes can't be moved to another user",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        // Own mailboxes can't be renamed into the shared namespace where they would be hidden
+        if shared_mailbox(new_folder).is_none() && is_reserved(new_folder) {
+            lines
+                .send(format!(
+                    "{} NO [CANNOT] Mailboxes can't be created in the shared namespace",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        if new_mailbox_path.exists() {
+            lines
+                .send(format!(
+                    "{} NO [ALREADYEXISTS] Mailbox already exists",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        if let Err(e) = storage
+            .rename_mailbox(&old_mailbox_path, &new_mailbox_path)
+            .await
+        {
+            error!("Failed to rename folder: {}", e);
+            lines
+                .send(format!("{} NO RENAME failure", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+        // The rights move along with the mailbox
+        database.rename_acl(old_owner, &old_name, &new_name).await?;
         lines
             .send(format!("{} OK RENAME completed", command_data.tag))
             .await?;
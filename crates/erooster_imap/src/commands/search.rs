@@ -0,0 +1,504 @@
+use crate::{
+    commands::{
+        parsers::{search_arguments, Range, RangeEnd, SearchKey, SearchReturnOption},
+        CommandData, Data,
+    },
+    state::{SequenceMap, State},
+};
+use erooster_core::backend::storage::{MailEntry, MailEntryType, MailStorage, Storage};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use mailparse::ParsedMail;
+use nom::{error::convert_error, Finish};
+use std::{collections::HashMap, sync::Arc, time::UNIX_EPOCH};
+use tracing::{debug, error, instrument};
+
+pub struct Search<'a> {
+    pub data: &'a Data,
+}
+
+impl Search<'_> {
+    #[instrument(skip(self, lines, storage, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        storage: Arc<Storage>,
+        command_data: &CommandData<'_>,
+        is_uid: bool,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let offset = if is_uid { 1 } else { 0 };
+        let (state, username, sequence_map) = {
+            let read_lock = self.data.con_state.read().await;
+            (
+                read_lock.state.clone(),
+                read_lock.username.clone(),
+                read_lock.sequence_map.clone(),
+            )
+        };
+        if let State::Selected(folder, _) = state {
+            let mailbox_path = storage.to_ondisk_path(folder, username.unwrap())?;
+            let search_args = command_data.joined_arguments(offset);
+            debug!("Search args: {}", search_args);
+            match search_arguments(&search_args).finish() {
+                Ok((left, args)) if left.is_empty() => {
+                    if let Some(charset) = &args.charset {
+                        if !charset.eq_ignore_ascii_case("UTF-8")
+                            && !charset.eq_ignore_ascii_case("US-ASCII")
+                        {
+                            lines
+                                .send(format!(
+                                    "{} NO [BADCHARSET (UTF-8 US-ASCII)] Unsupported charset",
+                                    command_data.tag
+                                ))
+                                .await?;
+                            return Ok(());
+                        }
+                    }
+                    // Keywords are not stored so searching for them can't give a right answer
+                    if uses_keywords(&args.key) {
+                        lines
+                            .send(format!(
+                                "{} BAD Keywords are not supported",
+                                command_data.tag
+                            ))
+                            .await?;
+                        return Ok(());
+                    }
+
+                    let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+                    let searcher = Searcher {
+                        sequence_map: &sequence_map,
+                    };
+
+                    let mut results = Vec::new();
+                    for (sequence, mut mail) in known_mails(mails, &sequence_map) {
+                        if searcher.matches(&args.key, &mut mail, sequence) {
+                            results.push((sequence, mail.uid()));
+                        }
+                    }
+                    let ids: Vec<i64> = if is_uid {
+                        results.iter().map(|(_, uid)| *uid).collect()
+                    } else {
+                        results
+                            .iter()
+                            .map(|(sequence, _)| i64::try_from(*sequence).unwrap_or(i64::MAX))
+                            .collect()
+                    };
+
+                    if let Some(return_options) = args.return_options {
+                        if return_options.contains(&SearchReturnOption::Save) {
+                            let uids: Vec<i64> = results.iter().map(|(_, uid)| *uid).collect();
+                            self.data.con_state.write().await.sequence_map.saved_search =
+                                saved_result(&return_options, &uids);
+                        }
+                        // SAVE on its own doesn't return anything
+                        if return_options != [SearchReturnOption::Save] {
+                            lines
+                                .feed(esearch_response(
+                                    command_data.tag,
+                                    is_uid,
+                                    &return_options,
+                                    &ids,
+                                ))
+                                .await?;
+                        }
+                    } else if ids.is_empty() {
+                        lines.feed(String::from("* SEARCH")).await?;
+                    } else {
+                        let ids = ids
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        lines.feed(format!("* SEARCH {}", ids)).await?;
+                    }
+
+                    if is_uid {
+                        lines
+                            .feed(format!("{} OK UID SEARCH completed", command_data.tag))
+                            .await?;
+                    } else {
+                        lines
+                            .feed(format!("{} OK SEARCH completed", command_data.tag))
+                            .await?;
+                    }
+                    lines.flush().await?;
+                }
+                Ok((left, _)) => {
+                    error!("Failed to parse search arguments. Leftover: {}", left);
+                    lines
+                        .send(format!("{} BAD Unable to parse", command_data.tag))
+                        .await?;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to parse search arguments: {}",
+                        convert_error(search_args.as_str(), e)
+                    );
+                    lines
+                        .send(format!("{} BAD Unable to parse", command_data.tag))
+                        .await?;
+                }
+            }
+        } else {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn esearch_response(
+    tag: &str,
+    is_uid: bool,
+    return_options: &[SearchReturnOption],
+    results: &[i64],
+) -> String {
+    let mut response = format!("* ESEARCH (TAG \"{}\")", tag);
+    if is_uid {
+        response.push_str(" UID");
+    }
+    // An empty list of return options is the same as asking for ALL
+    let return_options = if return_options
+        .iter()
+        .all(|option| *option == SearchReturnOption::Save)
+    {
+        &[SearchReturnOption::All]
+    } else {
+        return_options
+    };
+    for option in return_options {
+        match option {
+            SearchReturnOption::Min => {
+                if let Some(min) = results.first() {
+                    response.push_str(&format!(" MIN {}", min));
+                }
+            }
+            SearchReturnOption::Max => {
+                if let Some(max) = results.last() {
+                    response.push_str(&format!(" MAX {}", max));
+                }
+            }
+            SearchReturnOption::All => {
+                if !results.is_empty() {
+                    response.push_str(&format!(" ALL {}", to_sequence_set(results)));
+                }
+            }
+            SearchReturnOption::Count => {
+                response.push_str(&format!(" COUNT {}", results.len()));
+            }
+            SearchReturnOption::Save => {}
+        }
+    }
+    response
+}
+
+/// Compresses a sorted list of ids into a sequence set like `1:3,5`
+pub fn to_sequence_set(ids: &[i64]) -> String {
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for id in ids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *id => *end = *id,
+            _ => ranges.push((*id, *id)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}:{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Picks the uids `SEARCH RETURN (SAVE)` saves.
+///
+/// If only MIN and MAX are requested only those get saved.
+fn saved_result(return_options: &[SearchReturnOption], uids: &[i64]) -> Vec<i64> {
+    let min = return_options.contains(&SearchReturnOption::Min);
+    let max = return_options.contains(&SearchReturnOption::Max);
+    let all = return_options.contains(&SearchReturnOption::All)
+        || return_options.contains(&SearchReturnOption::Count);
+    if all || !(min || max) {
+        return uids.to_vec();
+    }
+    let mut saved = Vec::new();
+    if let Some(first) = uids.first().filter(|_| min) {
+        saved.push(*first);
+    }
+    if let Some(last) = uids.last().filter(|_| max) {
+        if !saved.contains(last) {
+            saved.push(*last);
+        }
+    }
+    saved
+}
+
+/// Checks if the value is in any of the ranges. `*` is resolved to `max`.
+///
+/// `$` has to be resolved with [`in_set`] as it refers to specific mails.
+pub fn in_ranges(ranges: &[Range], value: i64, max: i64) -> bool {
+    ranges.iter().any(|range| match range {
+        Range::Single(id) => *id == value,
+        Range::Range(start, RangeEnd::End(end)) => {
+            (*start.min(end)..=*start.max(end)).contains(&value)
+        }
+        Range::Range(start, RangeEnd::All) => value >= *start.min(&max),
+        Range::Last => value == max,
+        Range::Saved => false,
+    })
+}
+
+/// Checks if the mail is part of the sequence set.
+///
+/// `*` is resolved to the highest sequence number or uid and `$` to the saved search result
+/// which is combined with the other parts of the set.
+pub fn in_set(
+    sequence_map: &SequenceMap,
+    ranges: &[Range],
+    sequence: usize,
+    uid: i64,
+    is_uid: bool,
+) -> bool {
+    if ranges.contains(&Range::Saved) && sequence_map.saved_search.contains(&uid) {
+        return true;
+    }
+    if is_uid {
+        in_ranges(ranges, uid, sequence_map.max_uid())
+    } else {
+        in_ranges(
+            ranges,
+            i64::try_from(sequence).unwrap_or(i64::MAX),
+            i64::try_from(sequence_map.len()).unwrap_or(i64::MAX),
+        )
+    }
+}
+
+/// Pairs the mails the client knows about with their sequence numbers.
+///
+/// Mails the client wasn't told about yet are left out.
+pub fn known_mails(
+    mails: Vec<MailEntryType>,
+    sequence_map: &SequenceMap,
+) -> Vec<(usize, MailEntryType)> {
+    let mut mails: HashMap<i64, MailEntryType> =
+        mails.into_iter().map(|mail| (mail.uid(), mail)).collect();
+    sequence_map
+        .iter()
+        .filter_map(|(sequence, uid)| mails.remove(&uid).map(|mail| (sequence, mail)))
+        .collect()
+}
+
+/// Returns the sequence numbers and mails which are part of the sequence set in ascending order
+pub fn mails_in_set(
+    mails: Vec<MailEntryType>,
+    sequence_map: &SequenceMap,
+    ranges: &[Range],
+    is_uid: bool,
+) -> Vec<(usize, MailEntryType)> {
+    known_mails(mails, sequence_map)
+        .into_iter()
+        .filter(|(sequence, mail)| in_set(sequence_map, ranges, *sequence, mail.uid(), is_uid))
+        .collect()
+}
+
+/// Checks if the search key or one of its parts is KEYWORD or UNKEYWORD
+fn uses_keywords(key: &SearchKey) -> bool {
+    match key {
+        SearchKey::Keyword(_) | SearchKey::Unkeyword(_) => true,
+        SearchKey::Not(key) => uses_keywords(key),
+        SearchKey::Or(first, second) => uses_keywords(first) || uses_keywords(second),
+        SearchKey::And(keys) => keys.iter().any(uses_keywords),
+        _ => false,
+    }
+}
+
+struct Searcher<'a> {
+    sequence_map: &'a SequenceMap,
+}
+
+impl Searcher<'_> {
+    fn matches(&self, key: &SearchKey, mail: &mut MailEntryType, sequence: usize) -> bool {
+        match key {
+            SearchKey::All => true,
+            SearchKey::Answered => mail.is_replied(),
+            SearchKey::Bcc(value) => header_contains(mail, "Bcc", value),
+            SearchKey::Before(day) => internal_day(mail).map_or(false, |x| x < *day),
+            SearchKey::Body(value) => body_contains(mail, value),
+            SearchKey::Cc(value) => header_contains(mail, "Cc", value),
+            SearchKey::Deleted => mail.is_trashed(),
+            SearchKey::Draft => mail.is_draft(),
+            SearchKey::Flagged => mail.is_flagged(),
+            SearchKey::From(value) => header_contains(mail, "From", value),
+            SearchKey::Header(name, value) => header_contains(mail, name, value),
+            // Searches for keywords are refused before they get here
+            SearchKey::Keyword(_) | SearchKey::Unkeyword(_) => false,
+            SearchKey::Larger(size) => mail_size(mail) > *size,
+            SearchKey::New => is_recent(mail) && !mail.is_seen(),
+            SearchKey::Not(key) => !self.matches(key, mail, sequence),
+            SearchKey::Old => !is_recent(mail),
+            SearchKey::On(day) => internal_day(mail) == Some(*day),
+            SearchKey::Or(first, second) => {
+                self.matches(first, mail, sequence) || self.matches(second, mail, sequence)
+            }
+            SearchKey::Recent => is_recent(mail),
+            SearchKey::Seen => mail.is_seen(),
+            SearchKey::SentBefore(day) => sent_day(mail).map_or(false, |x| x < *day),
+            SearchKey::SentOn(day) => sent_day(mail) == Some(*day),
+            SearchKey::SentSince(day) => sent_day(mail).map_or(false, |x| x >= *day),
+            SearchKey::Since(day) => internal_day(mail).map_or(false, |x| x >= *day),
+            SearchKey::Smaller(size) => mail_size(mail) < *size,
+            SearchKey::Subject(value) => header_contains(mail, "Subject", value),
+            SearchKey::Text(value) => {
+                any_header_contains(mail, value) || body_contains(mail, value)
+            }
+            SearchKey::To(value) => header_contains(mail, "To", value),
+            SearchKey::Uid(ranges) => in_set(self.sequence_map, ranges, sequence, mail.uid(), true),
+            SearchKey::Unanswered => !mail.is_replied(),
+            SearchKey::Undeleted => !mail.is_trashed(),
+            SearchKey::Undraft => !mail.is_draft(),
+            SearchKey::Unflagged => !mail.is_flagged(),
+            SearchKey::Unseen => !mail.is_seen(),
+            SearchKey::SequenceSet(ranges) => {
+                in_set(self.sequence_map, ranges, sequence, mail.uid(), false)
+            }
+            SearchKey::And(keys) => keys.iter().all(|key| self.matches(key, mail, sequence)),
+        }
+    }
+}
+
+fn header_contains(mail: &mut MailEntryType, name: &str, value: &str) -> bool {
+    let value = value.to_lowercase();
+    mail.headers().map_or(false, |headers| {
+        headers.iter().any(|header| {
+            header.get_key().eq_ignore_ascii_case(name)
+                && header.get_value().to_lowercase().contains(&value)
+        })
+    })
+}
+
+fn any_header_contains(mail: &mut MailEntryType, value: &str) -> bool {
+    let value = value.to_lowercase();
+    mail.headers().map_or(false, |headers| {
+        headers.iter().any(|header| {
+            header.get_key().to_lowercase().contains(&value)
+                || header.get_value().to_lowercase().contains(&value)
+        })
+    })
+}
+
+fn body_contains(mail: &mut MailEntryType, value: &str) -> bool {
+    let value = value.to_lowercase();
+    mail.parsed()
+        .map_or(false, |parsed| part_contains(&parsed, &value))
+}
+
+/// Searches all text parts of the mail for the lowercase value
+fn part_contains(part: &ParsedMail, value: &str) -> bool {
+    if part.subparts.is_empty() {
+        part.ctype.mimetype.starts_with("text/")
+            && part
+                .get_body()
+                .map_or(false, |body| body.to_lowercase().contains(value))
+    } else {
+        part.subparts
+            .iter()
+            .any(|subpart| part_contains(subpart, value))
+    }
+}
+
+fn mail_size(mail: &mut MailEntryType) -> u64 {
+    mail.parsed()
+        .map_or(0, |parsed| parsed.raw_bytes.len() as u64)
+}
+
+fn is_recent(mail: &MailEntryType) -> bool {
+    mail.path()
+        .parent()
+        .map_or(false, |folder| folder.ends_with("new"))
+}
+
+/// The day of the internal date which is the time the mail was delivered to us
+fn internal_day(mail: &MailEntryType) -> Option<i64> {
+    let modified = std::fs::metadata(mail.path()).ok()?.modified().ok()?;
+    let timestamp = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(i64::try_from(timestamp).ok()?.div_euclid(86_400))
+}
+
+/// The day of the `Date` header
+fn sent_day(mail: &mut MailEntryType) -> Option<i64> {
+    mail.date()
+        .ok()
+        .map(|timestamp| timestamp.div_euclid(86_400))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_sequence_set() {
+        assert_eq!(to_sequence_set(&[]), "");
+        assert_eq!(to_sequence_set(&[1, 2, 3, 5, 7, 8]), "1:3,5,7:8");
+    }
+
+    #[test]
+    fn test_in_ranges() {
+        let ranges = vec![Range::Single(2), Range::Range(5, RangeEnd::All)];
+        assert!(in_ranges(&ranges, 2, 10));
+        assert!(!in_ranges(&ranges, 3, 10));
+        assert!(in_ranges(&ranges, 7, 10));
+        // `5:*` includes the highest id even if it is lower than 5
+        assert!(in_ranges(&ranges, 4, 4));
+        assert!(in_ranges(&[Range::Range(4, RangeEnd::End(2))], 3, 10));
+        assert!(in_ranges(&[Range::Last], 10, 10));
+    }
+
+    #[test]
+    fn test_uses_keywords() {
+        assert!(uses_keywords(&SearchKey::And(vec![
+            SearchKey::Seen,
+            SearchKey::Not(Box::new(SearchKey::Keyword(String::from("$Junk")))),
+        ])));
+        assert!(uses_keywords(&SearchKey::Or(
+            Box::new(SearchKey::Seen),
+            Box::new(SearchKey::Unkeyword(String::from("$Junk"))),
+        )));
+        assert!(!uses_keywords(&SearchKey::Not(Box::new(SearchKey::Seen))));
+    }
+
+    #[test]
+    fn test_in_set() {
+        let mut sequence_map = SequenceMap::new(vec![4, 9, 12]);
+        sequence_map.saved_search = vec![9];
+        assert!(in_set(&sequence_map, &[Range::Last], 3, 12, false));
+        assert!(in_set(&sequence_map, &[Range::Last], 3, 12, true));
+        assert!(!in_set(&sequence_map, &[Range::Single(2)], 2, 9, true));
+        assert!(in_set(&sequence_map, &[Range::Saved], 2, 9, false));
+        assert!(!in_set(&sequence_map, &[Range::Saved], 1, 4, true));
+        let ranges = [Range::Single(4), Range::Saved];
+        assert!(in_set(&sequence_map, &ranges, 1, 4, true));
+        assert!(in_set(&sequence_map, &ranges, 2, 9, true));
+        assert!(!in_set(&sequence_map, &ranges, 3, 12, true));
+    }
+
+    #[test]
+    fn test_saved_result() {
+        let uids = [3, 5, 8];
+        assert_eq!(
+            saved_result(&[SearchReturnOption::Save], &uids),
+            vec![3, 5, 8]
+        );
+        assert_eq!(
+            saved_result(&[SearchReturnOption::Save, SearchReturnOption::Max], &uids),
+            vec![8]
+        );
+    }
+}
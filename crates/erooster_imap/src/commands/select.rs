@@ -1,13 +1,22 @@
 use crate::{
-    commands::{CommandData, Data},
-    state::{Access, State},
+    commands::{
+        acl::{mailbox_rights, missing_rights},
+        fetch::flags_response,
+        parsers::{parse_selected_range, Argument, Range},
+        search::{in_ranges, known_mails, to_sequence_set},
+        CommandData, Data,
+    },
+    state::{Access, Capabilities, SequenceMap, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
 };
-use erooster_core::backend::storage::{MailStorage, Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use nom::Finish;
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
 };
 use tracing::instrument;
 
@@ -15,10 +24,12 @@ pub struct Select<'a> {
     pub data: &'a Data,
 }
 
-#[instrument(skip(data, lines, storage, rw, command_data))]
+#[allow(clippy::too_many_lines)]
+#[instrument(skip(data, lines, database, storage, rw, command_data))]
 async fn select<S>(
     data: &Data,
     lines: &mut S,
+    database: DB,
     storage: Arc<Storage>,
     rw: bool,
     command_data: &CommandData<'_>,
@@ -26,63 +37,240 @@ async fn select<S>(
 where
     S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
 {
-    let args = &command_data.arguments;
+    let (folder, parameters) = match command_data.arguments {
+        [folder] => (folder.as_str(), Some(SelectParameters::default())),
+        [folder, Argument::List(parameters)] => (folder.as_str(), select_parameters(parameters)),
+        _ => (None, None),
+    };
+    let folder = if let Some(folder) = folder {
+        folder.to_string()
+    } else {
+        lines
+            .send(format!("{} BAD Invalid mailbox name", command_data.tag))
+            .await?;
+        return Ok(());
+    };
     let mut write_lock = data.con_state.write().await;
-
-    assert!(args.len() == 1);
-    let folder_arg = args.first().expect("server selects a folder");
-    let folder = folder_arg.replace('"', "");
-    let access = if rw {
+    let parameters = match parameters {
+        Some(parameters) if parameters.qresync.is_none() || write_lock.qresync_enabled() => {
+            parameters
+        }
+        _ => {
+            lines
+                .send(format!(
+                    "{} BAD Invalid select parameters",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+    };
+    let rights = mailbox_rights(
+        &database,
+        &storage,
+        write_lock.username.as_deref().unwrap_or_default(),
+        &folder,
+    )
+    .await?;
+    if let Some(response) = missing_rights(command_data.tag, &rights, "r") {
+        lines.send(response).await?;
+        return Ok(());
+    }
+    if parameters.condstore && !write_lock.condstore_enabled() {
+        write_lock.active_capabilities.push(Capabilities::CondStore);
+    }
+    // Without any right to change the mailbox it can only be read
+    let access = if rw && rights.chars().any(|right| "siwte".contains(right)) {
         Access::ReadWrite
     } else {
         Access::ReadOnly
     };
     {
-        write_lock.state = State::Selected(folder.clone(), access);
+        write_lock.state = State::Selected(folder.clone(), access.clone());
     };
 
-    let folder_on_disk = folder_arg;
-    let mailbox_path = storage.to_ondisk_path(
-        (*folder_on_disk).to_string(),
-        write_lock.username.clone().unwrap(),
-    )?;
+    let mailbox_path =
+        storage.to_ondisk_path(folder.clone(), write_lock.username.clone().unwrap())?;
     // Special INBOX check to make sure we have a mailbox
     if folder == "INBOX" && !mailbox_path.exists() {
         storage.create_dirs(&mailbox_path)?;
         storage.add_flag(&mailbox_path, "\\Subscribed").await?;
         storage.add_flag(&mailbox_path, "\\NoInferiors").await?;
     }
-    send_success(lines, folder, storage, mailbox_path, rw, command_data).await?;
+    // Listing the mails assigns uids to new ones so UIDNEXT is past all of them
+    let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
+    write_lock.sequence_map = SequenceMap::new(mails.iter().map(MailEntry::uid).collect());
+    let count = write_lock.sequence_map.len();
+    send_success(
+        lines,
+        folder,
+        Arc::clone(&storage),
+        mailbox_path.clone(),
+        count,
+    )
+    .await?;
+    if let Some(qresync) = parameters.qresync {
+        send_resync(
+            lines,
+            &storage,
+            &mailbox_path,
+            mails,
+            &write_lock.sequence_map,
+            &qresync,
+        )
+        .await?;
+    }
+
+    let resp = if !rw {
+        format!("{} OK [READ-ONLY] EXAMINE completed", command_data.tag)
+    } else if access == Access::ReadWrite {
+        format!("{} OK [READ-WRITE] SELECT completed", command_data.tag)
+    } else {
+        format!("{} OK [READ-ONLY] SELECT completed", command_data.tag)
+    };
+    lines.feed(resp).await?;
+    lines.flush().await?;
+    Ok(())
+}
+
+/// The parameters of SELECT and EXAMINE added by CONDSTORE and QRESYNC
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SelectParameters {
+    condstore: bool,
+    qresync: Option<QResync>,
+}
+
+/// What the client knew about the mailbox when it was last selected
+#[derive(Debug, PartialEq, Eq)]
+struct QResync {
+    uid_validity: i64,
+    modseq: i64,
+    /// Limits the responses to the uids the client still knows about
+    known_uids: Option<Vec<Range>>,
+}
+
+/// Parses parameters like `(CONDSTORE)` or `(QRESYNC (67890007 20050715194045000 41:211))`.
+///
+/// The optional sequence match data of QRESYNC is only an optimization so it gets ignored.
+fn select_parameters(parameters: &[Argument]) -> Option<SelectParameters> {
+    let mut result = SelectParameters::default();
+    let mut parameters = parameters.iter();
+    while let Some(parameter) = parameters.next() {
+        if parameter.is_atom("CONDSTORE") {
+            result.condstore = true;
+        } else if parameter.is_atom("QRESYNC") {
+            let qresync = match parameters.next()? {
+                Argument::List(qresync) => qresync,
+                _ => return None,
+            };
+            let (uid_validity, modseq, known_uids) = match &qresync[..] {
+                [uid_validity, modseq] => (uid_validity, modseq, None),
+                [uid_validity, modseq, known_uids]
+                | [uid_validity, modseq, known_uids, Argument::List(_)] => {
+                    (uid_validity, modseq, Some(known_uids))
+                }
+                _ => return None,
+            };
+            let known_uids =
+                match known_uids.map(|x| parse_selected_range(x.as_str()?).finish().ok()) {
+                    Some(Some(("", ranges))) => Some(ranges),
+                    Some(_) => return None,
+                    None => None,
+                };
+            result.qresync = Some(QResync {
+                uid_validity: uid_validity.as_str()?.parse().ok()?,
+                modseq: modseq.as_str()?.parse().ok()?,
+                known_uids,
+            });
+        } else {
+            return None;
+        }
+    }
+    Some(result)
+}
+
+/// Sends the VANISHED (EARLIER) and FETCH responses for the changes since the client last
+/// synchronized the mailbox.
+///
+/// If the mailbox got recreated in between the client has to start over so nothing is sent.
+#[instrument(skip(lines, storage, mailbox_path, mails, sequence_map, qresync))]
+async fn send_resync<S>(
+    lines: &mut S,
+    storage: &Storage,
+    mailbox_path: &Path,
+    mails: Vec<MailEntryType>,
+    sequence_map: &SequenceMap,
+    qresync: &QResync,
+) -> color_eyre::eyre::Result<()>
+where
+    S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+{
+    if i64::from(storage.get_uid_validity(mailbox_path).await?) != qresync.uid_validity {
+        return Ok(());
+    }
+    let max_uid = sequence_map.max_uid();
+    let is_known = |uid: i64| {
+        qresync
+            .known_uids
+            .as_ref()
+            .map_or(true, |ranges| in_ranges(ranges, uid, max_uid))
+    };
+
+    let vanished: Vec<i64> = storage
+        .get_vanished(mailbox_path, qresync.modseq)
+        .await?
+        .into_iter()
+        .filter(|uid| is_known(*uid))
+        .collect();
+    if !vanished.is_empty() {
+        lines
+            .feed(format!(
+                "* VANISHED (EARLIER) {}",
+                to_sequence_set(&vanished)
+            ))
+            .await?;
+    }
+    for (sequence, mail) in known_mails(mails, sequence_map) {
+        if mail.modseq() <= qresync.modseq || !is_known(mail.uid()) {
+            continue;
+        }
+        let flags = flags_response(&mail);
+        lines
+            .feed(format!(
+                "* {} FETCH (UID {} {} MODSEQ ({}))",
+                sequence,
+                mail.uid(),
+                flags,
+                mail.modseq()
+            ))
+            .await?;
+    }
     Ok(())
 }
 
-#[instrument(skip(lines, folder, storage, mailbox_path, rw, command_data))]
+#[instrument(skip(lines, folder, storage, mailbox_path, count))]
 async fn send_success<S>(
     lines: &mut S,
     folder: String,
     storage: Arc<Storage>,
     mailbox_path: PathBuf,
-    rw: bool,
-    command_data: &CommandData<'_>,
+    count: usize,
 ) -> color_eyre::eyre::Result<()>
 where
     S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
 {
-    let count = storage.count_cur(&mailbox_path) + storage.count_new(&mailbox_path);
     lines.feed(format!("* {} EXISTS", count)).await?;
-    let current_time = SystemTime::now();
-    let unix_timestamp = current_time.duration_since(UNIX_EPOCH)?;
-    #[allow(clippy::cast_possible_truncation)]
-    let timestamp = unix_timestamp.as_millis() as u32;
+    let uid_validity = storage.get_uid_validity(&mailbox_path).await?;
     lines
-        .feed(format!("* OK [UIDVALIDITY {}] UIDs valid", timestamp))
+        .feed(format!("* OK [UIDVALIDITY {}] UIDs valid", uid_validity))
         .await?;
-    let current_uid = storage.get_uid_for_folder(&mailbox_path)?;
+    let uid_next = storage.get_uid_next(&mailbox_path).await?;
     lines
-        .feed(format!(
-            "* OK [UIDNEXT {}] Predicted next UID",
-            current_uid + 1,
-        ))
+        .feed(format!("* OK [UIDNEXT {}] Predicted next UID", uid_next))
+        .await?;
+    let highest_modseq = storage.get_highest_modseq(&mailbox_path).await?;
+    lines
+        .feed(format!("* OK [HIGHESTMODSEQ {}] Highest", highest_modseq))
         .await?;
     lines
         .feed(String::from(
@@ -115,22 +303,15 @@ where
             ))
             .await?;
     }
-
-    let resp = if rw {
-        format!("{} OK [READ-WRITE] SELECT completed", command_data.tag)
-    } else {
-        format!("{} OK [READ-ONLY] EXAMINE completed", command_data.tag)
-    };
-    lines.feed(resp).await?;
-    lines.flush().await?;
     Ok(())
 }
 
 impl Select<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
@@ -138,7 +319,7 @@ impl Select<'_> {
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
         if self.data.con_state.read().await.state == State::Authenticated {
-            select(self.data, lines, storage, true, command_data).await?;
+            select(self.data, lines, database, storage, true, command_data).await?;
         } else {
             lines
                 .send(format!("{} NO invalid state", command_data.tag))
@@ -153,10 +334,11 @@ pub struct Examine<'a> {
 }
 
 impl Examine<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
@@ -164,7 +346,7 @@ impl Examine<'_> {
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
         if self.data.con_state.read().await.state == State::Authenticated {
-            select(self.data, lines, storage, false, command_data).await?;
+            select(self.data, lines, database, storage, false, command_data).await?;
         } else {
             lines
                 .send(format!("{} NO invalid state", command_data.tag))
@@ -173,3 +355,50 @@ impl Examine<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::parsers::RangeEnd;
+
+    #[test]
+    fn test_select_parameters() {
+        assert_eq!(
+            select_parameters(&[Argument::Atom(String::from("CONDSTORE"))]),
+            Some(SelectParameters {
+                condstore: true,
+                qresync: None,
+            })
+        );
+        assert_eq!(
+            select_parameters(&[
+                Argument::Atom(String::from("QRESYNC")),
+                Argument::List(vec![
+                    Argument::Atom(String::from("67890007")),
+                    Argument::Atom(String::from("20050715194045000")),
+                    Argument::Atom(String::from("41:211")),
+                    Argument::List(vec![
+                        Argument::Atom(String::from("1:3")),
+                        Argument::Atom(String::from("41:43")),
+                    ]),
+                ]),
+            ]),
+            Some(SelectParameters {
+                condstore: false,
+                qresync: Some(QResync {
+                    uid_validity: 67_890_007,
+                    modseq: 20_050_715_194_045_000,
+                    known_uids: Some(vec![Range::Range(41, RangeEnd::End(211))]),
+                }),
+            })
+        );
+        assert_eq!(
+            select_parameters(&[Argument::Atom(String::from("QRESYNC"))]),
+            None
+        );
+        assert_eq!(
+            select_parameters(&[Argument::Atom(String::from("UNKNOWN"))]),
+            None
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    commands::{CommandData, Data},
+    state::State,
+};
+use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use tracing::instrument;
+
+pub struct StartTls<'a> {
+    pub data: &'a Data,
+}
+
+impl StartTls<'_> {
+    #[instrument(skip(self, lines, command_data))]
+    pub async fn exec<S>(
+        &self,
+        lines: &mut S,
+        command_data: &CommandData<'_>,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let response = {
+            let mut write_lock = self.data.con_state.write().await;
+            if write_lock.secure {
+                format!("{} BAD TLS is already active", command_data.tag)
+            } else if !write_lock.starttls {
+                format!("{} NO TLS is not available", command_data.tag)
+            } else if write_lock.state == State::NotAuthenticated {
+                // The server loop swaps the transport once it sees this state
+                write_lock.state = State::StartTls;
+                format!("{} OK Begin TLS negotiation now", command_data.tag)
+            } else {
+                format!("{} NO invalid state", command_data.tag)
+            }
+        };
+        lines.send(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{CommandData, Commands};
+    use crate::state::{Compression, Connection, SequenceMap};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_starttls() {
+        let data = Data {
+            con_state: Connection::new(false),
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::StartTls,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = StartTls { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 OK Begin TLS negotiation now"))
+        );
+        assert_eq!(data.con_state.read().await.state, State::StartTls);
+    }
+
+    #[tokio::test]
+    async fn test_starttls_unavailable() {
+        let data = Data {
+            con_state: Connection::new(false),
+        };
+        data.con_state.write().await.starttls = false;
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::StartTls,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = StartTls { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 NO TLS is not available"))
+        );
+        assert_eq!(data.con_state.read().await.state, State::NotAuthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_starttls_already_secure() {
+        let data = Data {
+            con_state: Arc::new(RwLock::new(Connection {
+                state: State::NotAuthenticated,
+                secure: true,
+                starttls: false,
+                oauth: false,
+                username: None,
+                active_capabilities: vec![],
+                sequence_map: SequenceMap::default(),
+                pending_command: None,
+                channel_binding: None,
+                compression: Compression::Off,
+            })),
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::StartTls,
+            arguments: &[],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let res = StartTls { data: &data }.exec(&mut tx, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            rx.next().await,
+            Some(String::from("1 BAD TLS is already active"))
+        );
+        assert_eq!(data.con_state.read().await.state, State::NotAuthenticated);
+    }
+}
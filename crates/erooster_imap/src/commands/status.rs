@@ -1,24 +1,209 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::Storage;
+use crate::{
+    commands::{
+        acl::{mailbox_rights, missing_rights},
+        mime::string,
+        parsers::{status_arguments, StatusDataItem},
+        CommandData, Data,
+    },
+    state::State,
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
-use std::sync::Arc;
-use tracing::instrument;
+use nom::{error::convert_error, Finish};
+use std::{path::Path, sync::Arc};
+use tracing::{error, instrument};
 
 pub struct Status<'a> {
     pub data: &'a Data,
 }
 
 impl Status<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
+        let (state, username) = {
+            let read_lock = self.data.con_state.read().await;
+            (read_lock.state.clone(), read_lock.username.clone())
+        };
+        if !matches!(state, State::Authenticated | State::Selected(_, _)) {
+            lines
+                .send(format!("{} NO invalid state", command_data.tag))
+                .await?;
+            return Ok(());
+        }
+
+        let status_args = command_data.joined_arguments(0);
+        let (folder, items) = match status_arguments(&status_args).finish() {
+            Ok(("", args)) => args,
+            Ok((left, _)) => {
+                error!("Failed to parse status arguments. Leftover: {}", left);
+                lines
+                    .send(format!("{} BAD Unable to parse", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!(
+                    "Failed to parse status arguments: {}",
+                    convert_error(status_args.as_str(), e)
+                );
+                lines
+                    .send(format!("{} BAD Unable to parse", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let username = username.unwrap();
+        let mailbox_path = storage.to_ondisk_path(folder.clone(), username.clone())?;
+        if !mailbox_path.exists() {
+            lines
+                .send(format!(
+                    "{} NO [NONEXISTENT] Mailbox does not exist",
+                    command_data.tag
+                ))
+                .await?;
+            return Ok(());
+        }
+        let rights = mailbox_rights(&database, &storage, &username, &folder).await?;
+        if let Some(response) = missing_rights(command_data.tag, &rights, "r") {
+            lines.send(response).await?;
+            return Ok(());
+        }
+
+        lines
+            .feed(status_response(&storage, &mailbox_path, &folder, &items).await?)
+            .await?;
+        lines
+            .feed(format!("{} OK STATUS completed", command_data.tag))
+            .await?;
+        lines.flush().await?;
         Ok(())
     }
 }
+
+/// Generates the untagged STATUS response for the mailbox
+#[instrument(skip(storage, mailbox_path, folder, items))]
+pub async fn status_response(
+    storage: &Storage,
+    mailbox_path: &Path,
+    folder: &str,
+    items: &[StatusDataItem],
+) -> color_eyre::eyre::Result<String> {
+    let mails: Vec<MailEntryType> = storage.list_all(mailbox_path).await;
+    let mut response_items = Vec::new();
+    for item in items {
+        let value = match item {
+            StatusDataItem::Messages => mails.len().to_string(),
+            StatusDataItem::UidNext => storage.get_uid_next(mailbox_path).await?.to_string(),
+            StatusDataItem::UidValidity => {
+                storage.get_uid_validity(mailbox_path).await?.to_string()
+            }
+            StatusDataItem::Unseen => mails
+                .iter()
+                .filter(|mail| !mail.is_seen())
+                .count()
+                .to_string(),
+            StatusDataItem::Deleted => mails
+                .iter()
+                .filter(|mail| mail.is_trashed())
+                .count()
+                .to_string(),
+            StatusDataItem::Size => {
+                let mut size = 0;
+                for mail in &mails {
+                    size += tokio::fs::metadata(mail.path()).await?.len();
+                }
+                size.to_string()
+            }
+            StatusDataItem::Recent => storage.count_new(mailbox_path).to_string(),
+            StatusDataItem::HighestModSeq => {
+                storage.get_highest_modseq(mailbox_path).await?.to_string()
+            }
+        };
+        response_items.push(format!("{} {}", status_item_name(*item), value));
+    }
+    Ok(format!(
+        "* STATUS {} ({})",
+        string(folder),
+        response_items.join(" ")
+    ))
+}
+
+const fn status_item_name(item: StatusDataItem) -> &'static str {
+    match item {
+        StatusDataItem::Messages => "MESSAGES",
+        StatusDataItem::UidNext => "UIDNEXT",
+        StatusDataItem::UidValidity => "UIDVALIDITY",
+        StatusDataItem::Unseen => "UNSEEN",
+        StatusDataItem::Deleted => "DELETED",
+        StatusDataItem::Size => "SIZE",
+        StatusDataItem::Recent => "RECENT",
+        StatusDataItem::HighestModSeq => "HIGHESTMODSEQ",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{parsers::Argument, CommandData, Commands};
+    use crate::state::{Compression, Connection, SequenceMap};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_no_auth() {
+        let status = Status {
+            data: &Data {
+                con_state: Arc::new(RwLock::new(Connection {
+                    state: State::NotAuthenticated,
+                    secure: true,
+                    starttls: false,
+                    oauth: false,
+                    username: None,
+                    active_capabilities: vec![],
+                    sequence_map: SequenceMap::default(),
+                    pending_command: None,
+                    channel_binding: None,
+                    compression: Compression::Off,
+                })),
+            },
+        };
+        let cmd_data = CommandData {
+            tag: "1",
+            command: Commands::Status,
+            arguments: &[
+                Argument::Atom(String::from("INBOX")),
+                Argument::List(vec![Argument::Atom(String::from("MESSAGES"))]),
+            ],
+        };
+        let (mut tx, mut rx) = mpsc::unbounded();
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = Arc::new(erooster_core::backend::storage::get_storage(
+            Arc::clone(&database),
+            Arc::clone(&config),
+        ));
+        let res = status.exec(&mut tx, database, storage, &cmd_data).await;
+        assert!(res.is_ok());
+        assert_eq!(rx.next().await, Some(String::from("1 NO invalid state")));
+    }
+}
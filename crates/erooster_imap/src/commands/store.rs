@@ -1,9 +1,19 @@
 use crate::{
-    commands::{CommandData, Data},
-    state::State,
+    commands::{
+        acl::{flag_right, mailbox_rights, missing_rights},
+        fetch::flags_response,
+        parsers::{parse_selected_range, Argument},
+        search::{mails_in_set, to_sequence_set},
+        CommandData, Data,
+    },
+    state::{Access, Capabilities, State},
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{MailEntry, MailEntryType, MailStorage, Storage},
 };
-use erooster_core::backend::storage::{MailEntry, MailEntryType, MailStorage, Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use nom::Finish;
 use std::sync::Arc;
 use tracing::{debug, error, instrument};
 
@@ -11,10 +21,12 @@ pub struct Store<'a> {
     pub data: &'a Data,
 }
 impl Store<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[allow(clippy::too_many_lines)]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
         uid: bool,
@@ -24,207 +36,157 @@ impl Store<'_> {
     {
         let offset = if uid { 1 } else { 0 };
         let arguments = &command_data.arguments;
-        assert!(arguments.len() >= 2 + offset);
         if arguments.len() >= 2 + offset {
-            if let State::Selected(folder, _) = &self.data.con_state.read().await.state {
-                let folder = folder.replace('/', ".");
-                let mailbox_path = storage.to_ondisk_path(
-                    folder.clone(),
-                    self.data.con_state.read().await.username.clone().unwrap(),
-                )?;
+            let (state, username, sequence_map) = {
+                let read_lock = self.data.con_state.read().await;
+                (
+                    read_lock.state.clone(),
+                    read_lock.username.clone(),
+                    read_lock.sequence_map.clone(),
+                )
+            };
+            if let State::Selected(folder, access) = state {
+                if access == Access::ReadOnly {
+                    lines
+                        .send(format!("{} NO in read-only mode", command_data.tag))
+                        .await?;
+                    return Ok(());
+                }
+                let username = username.unwrap();
+                let rights = mailbox_rights(&database, &storage, &username, &folder).await?;
+                let mailbox_path = storage.to_ondisk_path(folder, username)?;
                 let mails: Vec<MailEntryType> = storage.list_all(&mailbox_path).await;
 
-                let filtered_mails: Vec<MailEntryType> =
-                    if command_data.arguments[offset].contains(':') {
-                        let range = command_data.arguments[offset]
-                            .split(':')
-                            .collect::<Vec<_>>();
-                        let start = range[0].parse::<i64>().unwrap_or(1);
-                        let end = range[1];
-                        let end_int = end.parse::<i64>().unwrap_or(i64::max_value());
-                        if end == "*" {
-                            mails
-                                .into_iter()
-                                .filter(|mail| mail.uid() >= start)
-                                .collect()
-                        } else {
-                            mails
-                                .into_iter()
-                                .filter(|mail| mail.uid() >= start && mail.uid() <= end_int)
-                                .collect()
-                        }
-                    } else {
-                        let wanted_id = command_data.arguments[offset].parse::<i64>().unwrap_or(1);
-                        mails
-                            .into_iter()
-                            .filter(|mail| mail.uid() == wanted_id)
-                            .collect()
-                    };
-                let action = arguments[1 + offset];
-
-                let flags = command_data.arguments[2 + offset..].to_vec();
-                let flags_string = flags.join(" ");
-                if action.to_lowercase() == "flags" {
-                    for mail in filtered_mails {
-                        let mut path = mail.path().clone();
-                        path.pop();
-                        let path = path
-                            .into_os_string()
-                            .into_string()
-                            .expect("Failed to convert path. Your system may be incompatible");
-                        if path.ends_with("new") {
-                            if let Err(e) =
-                                storage.move_new_to_cur_with_flags(&mailbox_path, mail.id(), &flags)
-                            {
-                                error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                            }
-                        } else if let Err(e) = storage.set_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                        }
+                let ranges = if let Ok(("", ranges)) =
+                    parse_selected_range(arguments[offset].as_str().unwrap_or_default()).finish()
+                {
+                    ranges
+                } else {
+                    lines
+                        .send(format!("{} BAD Invalid sequence set", command_data.tag))
+                        .await?;
+                    return Ok(());
+                };
+                let filtered_mails = mails_in_set(mails, &sequence_map, &ranges, uid);
 
-                        if uid {
-                            lines
-                                .feed(format!(
-                                    "* {} FETCH (UID {} FLAGS {})",
-                                    mail.uid(),
-                                    mail.uid(),
-                                    flags_string
-                                ))
-                                .await?;
+                // CONDSTORE only changes mails which weren't modified since the given modseq
+                let (unchanged_since, action_index) = match &arguments[1 + offset] {
+                    Argument::List(modifiers) => {
+                        if let Some(modseq) = parse_unchanged_since(modifiers) {
+                            (Some(modseq), 2 + offset)
                         } else {
                             lines
-                                .feed(format!("* {} FETCH (FLAGS {})", mail.uid(), flags_string))
+                                .send(format!("{} BAD Invalid store modifiers", command_data.tag))
                                 .await?;
+                            return Ok(());
                         }
                     }
-                } else if action.to_lowercase() == "flags.silent" {
-                    for mail in filtered_mails {
-                        let mut path = mail.path().clone();
-                        path.pop();
-                        let path = path
-                            .into_os_string()
-                            .into_string()
-                            .expect("Failed to convert path. Your system may be incompatible");
-                        if path.ends_with("new") {
-                            if let Err(e) =
-                                storage.move_new_to_cur_with_flags(&mailbox_path, mail.id(), &flags)
-                            {
-                                error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                            }
-                        } else if let Err(e) = storage.set_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                        }
+                    _ => (None, 1 + offset),
+                };
+                let condstore = {
+                    let mut write_lock = self.data.con_state.write().await;
+                    // UNCHANGEDSINCE enables CONDSTORE for the rest of the session
+                    if unchanged_since.is_some() && !write_lock.condstore_enabled() {
+                        write_lock.active_capabilities.push(Capabilities::CondStore);
                     }
-                } else if action.to_lowercase() == "+flags" {
-                    for mail in filtered_mails {
-                        let mut path = mail.path().clone();
-                        path.pop();
-                        let path = path
-                            .into_os_string()
-                            .into_string()
-                            .expect("Failed to convert path. Your system may be incompatible");
-                        debug!("Path: {}", path);
-                        if path.ends_with("new") {
-                            if let Err(e) =
-                                storage.move_new_to_cur_with_flags(&mailbox_path, mail.id(), &flags)
-                            {
-                                error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                            }
-                        } else if let Err(e) = storage.add_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
+                    write_lock.condstore_enabled()
+                };
+
+                let action = arguments
+                    .get(action_index)
+                    .and_then(Argument::as_str)
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let (action, silent) = match action.strip_suffix(".silent") {
+                    Some(action) => (action, true),
+                    None => (action.as_str(), false),
+                };
+                if !matches!(action, "flags" | "+flags" | "-flags") {
+                    lines
+                        .send(format!(
+                            "{} BAD [SERVERBUG] invalid arguments",
+                            command_data.tag
+                        ))
+                        .await?;
+                    return Ok(());
+                }
+
+                // The flags may be given as a list or separated by spaces
+                let flags: Vec<&str> = arguments
+                    .get(1 + action_index..)
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|argument| match argument {
+                        Argument::List(flags) => {
+                            flags.iter().filter_map(Argument::as_str).collect()
                         }
+                        argument => argument.as_str().into_iter().collect::<Vec<_>>(),
+                    })
+                    .collect();
 
-                        if uid {
-                            lines
-                                .feed(format!(
-                                    "* {} FETCH (UID {} FLAGS {})",
-                                    mail.uid(),
-                                    mail.uid(),
-                                    flags_string
-                                ))
-                                .await?;
+                if let Some(response) =
+                    missing_rights(command_data.tag, &rights, &required_rights(action, &flags))
+                {
+                    lines.send(response).await?;
+                    return Ok(());
+                }
+
+                let mut modified = Vec::new();
+                for (sequence, mut mail) in filtered_mails {
+                    if unchanged_since.map_or(false, |modseq| mail.modseq() > modseq) {
+                        modified.push(if uid {
+                            mail.uid()
                         } else {
-                            lines
-                                .feed(format!("* {} FETCH (FLAGS {})", mail.uid(), flags_string))
-                                .await?;
-                        }
+                            i64::try_from(sequence).unwrap_or(i64::MAX)
+                        });
+                        continue;
                     }
-                } else if action.to_lowercase() == "+flags.silent" {
-                    for mail in filtered_mails {
-                        let mut path = mail.path().clone();
-                        path.pop();
-                        let path = path
-                            .into_os_string()
-                            .into_string()
-                            .expect("Failed to convert path. Your system may be incompatible");
-                        if path.ends_with("new") {
-                            if let Err(e) =
-                                storage.move_new_to_cur_with_flags(&mailbox_path, mail.id(), &flags)
-                            {
-                                error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                            }
-                        } else if let Err(e) = storage.add_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
+                    let in_new = mail.path().parent().map_or(false, |x| x.ends_with("new"));
+                    debug!("Storing {} for {}", action, mail.id());
+                    let result = match action {
+                        // Mails in new don't have any flags yet
+                        "-flags" if in_new => Ok(()),
+                        "-flags" => storage.remove_flags(&mailbox_path, mail.id(), &flags),
+                        _ if in_new => {
+                            storage.move_new_to_cur_with_flags(&mailbox_path, mail.id(), &flags)
                         }
+                        "flags" => storage.set_flags(&mailbox_path, mail.id(), &flags),
+                        _ => storage.add_flags(&mailbox_path, mail.id(), &flags),
+                    };
+                    if let Err(e) = result {
+                        error!("Failed to store flags or move email {}: {}", mail.id(), e);
                     }
-                } else if action.to_lowercase() == "-flags" {
-                    for mail in filtered_mails {
-                        let mut current_flags = vec![];
-                        if mail.is_replied() {
-                            current_flags.push("\\Answered");
-                        } else if mail.is_trashed() {
-                            current_flags.push("\\Deleted");
-                        } else if mail.is_draft() {
-                            current_flags.push("\\Draft");
-                        } else if mail.is_seen() {
-                            current_flags.push("\\Seen");
-                        } else if mail.is_flagged() {
-                            current_flags.push("\\Flagged");
-                        }
 
-                        let new_flags = current_flags
-                            .iter()
-                            .copied()
-                            .filter_map(|x| {
-                                let x = x.replace('(', "").replace(')', "");
-                                (!flags.contains(&x.as_str())).then(|| x)
-                            })
-                            .collect::<Vec<_>>();
-
-                        if let Err(e) = storage.remove_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                        }
-
-                        if uid {
-                            lines
-                                .feed(format!(
-                                    "* {} FETCH (UID {} FLAGS ({}))",
-                                    mail.uid(),
-                                    mail.uid(),
-                                    new_flags.join(" ")
-                                ))
-                                .await?;
-                        } else {
-                            lines
-                                .feed(format!(
-                                    "* {} FETCH (FLAGS ({}))",
-                                    mail.uid(),
-                                    new_flags.join(" ")
-                                ))
-                                .await?;
-                        }
+                    // CONDSTORE clients learn about the new modseq even for .SILENT
+                    if silent && !condstore {
+                        continue;
                     }
-                } else if action.to_lowercase() == "-flags.silent" {
-                    for mail in filtered_mails {
-                        if let Err(e) = storage.remove_flags(&mailbox_path, mail.id(), &flags) {
-                            error!("Failed to store flags or move email {}: {}", mail.id(), e);
-                        }
+                    if let Some(updated) = storage.find(&mailbox_path, mail.id()).await {
+                        mail = updated;
+                    }
+                    let mut items = Vec::new();
+                    if uid || condstore {
+                        items.push(format!("UID {}", mail.uid()));
+                    }
+                    if !silent {
+                        items.push(flags_response(&mail));
+                    }
+                    if condstore {
+                        items.push(format!("MODSEQ ({})", mail.modseq()));
                     }
-                } else {
+                    lines
+                        .feed(format!("* {} FETCH ({})", sequence, items.join(" ")))
+                        .await?;
+                }
+                if !modified.is_empty() {
+                    modified.sort_unstable();
+                    let name = if uid { "UID STORE" } else { "STORE" };
                     lines
                         .send(format!(
-                            "{} BAD [SERVERBUG] invalid arguments",
-                            command_data.tag
+                            "{} OK [MODIFIED {}] Conditional {} failed",
+                            command_data.tag,
+                            to_sequence_set(&modified),
+                            name
                         ))
                         .await?;
                     return Ok(());
@@ -259,3 +221,94 @@ impl Store<'_> {
         Ok(())
     }
 }
+
+/// Returns the rights needed to change the given flags.
+///
+/// Replacing all flags may also clear `\Seen` and `\Deleted` so it needs every flag right.
+fn required_rights(action: &str, flags: &[&str]) -> String {
+    if action == "flags" {
+        return String::from("stw");
+    }
+    let mut rights = String::new();
+    for flag in flags {
+        let right = flag_right(flag);
+        if !rights.contains(right) {
+            rights.push(right);
+        }
+    }
+    rights
+}
+
+/// Parses the `(UNCHANGEDSINCE <modseq>)` modifier of CONDSTORE
+fn parse_unchanged_since(modifiers: &[Argument]) -> Option<i64> {
+    match modifiers {
+        [modifier, modseq] if modifier.is_atom("UNCHANGEDSINCE") => modseq.as_str()?.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unchanged_since() {
+        assert_eq!(
+            parse_unchanged_since(&[
+                Argument::Atom(String::from("UNCHANGEDSINCE")),
+                Argument::Atom(String::from("12121230045")),
+            ]),
+            Some(12_121_230_045)
+        );
+        assert_eq!(
+            parse_unchanged_since(&[Argument::Atom(String::from("UNCHANGEDSINCE"))]),
+            None
+        );
+        assert_eq!(
+            parse_unchanged_since(&[
+                Argument::Atom(String::from("CHANGEDSINCE")),
+                Argument::Atom(String::from("1")),
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_required_rights() {
+        assert_eq!(required_rights("+flags", &["\\Seen"]), "s");
+        assert_eq!(
+            required_rights("-flags", &["\\Deleted", "\\Flagged", "$Label"]),
+            "tw"
+        );
+        assert_eq!(required_rights("flags", &["\\Seen"]), "stw");
+    }
+
+    #[tokio::test]
+    async fn test_remove_deleted_flag() {
+        let config = erooster_core::get_config(String::from("./config.yml"))
+            .await
+            .unwrap();
+        let database = Arc::new(
+            erooster_core::backend::database::get_database(Arc::clone(&config))
+                .await
+                .unwrap(),
+        );
+        let storage = erooster_core::backend::storage::get_storage(database, config);
+        let path = std::env::temp_dir().join(format!("erooster_store_{}", std::process::id()));
+        storage.create_dirs(&path).unwrap();
+        let id = storage
+            .store_cur_with_flags(
+                &path,
+                b"Subject: Test\r\n\r\nTest\r\n",
+                vec![String::from("\\Seen"), String::from("\\Deleted")],
+            )
+            .await
+            .unwrap();
+
+        storage.remove_flags(&path, &id, &["\\Deleted"]).unwrap();
+        let mail = storage.find(&path, &id).await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(!mail.is_trashed());
+        assert!(mail.is_seen());
+    }
+}
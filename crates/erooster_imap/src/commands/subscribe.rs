@@ -1,5 +1,13 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::{MailStorage, Storage};
+use crate::commands::{
+    acl::{mailbox_rights, missing_rights},
+    create::{create_mailbox, special_use_for_name},
+    list::is_reserved,
+    CommandData, Data,
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{shared_mailbox, MailStorage, Storage},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
 use tracing::{debug, instrument};
@@ -9,42 +17,56 @@ pub struct Subscribe<'a> {
 }
 
 impl Subscribe<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let arguments = &command_data.arguments;
-        assert!(arguments.len() == 1);
-        if arguments.len() == 1 {
-            let folder = arguments[0].replace('/', ".");
-            let mailbox_path = storage.to_ondisk_path(
-                folder.clone(),
-                self.data.con_state.read().await.username.clone().unwrap(),
-            )?;
+        let folder = match command_data.arguments {
+            [folder] => folder.as_str(),
+            _ => None,
+        };
+        if let Some(folder) = folder {
+            let username = self.data.con_state.read().await.username.clone().unwrap();
+            // Shared mailboxes are always listed as subscribed since the flag belongs to the owner
+            if shared_mailbox(folder).is_some() {
+                let rights = mailbox_rights(&database, &storage, &username, folder).await?;
+                if let Some(response) = missing_rights(command_data.tag, &rights, "l") {
+                    lines.send(response).await?;
+                    return Ok(());
+                }
+                lines
+                    .send(format!("{} OK SUBSCRIBE completed", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            // Subscribing creates the mailbox which must not end up in the shared namespace
+            if is_reserved(folder) {
+                lines
+                    .send(format!(
+                        "{} NO [CANNOT] Mailboxes can't be created in the shared namespace",
+                        command_data.tag
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            let folder = folder.replace('/', ".");
+            let mailbox_path = storage.to_ondisk_path(folder.clone(), username)?;
 
             // This is a spec violation. However we need to do this currently due to how the storage is set up
             debug!("mailbox_path: {:?}", &mailbox_path);
-            if !mailbox_path.exists() {
-                storage.create_dirs(&mailbox_path)?;
-                if folder.to_lowercase() == ".sent" {
-                    storage.add_flag(&mailbox_path, "\\Sent").await?;
-                } else if folder.to_lowercase() == ".junk" {
-                    storage.add_flag(&mailbox_path, "\\Junk").await?;
-                } else if folder.to_lowercase() == ".drafts" {
-                    storage.add_flag(&mailbox_path, "\\Drafts").await?;
-                } else if folder.to_lowercase() == ".archive" {
-                    storage.add_flag(&mailbox_path, "\\Archive").await?;
-                } else if folder.to_lowercase() == ".trash" {
-                    storage.add_flag(&mailbox_path, "\\Trash").await?;
-                }
+            if mailbox_path.exists() {
+                storage.add_flag(&mailbox_path, "\\Subscribed").await?;
+            } else {
+                let folder = storage.to_ondisk_path_name(folder)?;
+                create_mailbox(&storage, &mailbox_path, special_use_for_name(&folder)).await?;
             }
-            storage.add_flag(&mailbox_path, "\\Subscribed").await?;
             lines
                 .send(format!("{} OK SUBSCRIBE completed", command_data.tag))
                 .await?;
@@ -1,5 +1,8 @@
-use crate::commands::{fetch::Fetch, store::Store, CommandData, Data};
-use erooster_core::backend::storage::Storage;
+use crate::commands::{
+    copy::Copy, expunge::Expunge, fetch::Fetch, move_command::Move, search::Search, store::Store,
+    CommandData, Data,
+};
+use erooster_core::backend::{database::DB, storage::Storage};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
 use tracing::instrument;
@@ -9,40 +12,47 @@ pub struct Uid<'a> {
 }
 
 impl Uid<'_> {
-    #[instrument(skip(self, lines, command_data, storage))]
+    #[instrument(skip(self, lines, command_data, database, storage))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         command_data: &CommandData<'_>,
+        database: DB,
         storage: Arc<Storage>,
     ) -> color_eyre::eyre::Result<()>
     where
-        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+        S: Sink<String, Error = SendError>
+            + Sink<Vec<u8>, Error = SendError>
+            + std::marker::Unpin
+            + std::marker::Send,
     {
-        if command_data.arguments[0].to_lowercase() == "fetch" {
+        if command_data.arguments[0].is_atom("fetch") {
             Fetch { data: self.data }
-                .exec(lines, command_data, storage, true)
+                .exec(lines, command_data, database, storage, true)
                 .await?;
-        } else if command_data.arguments[0].to_lowercase() == "copy" {
-            // TODO implement other commands
-            lines
-                .send(format!("{} BAD Not supported", command_data.tag))
+        } else if command_data.arguments[0].is_atom("copy") {
+            Copy { data: self.data }
+                .exec(lines, database, storage, command_data, true)
                 .await?;
-        } else if command_data.arguments[0].to_lowercase() == "move" {
-            lines
-                .send(format!("{} BAD Not supported", command_data.tag))
+        } else if command_data.arguments[0].is_atom("move") {
+            Move { data: self.data }
+                .exec(lines, database, storage, command_data, true)
                 .await?;
-        } else if command_data.arguments[0].to_lowercase() == "expunge" {
-            lines
-                .send(format!("{} BAD Not supported", command_data.tag))
+        } else if command_data.arguments[0].is_atom("expunge") {
+            Expunge { data: self.data }
+                .exec(lines, database, storage, command_data, true)
                 .await?;
-        } else if command_data.arguments[0].to_lowercase() == "search" {
-            lines
-                .send(format!("{} BAD Not supported", command_data.tag))
+        } else if command_data.arguments[0].is_atom("search") {
+            Search { data: self.data }
+                .exec(lines, storage, command_data, true)
                 .await?;
-        } else if command_data.arguments[0].to_lowercase() == "store" {
+        } else if command_data.arguments[0].is_atom("store") {
             Store { data: self.data }
-                .exec(lines, storage, command_data, true)
+                .exec(lines, database, storage, command_data, true)
+                .await?;
+        } else {
+            lines
+                .send(format!("{} BAD Unknown UID command", command_data.tag))
                 .await?;
         }
         Ok(())
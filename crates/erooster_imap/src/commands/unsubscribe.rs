@@ -1,5 +1,11 @@
-use crate::commands::{CommandData, Data};
-use erooster_core::backend::storage::{MailStorage, Storage};
+use crate::commands::{
+    acl::{mailbox_rights, missing_rights},
+    CommandData, Data,
+};
+use erooster_core::backend::{
+    database::DB,
+    storage::{shared_mailbox, MailStorage, Storage},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use std::sync::Arc;
 use tracing::instrument;
@@ -9,24 +15,37 @@ pub struct Unsubscribe<'a> {
 }
 
 impl Unsubscribe<'_> {
-    #[instrument(skip(self, lines, storage, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        database: DB,
         storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        let arguments = &command_data.arguments;
-        assert!(arguments.len() == 1);
-        if arguments.len() == 1 {
-            let folder = arguments[0].replace('/', ".");
-            let mailbox_path = storage.to_ondisk_path(
-                folder.clone(),
-                self.data.con_state.read().await.username.clone().unwrap(),
-            )?;
+        let folder = match command_data.arguments {
+            [folder] => folder.as_str(),
+            _ => None,
+        };
+        if let Some(folder) = folder {
+            let username = self.data.con_state.read().await.username.clone().unwrap();
+            // Shared mailboxes are always listed as subscribed since the flag belongs to the owner
+            if shared_mailbox(folder).is_some() {
+                let rights = mailbox_rights(&database, &storage, &username, folder).await?;
+                if let Some(response) = missing_rights(command_data.tag, &rights, "l") {
+                    lines.send(response).await?;
+                    return Ok(());
+                }
+                lines
+                    .send(format!("{} OK UNSUBSCRIBE completed", command_data.tag))
+                    .await?;
+                return Ok(());
+            }
+            let folder = folder.replace('/', ".");
+            let mailbox_path = storage.to_ondisk_path(folder.clone(), username)?;
             // Note we deviate from spec here and actually do this automatically. So we can just return OK here.
             if !mailbox_path.exists() {
                 lines
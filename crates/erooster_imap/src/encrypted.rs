@@ -1,20 +1,17 @@
 use crate::{
-    commands::Data,
-    state::{Connection, State},
-    Server, CAPABILITY_HELLO,
+    capability_hello,
+    codec::ImapCodec,
+    session::{serve, Compressed, Upgrade},
+    state::Connection,
+    Server,
 };
 use async_trait::async_trait;
 use erooster_core::{
     backend::{database::DB, storage::Storage},
     config::Config,
-    line_codec::LinesCodec,
     LINE_LIMIT,
 };
-use futures::SinkExt;
-use futures::{
-    channel::mpsc::{self, UnboundedSender},
-    StreamExt,
-};
+use futures::{SinkExt, StreamExt};
 use notify::Event;
 use std::{
     fs::{self},
@@ -24,11 +21,12 @@ use std::{
     sync::Arc,
 };
 use tokio::{
-    net::TcpListener,
+    net::{TcpListener, TcpStream},
     sync::{broadcast, RwLock},
 };
 use tokio_rustls::{
     rustls::{self, Certificate, PrivateKey},
+    server::TlsStream,
     TlsAcceptor,
 };
 use tokio_stream::wrappers::TcpListenerStream;
@@ -71,6 +69,26 @@ impl Encrypted {
             path
         ))
     }
+
+    /// Sets up the TLS acceptor from the configured certificate
+    ///
+    /// This is shared with the unencrypted server which uses it for `STARTTLS`
+    #[instrument(skip(config))]
+    pub fn get_tls_acceptor(config: &Config) -> color_eyre::eyre::Result<TlsAcceptor> {
+        // Load SSL Keys
+        let certs = Encrypted::load_certs(Path::new(&config.tls.cert_path))?;
+        let key = Encrypted::load_key(Path::new(&config.tls.key_path))?;
+
+        // Sets up the TLS acceptor.
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        // Starts a TLS accepting thing.
+        Ok(TlsAcceptor::from(Arc::new(server_config)))
+    }
 }
 
 #[async_trait]
@@ -88,19 +106,7 @@ impl Server for Encrypted {
         storage: Arc<Storage>,
         file_watcher: broadcast::Sender<Event>,
     ) -> color_eyre::eyre::Result<()> {
-        // Load SSL Keys
-        let certs = Encrypted::load_certs(Path::new(&config.tls.cert_path))?;
-        let key = Encrypted::load_key(Path::new(&config.tls.key_path))?;
-
-        // Sets up the TLS acceptor.
-        let server_config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
-
-        // Starts a TLS accepting thing.
-        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let acceptor = Encrypted::get_tls_acceptor(&config)?;
 
         // Opens the listener
         let addrs: Vec<SocketAddr> = if let Some(listen_ips) = &config.listen_ips {
@@ -173,81 +179,25 @@ async fn listen(
                     debug!("[IMAP] TLS negotiation done");
 
                     // Proceed as normal
-                    let lines = Framed::new(stream, LinesCodec::new_with_max_length(LINE_LIMIT));
-                    // We split these as we handle the sink in a broadcast instead to be able to push non linear data over the socket
-                    let (mut lines_sender, mut lines_reader) = lines.split();
+                    let mut lines = Framed::new(stream, ImapCodec::new_with_max_length(LINE_LIMIT));
 
                     // Greet the client with the capabilities we provide
-                    lines_sender
-                        .send(CAPABILITY_HELLO.to_string())
+                    let connection = Connection::new(true);
+                    connection.write().await.oauth = config.oauth_enabled();
+                    lines
+                        .send(capability_hello(&*connection.read().await))
                         .await
                         .unwrap();
-                    // Create our Connection
-                    let connection = Connection::new(true);
-
-                    // Prepare our custom return path
-                    let (mut tx, mut rx) = mpsc::unbounded();
-                    let cloned_tx = tx.clone();
-                    tokio::spawn(async move {
-                        while let Some(res) = rx.next().await {
-                            lines_sender.send(res).await.unwrap();
-                        }
-                    });
-                    // Connection needed for the file watcher
-                    let cloned_connection = Arc::clone(&connection);
-
-                    // Start listening to file changes for this session
-                    let mut file_watcher_subscriber = file_watcher.subscribe();
-
-                    // Listen to file changes on another thread
-                    tokio::spawn(async move {
-                        while let Ok(res) = file_watcher_subscriber.recv().await {
-                            check_changes(cloned_tx.clone(), Arc::clone(&cloned_connection), res)
-                                .await;
-                        }
-                    });
-
-                    // Read lines from the stream
-                    while let Some(Ok(line)) = lines_reader.next().await {
-                        let data = Data {
-                            con_state: Arc::clone(&connection),
-                        };
-                        debug!("[IMAP] [{}] Got Command: {}", peer, line);
-                        // TODO make sure to handle IDLE different as it needs us to stream lines
-
-                        {
-                            let close = data
-                                .parse(
-                                    &mut tx,
-                                    Arc::clone(&config),
-                                    Arc::clone(&database),
-                                    Arc::clone(&storage),
-                                    line,
-                                )
-                                .await;
-                            match close {
-                                Ok(close) => {
-                                    // Cleanup timeout managers
-                                    if close {
-                                        // Used for later session timer management
-                                        debug!("[IMAP] Closing TLS connection");
-                                        break;
-                                    }
-                                }
-                                // We try a last time to do a graceful shutdown before closing
-                                Err(e) => {
-                                    tx.send(format!(
-                                        "* BAD [SERVERBUG] This should not happen: {}",
-                                        e
-                                    ))
-                                    .await
-                                    .unwrap();
-                                    debug!("[IMAP] Closing TLS connection");
-                                    break;
-                                }
-                            }
-                        };
-                    }
+                    handle_tls(
+                        lines,
+                        connection,
+                        peer,
+                        config,
+                        database,
+                        storage,
+                        file_watcher,
+                    )
+                    .await;
                 }
                 Err(e) => error!("[IMAP] Got error while accepting TLS: {}", e),
             }
@@ -255,13 +205,58 @@ async fn listen(
     }
 }
 
-async fn check_changes(
-    _lines: UnboundedSender<String>,
-    state: Arc<RwLock<Connection>>,
-    event: Event,
+/// Serves a client over an established TLS stream
+///
+/// The greeting is up to the caller as a connection upgraded by `STARTTLS` doesn't get one
+#[instrument(skip(lines, connection, config, database, storage, file_watcher))]
+pub async fn handle_tls(
+    lines: Framed<TlsStream<TcpStream>, ImapCodec>,
+    connection: Arc<RwLock<Connection>>,
+    peer: SocketAddr,
+    config: Arc<Config>,
+    database: DB,
+    storage: Arc<Storage>,
+    file_watcher: broadcast::Sender<Event>,
 ) {
-    let state = { &state.read().await.state };
-    if matches!(state, State::Authenticated) || matches!(state, State::Selected(_, _)) {
-        println!("{:?}", event);
+    // The `tls-exporter` channel binding of RFC 9266 for SCRAM-SHA-256-PLUS
+    let mut channel_binding = vec![0; 32];
+    let channel_binding = lines
+        .get_ref()
+        .get_ref()
+        .1
+        .export_keying_material(&mut channel_binding, b"EXPORTER-Channel-Binding", None)
+        .map(|_| channel_binding)
+        .map_err(|e| error!("[IMAP] Unable to export the channel binding: {}", e))
+        .ok();
+
+    connection.write().await.channel_binding = channel_binding;
+
+    if let Some(Upgrade::Compress(tls_stream)) = serve(
+        lines,
+        Arc::clone(&connection),
+        peer,
+        Arc::clone(&config),
+        Arc::clone(&database),
+        Arc::clone(&storage),
+        file_watcher.clone(),
+    )
+    .await
+    {
+        debug!("[IMAP] [{}] Starting DEFLATE compression", peer);
+        let lines = Framed::new(
+            Compressed::new(tls_stream),
+            ImapCodec::new_with_max_length(LINE_LIMIT),
+        );
+        // COMPRESS can only be used once per session
+        serve(
+            lines,
+            connection,
+            peer,
+            config,
+            database,
+            storage,
+            file_watcher,
+        )
+        .await;
     }
 }
@@ -36,9 +36,8 @@
     clippy::module_name_repetitions
 )]
 
-use crate::commands::capability::get_capabilities;
+use crate::{commands::capability::get_capabilities, state::Connection};
 use async_trait::async_trait;
-use const_format::formatcp;
 use erooster_core::{
     backend::{database::DB, storage::Storage},
     config::Config,
@@ -46,18 +45,22 @@ use erooster_core::{
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
 use std::{path::Path, sync::Arc};
 use tokio::sync::broadcast;
-use tracing::instrument;
+use tracing::{debug, instrument};
 
+pub(crate) mod codec;
 pub(crate) mod commands;
 pub(crate) mod encrypted;
+pub(crate) mod session;
 pub(crate) mod state;
 pub(crate) mod unencrypted;
 
-/// A const variant of the Capabilities we welcome clients with
-pub const CAPABILITY_HELLO: &str = formatcp!(
-    "* OK [{}] IMAP4rev1/IMAP4rev2 Service Ready",
-    get_capabilities()
-);
+/// The greeting with the capabilities we offer on the connection
+pub(crate) fn capability_hello(connection: &Connection) -> String {
+    format!(
+        "* OK [{}] IMAP4rev1/IMAP4rev2 Service Ready",
+        get_capabilities(connection)
+    )
+}
 
 /// An implementation of a imap server
 #[async_trait]
@@ -82,14 +85,14 @@ pub fn start(
     database: DB,
     storage: Arc<Storage>,
 ) -> color_eyre::eyre::Result<()> {
-    let (tx, _rx) = broadcast::channel(1);
+    let (tx, _rx) = broadcast::channel(16);
     let tx_clone = tx.clone();
     let mut watcher = RecommendedWatcher::new(move |res: notify::Result<Event>| {
         if let Ok(event) = res {
-            futures::executor::block_on(async {
-                tx.send(event.clone())
-                    .expect("failed to send filechange event");
-            });
+            // This only fails if no client is connected which is fine
+            if tx.send(event).is_err() {
+                debug!("No session is listening to file changes");
+            }
         }
     })?;
 
@@ -99,6 +102,8 @@ pub fn start(
         Path::new(&config.mail.maildir_folders),
         RecursiveMode::Recursive,
     )?;
+    // The watcher stops watching once it is dropped but we need it for the whole lifetime of the server
+    std::mem::forget(watcher);
 
     let config_clone = Arc::clone(&config);
     let db_clone = Arc::clone(&database);
@@ -0,0 +1,259 @@
+use crate::{
+    codec::{Frame, ImapCodec},
+    commands::{idle::watch_changes, Data},
+    state::{Compression, Connection, State},
+};
+use async_compression::tokio::{bufread::DeflateDecoder, write::DeflateEncoder};
+use erooster_core::{
+    backend::{database::DB, storage::Storage},
+    config::Config,
+};
+use futures::{
+    channel::mpsc::{self, SendError, UnboundedSender},
+    Sink, SinkExt, StreamExt,
+};
+use notify::Event;
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{split, AsyncRead, AsyncWrite, BufReader, ReadBuf, ReadHalf, WriteHalf},
+    sync::{broadcast, RwLock},
+};
+use tokio_util::codec::{Framed, FramedRead, FramedWrite};
+use tracing::{debug, error, instrument};
+
+/// The transport change a client asked for
+pub enum Upgrade<T> {
+    /// The client sent STARTTLS
+    StartTls(T),
+    /// The client sent COMPRESS DEFLATE
+    Compress(T),
+}
+
+/// Serves the client over the transport until it logs out or the transport has to change.
+///
+/// For an upgrade the raw transport is handed back once the tagged OK was written.
+/// Anything the client sent ahead of the negotiation gets dropped with the read buffer.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(lines, connection, config, database, storage, file_watcher))]
+pub async fn serve<T>(
+    lines: Framed<T, ImapCodec>,
+    connection: Arc<RwLock<Connection>>,
+    peer: SocketAddr,
+    config: Arc<Config>,
+    database: DB,
+    storage: Arc<Storage>,
+    file_watcher: broadcast::Sender<Event>,
+) -> Option<Upgrade<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // We split these as we handle the sink in a broadcast instead to be able to push non linear data over the socket.
+    // The reader stays with us as it has to know which literals the client sends.
+    let parts = lines.into_parts();
+    let (reader, writer) = split(parts.io);
+    let mut lines_reader = FramedRead::new(reader, parts.codec.clone());
+    let mut lines_sender = FramedWrite::new(writer, parts.codec);
+
+    // Prepare our custom return path
+    let (tx, mut rx) = mpsc::unbounded();
+    let mut tx = ResponseSender(tx);
+    let cloned_tx = tx.clone();
+    let sender_task = tokio::spawn(async move {
+        while let Some(res) = rx.next().await {
+            lines_sender.send(res).await.unwrap();
+        }
+        // Returned to be able to swap the transport
+        lines_sender
+    });
+
+    // Listen to file changes on another thread
+    let file_watcher_task = tokio::spawn(watch_changes(
+        file_watcher.subscribe(),
+        cloned_tx,
+        Arc::clone(&connection),
+        Arc::clone(&storage),
+    ));
+
+    // Read lines from the stream
+    let mut upgrade: Option<fn(T) -> Upgrade<T>> = None;
+    while let Some(Ok(frame)) = lines_reader.next().await {
+        let data = Data {
+            con_state: Arc::clone(&connection),
+        };
+
+        let close = match frame {
+            Frame::Line(line) => {
+                debug!("[IMAP] [{}] Got Command: {}", peer, line);
+                data.parse(
+                    &mut tx,
+                    Arc::clone(&config),
+                    Arc::clone(&database),
+                    Arc::clone(&storage),
+                    line,
+                )
+                .await
+            }
+            Frame::Literal(literal) => {
+                debug!("[IMAP] [{}] Got literal of {} octets", peer, literal.len());
+                data.add_literal(literal).await;
+                Ok(false)
+            }
+        };
+        match close {
+            Ok(close) => {
+                // Cleanup timeout managers
+                if close {
+                    // Used for later session timer management
+                    debug!("[IMAP] Closing connection");
+                    break;
+                }
+                let mut write_lock = connection.write().await;
+                // The client sends the literal it announced next
+                if let Some(pending) = &write_lock.pending_command {
+                    if pending.remaining > 0 {
+                        lines_reader
+                            .decoder_mut()
+                            .read_literal(pending.remaining, pending.too_big);
+                    }
+                }
+                if write_lock.state == State::StartTls {
+                    upgrade = Some(Upgrade::StartTls);
+                    break;
+                }
+                if write_lock.compression == Compression::Requested {
+                    write_lock.compression = Compression::Active;
+                    upgrade = Some(Upgrade::Compress);
+                    break;
+                }
+            }
+            // We try a last time to do a graceful shutdown before closing
+            Err(e) => {
+                tx.send(format!("* BAD [SERVERBUG] This should not happen: {}", e))
+                    .await
+                    .unwrap();
+                debug!("[IMAP] Closing connection");
+                break;
+            }
+        }
+    }
+    file_watcher_task.abort();
+    let upgrade = upgrade?;
+
+    // All senders need to be gone for the sender task to hand back the sink.
+    // This also makes sure the tagged OK got written before the negotiation starts.
+    drop(tx);
+    // The task only got aborted which is the expected error here
+    let _ = file_watcher_task.await;
+    let lines_sender = sender_task
+        .await
+        .map_err(|e| error!("[IMAP] Unable to swap the transport: {}", e))
+        .ok()?;
+    let stream = lines_reader.into_inner().unsplit(lines_sender.into_inner());
+    Some(upgrade(stream))
+}
+
+/// Queues the responses for the client.
+///
+/// Most responses are text but literals in them may contain any octet.
+#[derive(Clone)]
+pub struct ResponseSender(UnboundedSender<Vec<u8>>);
+
+impl Sink<Vec<u8>> for ResponseSender {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Pin::new(&mut self.get_mut().0).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), SendError> {
+        Pin::new(&mut self.get_mut().0).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Pin::new(&mut self.get_mut().0).poll_close(cx)
+    }
+}
+
+impl Sink<String> for ResponseSender {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Sink::<Vec<u8>>::poll_ready(self, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: String) -> Result<(), SendError> {
+        self.start_send(item.into_bytes())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Sink::<Vec<u8>>::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Sink::<Vec<u8>>::poll_close(self, cx)
+    }
+}
+
+/// A transport compressed with raw DEFLATE as defined by RFC 4978
+pub struct Compressed<T> {
+    reader: DeflateDecoder<BufReader<ReadHalf<T>>>,
+    writer: DeflateEncoder<WriteHalf<T>>,
+}
+
+impl<T> Compressed<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    pub fn new(stream: T) -> Self {
+        let (reader, writer) = split(stream);
+        Compressed {
+            reader: DeflateDecoder::new(BufReader::new(reader)),
+            writer: DeflateEncoder::new(writer),
+        }
+    }
+}
+
+impl<T> AsyncRead for Compressed<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().reader).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Compressed<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    // Flushing ends the deflate block so the client can read every response right away
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
+    }
+}
@@ -1,5 +1,8 @@
-use crate::commands::{auth::AuthenticationMethod, parsers::DateTime};
-use std::sync::Arc;
+use crate::commands::auth::AuthenticationMethod;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 
 /// State of the connection session between us and the Client
@@ -7,8 +10,20 @@ use tokio::sync::RwLock;
 pub struct Connection {
     pub state: State,
     pub secure: bool,
+    /// Whether the connection can still be upgraded with STARTTLS
+    pub starttls: bool,
+    /// Whether bearer tokens of an identity provider are accepted
+    pub oauth: bool,
     pub username: Option<String>,
     pub active_capabilities: Vec<Capabilities>,
+    /// The message sequence numbers of the selected mailbox
+    pub sequence_map: SequenceMap,
+    /// A command which still waits for the data of a literal
+    pub pending_command: Option<PendingCommand>,
+    /// The `tls-exporter` channel binding of the TLS session used by SCRAM-SHA-256-PLUS
+    pub channel_binding: Option<Vec<u8>>,
+    /// Whether the client negotiated COMPRESS=DEFLATE
+    pub compression: Compression,
 }
 
 impl Connection {
@@ -16,15 +31,49 @@ impl Connection {
         Arc::new(RwLock::new(Connection {
             state: State::NotAuthenticated,
             secure,
+            starttls: !secure,
+            oauth: false,
             username: None,
             active_capabilities: vec![],
+            sequence_map: SequenceMap::default(),
+            pending_command: None,
+            channel_binding: None,
+            compression: Compression::Off,
         }))
     }
+
+    /// Whether the client enabled CONDSTORE directly or through QRESYNC
+    pub fn condstore_enabled(&self) -> bool {
+        self.active_capabilities
+            .iter()
+            .any(|capability| matches!(capability, Capabilities::CondStore | Capabilities::QResync))
+    }
+
+    /// Whether the client enabled QRESYNC which replaces EXPUNGE with VANISHED responses
+    pub fn qresync_enabled(&self) -> bool {
+        self.active_capabilities
+            .iter()
+            .any(|capability| matches!(capability, Capabilities::QResync))
+    }
+}
+
+/// The compression state of the connection as defined by RFC 4978
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    /// The transport is not compressed
+    #[default]
+    Off,
+    /// The client got told that compression starts after the tagged OK
+    Requested,
+    /// The transport is compressed with DEFLATE
+    Active,
 }
 
 #[derive(Debug, Clone)]
 pub enum Capabilities {
     UTF8,
+    CondStore,
+    QResync,
     Other(String),
 }
 
@@ -38,22 +87,30 @@ pub enum State {
     Authenticated,
     /// Folder selected
     Selected(String, Access),
-    /// An Email is getting appended to the folder
-    /// Fields are left to right:
-    /// - folder
-    /// - flags
-    /// - datetime
-    Appending(AppendingState),
+    /// The client is waiting for changes to the mailbox
+    Idle(IdleState),
+    /// The client got told to begin the TLS negotiation
+    StartTls,
+}
+
+/// The beginning of a command which is still waiting for the data of a literal
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct PendingCommand {
+    /// The command received so far with its line breaks and the data of its literals
+    pub text: Vec<u8>,
+    /// The length of the literal the client sends next
+    pub remaining: usize,
+    /// The command was rejected for a too big literal and the data gets discarded
+    pub too_big: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct AppendingState {
-    pub folder: String,
-    pub flags: Option<Vec<String>>,
-    pub datetime: Option<DateTime>,
-    pub data: Option<Vec<u8>>,
-    pub datalen: usize,
+pub struct IdleState {
+    /// The state to return to once the client sends DONE
+    pub previous: Box<State>,
     pub tag: String,
+    /// The `FLAGS` responses the client knows about by uid
+    pub known_flags: HashMap<i64, String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -61,3 +118,119 @@ pub enum Access {
     ReadOnly,
     ReadWrite,
 }
+
+/// Maps the message sequence numbers the client knows about to the uids of the mails.
+///
+/// It is built when a mailbox gets selected and only changes when we tell the client
+/// about new mails with EXISTS or removed mails with EXPUNGE.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SequenceMap {
+    /// The uids in ascending order. The sequence number is the index plus one.
+    uids: Vec<i64>,
+    /// The uids saved by `SEARCH RETURN (SAVE)` which `$` refers to
+    pub saved_search: Vec<i64>,
+}
+
+impl SequenceMap {
+    pub fn new(mut uids: Vec<i64>) -> Self {
+        uids.sort_unstable();
+        uids.dedup();
+        SequenceMap {
+            uids,
+            saved_search: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.uids.len()
+    }
+
+    /// The highest uid or 0 if the mailbox is empty
+    pub fn max_uid(&self) -> i64 {
+        self.uids.last().copied().unwrap_or(0)
+    }
+
+    pub fn sequence(&self, uid: i64) -> Option<usize> {
+        self.uids.binary_search(&uid).ok().map(|index| index + 1)
+    }
+
+    /// Iterates over the sequence numbers and uids in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, i64)> + '_ {
+        self.uids
+            .iter()
+            .enumerate()
+            .map(|(index, uid)| (index + 1, *uid))
+    }
+
+    /// Adds a new mail and returns its sequence number
+    pub fn push(&mut self, uid: i64) -> usize {
+        match self.uids.binary_search(&uid) {
+            Ok(index) => index + 1,
+            Err(index) => {
+                self.uids.insert(index, uid);
+                index + 1
+            }
+        }
+    }
+
+    /// Removes an expunged mail and returns the sequence number it had
+    pub fn remove(&mut self, uid: i64) -> Option<usize> {
+        self.saved_search.retain(|saved_uid| *saved_uid != uid);
+        let index = self.uids.binary_search(&uid).ok()?;
+        self.uids.remove(index);
+        Some(index + 1)
+    }
+
+    /// Brings the map up to date with the uids currently in the mailbox.
+    ///
+    /// Returns the sequence numbers and uids of the removed mails in the order the EXPUNGE
+    /// responses have to be sent and whether mails were added which needs an EXISTS response.
+    pub fn update(&mut self, current_uids: &[i64]) -> (Vec<(usize, i64)>, bool) {
+        let current: HashSet<&i64> = current_uids.iter().collect();
+        let removed_uids: Vec<i64> = self
+            .uids
+            .iter()
+            .copied()
+            .filter(|uid| !current.contains(uid))
+            .collect();
+        // Going from the highest sequence number down keeps the lower ones valid
+        let expunged = removed_uids
+            .iter()
+            .rev()
+            .filter_map(|uid| self.remove(*uid).map(|sequence| (sequence, *uid)))
+            .collect();
+
+        let known_count = self.len();
+        for uid in current_uids {
+            self.push(*uid);
+        }
+        (expunged, self.len() != known_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_map() {
+        let mut sequence_map = SequenceMap::new(vec![7, 3, 5]);
+        assert_eq!(
+            sequence_map.iter().collect::<Vec<_>>(),
+            vec![(1, 3), (2, 5), (3, 7)]
+        );
+        assert_eq!(sequence_map.sequence(7), Some(3));
+        assert_eq!(sequence_map.sequence(4), None);
+
+        assert_eq!(sequence_map.remove(5), Some(2));
+        assert_eq!(sequence_map.sequence(7), Some(2));
+        assert_eq!(sequence_map.remove(5), None);
+
+        assert_eq!(sequence_map.update(&[3, 8, 9]), (vec![(2, 7)], true));
+        assert_eq!(
+            sequence_map.iter().collect::<Vec<_>>(),
+            vec![(1, 3), (2, 8), (3, 9)]
+        );
+        assert_eq!(sequence_map.update(&[3, 8, 9]), (vec![], false));
+    }
+}
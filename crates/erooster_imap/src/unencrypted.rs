@@ -1,18 +1,25 @@
-use crate::{commands::Data, state::Connection, Server, CAPABILITY_HELLO};
+use crate::{
+    capability_hello,
+    codec::ImapCodec,
+    encrypted::{handle_tls, Encrypted},
+    session::{serve, Compressed, Upgrade},
+    state::Connection,
+    Server,
+};
 use async_trait::async_trait;
 use erooster_core::{
     backend::{database::DB, storage::Storage},
     config::Config,
-    line_codec::LinesCodec,
     LINE_LIMIT,
 };
-use futures::{channel::mpsc, SinkExt, StreamExt};
+use futures::{SinkExt, StreamExt};
 use notify::Event;
 use std::{net::SocketAddr, sync::Arc};
 use tokio::{net::TcpListener, sync::broadcast};
+use tokio_rustls::TlsAcceptor;
 use tokio_stream::wrappers::TcpListenerStream;
 use tokio_util::codec::Framed;
-use tracing::{debug, info, instrument};
+use tracing::{debug, error, info, instrument};
 
 /// An unencrypted imap Server
 pub struct Unencrypted;
@@ -26,6 +33,11 @@ impl Server for Unencrypted {
         storage: Arc<Storage>,
         file_watcher: broadcast::Sender<Event>,
     ) -> color_eyre::eyre::Result<()> {
+        // Used to upgrade connections with STARTTLS which isn't offered without a certificate
+        let acceptor = Encrypted::get_tls_acceptor(&config)
+            .map_err(|e| error!("[IMAP] Unable to set up TLS, STARTTLS is disabled: {}", e))
+            .ok();
+
         let addrs: Vec<SocketAddr> = if let Some(listen_ips) = &config.listen_ips {
             listen_ips
                 .iter()
@@ -44,6 +56,7 @@ impl Server for Unencrypted {
             let database = Arc::clone(&database);
             let storage = Arc::clone(&storage);
             let file_watcher = file_watcher.clone();
+            let acceptor = acceptor.clone();
             tokio::spawn(async move {
                 listen(
                     stream,
@@ -51,6 +64,7 @@ impl Server for Unencrypted {
                     Arc::clone(&database),
                     Arc::clone(&storage),
                     file_watcher.clone(),
+                    acceptor.clone(),
                 )
                 .await;
             });
@@ -59,13 +73,14 @@ impl Server for Unencrypted {
     }
 }
 
-#[instrument(skip(stream, config, database, storage, _file_watcher))]
+#[instrument(skip(stream, config, database, storage, file_watcher, acceptor))]
 async fn listen(
     mut stream: TcpListenerStream,
     config: Arc<Config>,
     database: DB,
     storage: Arc<Storage>,
-    mut _file_watcher: broadcast::Sender<Event>,
+    file_watcher: broadcast::Sender<Event>,
+    acceptor: Option<TlsAcceptor>,
 ) {
     while let Some(Ok(tcp_stream)) = stream.next().await {
         let peer = tcp_stream.peer_addr().expect("peer addr to exist");
@@ -74,58 +89,72 @@ async fn listen(
         let config = Arc::clone(&config);
         let database = Arc::clone(&database);
         let storage = Arc::clone(&storage);
+        let file_watcher = file_watcher.clone();
+        let acceptor = acceptor.clone();
         tokio::spawn(async move {
-            let lines = Framed::new(tcp_stream, LinesCodec::new_with_max_length(LINE_LIMIT));
-            let (mut lines_sender, mut lines_reader) = lines.split();
-            lines_sender
-                .send(CAPABILITY_HELLO.to_string())
+            let mut lines = Framed::new(tcp_stream, ImapCodec::new_with_max_length(LINE_LIMIT));
+            let connection = Connection::new(false);
+            connection.write().await.starttls = acceptor.is_some();
+            lines
+                .send(capability_hello(&*connection.read().await))
                 .await
                 .unwrap();
-            let state = Connection::new(false);
 
-            let (mut tx, mut rx) = mpsc::unbounded();
-            tokio::spawn(async move {
-                while let Some(res) = rx.next().await {
-                    lines_sender.send(res).await.unwrap();
+            match serve(
+                lines,
+                Arc::clone(&connection),
+                peer,
+                Arc::clone(&config),
+                Arc::clone(&database),
+                Arc::clone(&storage),
+                file_watcher.clone(),
+            )
+            .await
+            {
+                Some(Upgrade::StartTls(tcp_stream)) => {
+                    // STARTTLS gets refused before this point if TLS isn't set up
+                    let acceptor = acceptor.expect("STARTTLS to be available");
+                    match acceptor.accept(tcp_stream).await {
+                        Ok(stream) => {
+                            debug!("[IMAP] STARTTLS negotiation done");
+                            // The client starts over without a greeting and a fresh state
+                            let lines =
+                                Framed::new(stream, ImapCodec::new_with_max_length(LINE_LIMIT));
+                            let tls_connection = Connection::new(true);
+                            tls_connection.write().await.oauth = config.oauth_enabled();
+                            handle_tls(
+                                lines,
+                                tls_connection,
+                                peer,
+                                config,
+                                database,
+                                storage,
+                                file_watcher,
+                            )
+                            .await;
+                        }
+                        Err(e) => error!("[IMAP] Got error while accepting TLS: {}", e),
+                    }
                 }
-            });
-            while let Some(Ok(line)) = lines_reader.next().await {
-                let data = Data {
-                    con_state: Arc::clone(&state),
-                };
-
-                debug!("[IMAP] [{}] Got Command: {}", peer, line);
-
-                // TODO make sure to handle IDLE different as it needs us to stream lines
-                // TODO pass lines and make it possible to not need new lines in responds but instead directly use `lines.send`
-                let response = data
-                    .parse(
-                        &mut tx,
-                        Arc::clone(&config),
-                        Arc::clone(&database),
-                        Arc::clone(&storage),
-                        line,
+                Some(Upgrade::Compress(tcp_stream)) => {
+                    debug!("[IMAP] [{}] Starting DEFLATE compression", peer);
+                    let lines = Framed::new(
+                        Compressed::new(tcp_stream),
+                        ImapCodec::new_with_max_length(LINE_LIMIT),
+                    );
+                    // Neither STARTTLS nor COMPRESS is possible anymore at this point
+                    serve(
+                        lines,
+                        connection,
+                        peer,
+                        config,
+                        database,
+                        storage,
+                        file_watcher,
                     )
                     .await;
-
-                match response {
-                    Ok(response) => {
-                        // Cleanup timeout managers
-                        if response {
-                            // Used for later session timer management
-                            debug!("[IMAP] Closing connection");
-                            break;
-                        }
-                    }
-                    // We try a last time to do a graceful shutdown before closing
-                    Err(e) => {
-                        tx.send(format!("* BAD [SERVERBUG] This should not happen: {}", e))
-                            .await
-                            .unwrap();
-                        debug!("[IMAP] Closing connection");
-                        break;
-                    }
                 }
+                None => {}
             }
         });
     }
@@ -4,50 +4,156 @@ use crate::{
     commands::{CommandData, Data},
     servers::state::{AuthState, State},
 };
-use erooster_core::backend::database::{Database, DB};
+use erooster_core::{
+    backend::database::{normalize_username, Database, DB},
+    config::Config,
+    oauth::{oauthbearer_response, verify_token, xoauth2_response, OAUTH_ERROR},
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
 use secrecy::{ExposeSecret, SecretString, SecretVec};
 use simdutf8::compat::from_utf8;
-use tracing::instrument;
+use std::sync::Arc;
+use tracing::{debug, instrument};
 pub struct Auth<'a> {
     pub data: &'a Data,
 }
 
 impl Auth<'_> {
-    #[instrument(skip(self, lines, command_data))]
+    #[instrument(skip(self, lines, config, database, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
-        //let secure = self.data.con_state.read().await.secure;
-        let secure = true;
-        if secure {
-            assert!(command_data.arguments.len() == 1);
-            if command_data.arguments[0] == "LOGIN" {
+        let secure = self.data.con_state.read().await.secure;
+        let oauth_state = command_data
+            .arguments
+            .first()
+            .filter(|_| config.oauth_enabled())
+            .and_then(|mechanism| {
+                if mechanism.eq_ignore_ascii_case("OAUTHBEARER") {
+                    Some(AuthState::OAuthBearer)
+                } else if mechanism.eq_ignore_ascii_case("XOAUTH2") {
+                    Some(AuthState::XOAuth2)
+                } else {
+                    None
+                }
+            });
+        match (command_data.arguments, oauth_state) {
+            (["LOGIN"], _) => {
                 {
                     self.data.con_state.write().await.state =
                         State::Authenticating(AuthState::Username);
                 };
                 lines.send(String::from("334 VXNlcm5hbWU6")).await?;
-            } else {
+            }
+            // Bearer tokens must not be sent in plain text
+            (_, Some(_)) if !secure => {
+                lines
+                    .send(String::from(
+                        "538 Encryption required for requested authentication mechanism",
+                    ))
+                    .await?;
+            }
+            ([_], Some(auth_state)) => {
+                {
+                    self.data.con_state.write().await.state = State::Authenticating(auth_state);
+                };
+                lines.send(String::from("334 ")).await?;
+            }
+            // The token was sent as initial response
+            ([_, initial_response], Some(auth_state)) => {
+                self.oauth(lines, config, database, &auth_state, initial_response)
+                    .await?;
+            }
+            _ => {
                 lines
                     .send(String::from("504 Unrecognized authentication type."))
                     .await?;
             }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, lines, config, database, auth_state, line))]
+    pub async fn oauth<S>(
+        &self,
+        lines: &mut S,
+        config: Arc<Config>,
+        database: DB,
+        auth_state: &AuthState,
+        line: &str,
+    ) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        let message = base64::decode(line.as_bytes())
+            .ok()
+            .and_then(|bytes| from_utf8(&bytes).ok().map(ToString::to_string))
+            .unwrap_or_default();
+        let response = if let AuthState::OAuthBearer = auth_state {
+            oauthbearer_response(&message)
+        } else {
+            xoauth2_response(&message)
+        };
+        let mut write_lock = self.data.con_state.write().await;
+        let response = if let Some(response) = response {
+            response
+        } else {
+            write_lock.state = State::NotAuthenticated;
+            lines
+                .send(String::from("501 Syntax error in parameters or arguments"))
+                .await?;
+            return Ok(());
+        };
+
+        let username = match verify_token(&config, response.token).await {
+            // The token has to belong to the user the client asked for
+            Ok(username) if response.user.map_or(true, |user| user == username) => {
+                database.user_exists(&username).await.then_some(username)
+            }
+            Ok(username) => {
+                debug!(
+                    "[SMTP] The token of {} doesn't belong to {:?}",
+                    username, response.user
+                );
+                None
+            }
+            Err(e) => {
+                debug!("[SMTP] Invalid token: {}", e);
+                None
+            }
+        };
+        if let Some(username) = username {
+            write_lock.state = State::Authenticated(username);
+            lines.send(String::from("235 ok")).await?;
         } else {
+            write_lock.state = State::Authenticating(AuthState::OAuthFailed);
             lines
-                .send(String::from(
-                    "538 Encryption required for requested authentication mechanism",
-                ))
+                .send(format!("334 {}", base64::encode(OAUTH_ERROR)))
                 .await?;
         }
         Ok(())
     }
 
+    /// Whatever the client answers to the error of a rejected token the authentication failed
+    #[instrument(skip(self, lines))]
+    pub async fn oauth_failed<S>(&self, lines: &mut S) -> color_eyre::eyre::Result<()>
+    where
+        S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
+    {
+        self.data.con_state.write().await.state = State::NotAuthenticated;
+        lines
+            .send(String::from("535 5.7.8 Authentication credentials invalid"))
+            .await?;
+        Ok(())
+    }
+
     #[instrument(skip(self, lines, line))]
     pub async fn username<S>(&self, lines: &mut S, line: &str) -> color_eyre::eyre::Result<()>
     where
@@ -59,7 +165,7 @@ impl Auth<'_> {
                 let username = from_utf8(&bytes)?;
                 {
                     self.data.con_state.write().await.state =
-                        State::Authenticating(AuthState::Password(username.to_string()));
+                        State::Authenticating(AuthState::Password(normalize_username(username)));
                 };
                 lines.send(String::from("334 UGFzc3dvcmQ6")).await?;
             }
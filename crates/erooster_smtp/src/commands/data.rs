@@ -98,6 +98,12 @@ impl DataCommand<'_> {
                     State::Authenticated(username.clone())
                 } else if matches!(write_lock.state, State::ReceivingData(None)) {
                     debug!("No authenticated user");
+                    let size = write_lock.data.as_ref().map_or(0, String::len);
+                    if !fits_quota(&database, &storage, &receipts, size).await? {
+                        lines.send(String::from("452 4.2.2 Mailbox full")).await?;
+                        write_lock.state = State::NotAuthenticated;
+                        return Ok(());
+                    }
                     for receipt in receipts {
                         let folder = "INBOX".to_string();
                         let mailbox_path = Path::new(&config.mail.maildir_folders)
@@ -133,3 +139,22 @@ impl DataCommand<'_> {
         Ok(())
     }
 }
+
+/// Checks if a mail of the given size still fits into the mailboxes of all receipts
+#[instrument(skip(database, storage, receipts))]
+pub async fn fits_quota(
+    database: &DB,
+    storage: &Storage,
+    receipts: &[String],
+    size: usize,
+) -> color_eyre::eyre::Result<bool> {
+    for receipt in receipts {
+        let quota = database.get_quota(receipt).await?;
+        let usage = storage.quota_usage(receipt).await?;
+        if !quota.allows(&usage, u64::try_from(size)?, 1) {
+            debug!("Mailbox of {} is full", receipt);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -4,13 +4,25 @@ use tracing::instrument;
 pub struct Ehlo;
 
 impl Ehlo {
+    /// Bearer tokens are only offered on encrypted connections if an identity provider is configured
     #[instrument(skip(self, hostname, lines))]
-    pub async fn exec<S>(&self, hostname: String, lines: &mut S) -> color_eyre::eyre::Result<()>
+    pub async fn exec<S>(
+        &self,
+        hostname: String,
+        oauth: bool,
+        lines: &mut S,
+    ) -> color_eyre::eyre::Result<()>
     where
         S: Sink<String, Error = SendError> + std::marker::Unpin + std::marker::Send,
     {
         lines.feed(format!("250-{}", hostname)).await?;
-        lines.feed(String::from("250 AUTH LOGIN")).await?;
+        if oauth {
+            lines
+                .feed(String::from("250 AUTH LOGIN OAUTHBEARER XOAUTH2"))
+                .await?;
+        } else {
+            lines.feed(String::from("250 AUTH LOGIN")).await?;
+        }
         lines.flush().await?;
         Ok(())
     }
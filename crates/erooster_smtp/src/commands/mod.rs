@@ -162,6 +162,14 @@ impl Data {
                 AuthState::Password(_) => {
                     Auth { data: self }.password(lines, database, &line).await?;
                 }
+                AuthState::OAuthBearer | AuthState::XOAuth2 => {
+                    Auth { data: self }
+                        .oauth(lines, config, database, &auth_state, &line)
+                        .await?;
+                }
+                AuthState::OAuthFailed => {
+                    Auth { data: self }.oauth_failed(lines).await?;
+                }
             }
             // We are done here
             return Ok(false);
@@ -188,7 +196,13 @@ impl Data {
                         Rset.exec(lines).await?;
                     }
                     Commands::EHLO => {
-                        Ehlo.exec(config.mail.hostname.clone(), lines).await?;
+                        let secure = self.con_state.read().await.secure;
+                        Ehlo.exec(
+                            config.mail.hostname.clone(),
+                            secure && config.oauth_enabled(),
+                            lines,
+                        )
+                        .await?;
                     }
                     Commands::QUIT => {
                         Quit.exec(lines).await?;
@@ -200,14 +214,16 @@ impl Data {
                     }
                     Commands::RCPTTO => {
                         Rcpt { data: self }
-                            .exec(lines, database, &command_data)
+                            .exec(lines, database, storage, &command_data)
                             .await?;
                     }
                     Commands::DATA => {
                         DataCommand { data: self }.exec(lines).await?;
                     }
                     Commands::AUTH => {
-                        Auth { data: self }.exec(lines, &command_data).await?;
+                        Auth { data: self }
+                            .exec(lines, config, database, &command_data)
+                            .await?;
                     }
                     Commands::NOOP => {
                         Noop.exec(lines).await?;
@@ -1,9 +1,13 @@
 use crate::{
-    commands::{parsers::localpart_arguments, CommandData, Data},
+    commands::{data::fits_quota, parsers::localpart_arguments, CommandData, Data},
     servers::state::State,
 };
-use erooster_core::backend::database::{Database, DB};
+use erooster_core::backend::{
+    database::{normalize_username, Database, DB},
+    storage::Storage,
+};
 use futures::{channel::mpsc::SendError, Sink, SinkExt};
+use std::sync::Arc;
 use tracing::{info, instrument};
 
 pub struct Rcpt<'a> {
@@ -11,11 +15,12 @@ pub struct Rcpt<'a> {
 }
 
 impl Rcpt<'_> {
-    #[instrument(skip(self, lines, database, command_data))]
+    #[instrument(skip(self, lines, database, storage, command_data))]
     pub async fn exec<S>(
         &self,
         lines: &mut S,
         database: DB,
+        storage: Arc<Storage>,
         command_data: &CommandData<'_>,
     ) -> color_eyre::eyre::Result<()>
     where
@@ -27,18 +32,22 @@ impl Rcpt<'_> {
             .map(|(_, receipts)| receipts)
             .expect("Failed to parse localpart arguments")
             .iter()
-            .map(ToString::to_string)
+            .map(|receipt| normalize_username(receipt))
             .collect();
 
         {
             let mut write_lock = self.data.con_state.write().await;
             if matches!(&write_lock.state, State::NotAuthenticated) {
                 for receipt in &receipts {
-                    if !database.user_exists(&receipt.to_lowercase()).await {
+                    if !database.user_exists(receipt).await {
                         lines.send(String::from("550 No such user here")).await?;
                         return Ok(());
                     }
                 }
+                if !fits_quota(&database, &storage, &receipts, 0).await? {
+                    lines.send(String::from("452 4.2.2 Mailbox full")).await?;
+                    return Ok(());
+                }
             }
 
             write_lock.receipts = Some(receipts);
@@ -41,4 +41,8 @@ pub enum State {
 pub enum AuthState {
     Username,
     Password(String),
+    OAuthBearer,
+    XOAuth2,
+    /// The client got told why the token was rejected and has to respond before it gets the error
+    OAuthFailed,
 }
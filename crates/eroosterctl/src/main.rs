@@ -33,7 +33,7 @@
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::Result;
 use erooster_core::{
-    backend::database::{get_database, Database},
+    backend::database::{get_database, normalize_username, Database},
     config::Config,
     panic_handler::EroosterPanicMessage,
 };
@@ -315,9 +315,9 @@ async fn actual_register(
     config: Arc<Config>,
 ) -> Result<()> {
     let database = get_database(config).await?;
-    database.add_user(&username.to_lowercase()).await?;
+    database.add_user(&normalize_username(&username)).await?;
     database
-        .change_password(&username.to_lowercase(), password)
+        .change_password(&normalize_username(&username), password)
         .await?;
     Ok(())
 }
@@ -369,7 +369,7 @@ async fn change_password(
 
         // TODO repromt as needed
         if !verify_password(
-            username.to_lowercase(),
+            normalize_username(&username),
             current_password,
             Arc::clone(&config),
         )
@@ -475,7 +475,7 @@ async fn actual_change_password(
 ) -> Result<()> {
     let database = get_database(config).await?;
     database
-        .change_password(&username.to_lowercase(), new_password)
+        .change_password(&normalize_username(&username), new_password)
         .await?;
     Ok(())
 }